@@ -7,13 +7,15 @@ use syn::{
 };
 
 /// # Example
-/// ```rust
+/// ```rust,ignore
 /// use rust_livo2_macros::uncertainties;
 /// #[uncertainties]
 /// struct PointUncertainty {
-///     pub distance: DistanceUncertainty, // publish this field will generate a view method
+///     // publish this field will generate a view method and an index constant
+///     pub distance: DistanceUncertainty,
 ///     direction: DirectionUncertainty,
 /// }
+/// // PointUncertainty::DISTANCE_INDEX == 0
 /// ```
 #[proc_macro_attribute]
 pub fn uncertainties(_attr: TokenStream, input: TokenStream) -> TokenStream {
@@ -122,7 +124,13 @@ impl ToTokens for ItemUncertainties {
                 let ty = &field.ty;
 
                 let fn_view_ident = format_ident!("view_{}", ident);
+                let const_index_ident =
+                    format_ident!("{}_INDEX", ident.to_string().to_uppercase());
                 Some(quote! {
+                    /// The start index of this field in the uncertainty matrix.
+                    pub const #const_index_ident: usize =
+                        <<#folded_field_ty as crate::uncertain::Uncertainty>::Dim as nalgebra::dimension::DimName>::DIM;
+
                     pub fn #fn_view_ident(&self) -> nalgebra::MatrixView<
                         <Self as crate::uncertain::Uncertainty>::Element,
                         <#ty as crate::uncertain::Uncertainty>::Dim,
@@ -130,8 +138,7 @@ impl ToTokens for ItemUncertainties {
                         nalgebra::Const<1>,
                         <Self as crate::uncertain::Uncertainty>::Dim,
                     > {
-                        let start = <<#folded_field_ty as crate::uncertain::Uncertainty>::Dim as nalgebra::dimension::DimName>::dim();
-                        self.0.fixed_view(start, start)
+                        self.0.fixed_view(Self::#const_index_ident, Self::#const_index_ident)
                     }
                 })
             });
@@ -0,0 +1,476 @@
+//! Camera models, projecting points in [`Camera`](crate::frame::Camera) frame to pixels.
+use nalgebra::{Matrix2, Matrix2x3, RowVector3, Vector2, Vector3};
+
+use serde::{Deserialize, Deserializer, de::Error};
+
+use crate::frame::CameraPoint;
+
+const UNDISTORT_ITERATIONS: usize = 20;
+
+/// A camera model with its image size.
+pub trait CameraModel {
+    fn width(&self) -> usize;
+
+    fn height(&self) -> usize;
+
+    /// Project the point to a pixel, which may be out of the image,
+    /// `None` if the point can not be projected by this model.
+    fn project(&self, point: &CameraPoint<f64>) -> Option<Vector2<f64>>;
+
+    /// The unit bearing vector of the pixel, `None` if the pixel can not be unprojected.
+    fn unproject(&self, pixel: &Vector2<f64>) -> Option<CameraPoint<f64>>;
+
+    /// The jacobian of the projected pixel w.r.t. the point.
+    fn project_jacobian(&self, point: &CameraPoint<f64>) -> Matrix2x3<f64>;
+
+    /// Whether the pixel is in the image and at least `border` pixels away from the edges.
+    fn contains(&self, pixel: &Vector2<f64>, border: f64) -> bool {
+        pixel.x >= border
+            && pixel.y >= border
+            && pixel.x < self.width() as f64 - border
+            && pixel.y < self.height() as f64 - border
+    }
+
+    /// Project the point to a pixel in the image.
+    fn project_to_image(&self, point: &CameraPoint<f64>) -> Option<Vector2<f64>> {
+        self.project(point)
+            .filter(|pixel| self.contains(pixel, 0.0))
+    }
+}
+
+/// The radial-tangential (plumb bob) distortion of normalized image coordinates.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadialTangential {
+    pub k1: f64,
+    pub k2: f64,
+    pub p1: f64,
+    pub p2: f64,
+    pub k3: f64,
+}
+
+impl RadialTangential {
+    pub fn distort(&self, point: &Vector2<f64>) -> Vector2<f64> {
+        let Self { k1, k2, p1, p2, k3 } = *self;
+        let (x, y) = (point.x, point.y);
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+        Vector2::new(
+            x * radial + 2.0 * p1 * x * y + p2 * (r2 + 2.0 * x * x),
+            y * radial + p1 * (r2 + 2.0 * y * y) + 2.0 * p2 * x * y,
+        )
+    }
+
+    /// The jacobian of the distorted point w.r.t. the undistorted point.
+    pub fn distort_jacobian(&self, point: &Vector2<f64>) -> Matrix2<f64> {
+        let Self { k1, k2, p1, p2, k3 } = *self;
+        let (x, y) = (point.x, point.y);
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (k1 + r2 * (k2 + r2 * k3));
+        // the derivative of the radial factor w.r.t. r2
+        let radial_derivative = k1 + r2 * (2.0 * k2 + r2 * 3.0 * k3);
+        let cross = 2.0 * x * y * radial_derivative + 2.0 * p1 * x + 2.0 * p2 * y;
+        Matrix2::new(
+            radial + 2.0 * x * x * radial_derivative + 2.0 * p1 * y + 6.0 * p2 * x,
+            cross,
+            cross,
+            radial + 2.0 * y * y * radial_derivative + 6.0 * p1 * y + 2.0 * p2 * x,
+        )
+    }
+
+    /// Invert [`Self::distort`] with gauss-newton iterations.
+    pub fn undistort(&self, distorted: &Vector2<f64>) -> Option<Vector2<f64>> {
+        let mut point = *distorted;
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let error = self.distort(&point) - distorted;
+            if error.norm_squared() < 1e-20 {
+                break;
+            }
+            point -= self.distort_jacobian(&point).try_inverse()? * error;
+        }
+        point.iter().all(|value| value.is_finite()).then_some(point)
+    }
+}
+
+/// The pinhole model with radial-tangential distortion.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PinholeCamera {
+    pub width: usize,
+    pub height: usize,
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    #[serde(default)]
+    pub distortion: RadialTangential,
+}
+
+impl CameraModel for PinholeCamera {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn project(&self, point: &CameraPoint<f64>) -> Option<Vector2<f64>> {
+        if point.z <= 0.0 {
+            return None;
+        }
+        let distorted = self.distortion.distort(&(point.coords.xy() / point.z));
+        Some(Vector2::new(
+            self.fx * distorted.x + self.cx,
+            self.fy * distorted.y + self.cy,
+        ))
+    }
+
+    fn unproject(&self, pixel: &Vector2<f64>) -> Option<CameraPoint<f64>> {
+        let distorted = Vector2::new((pixel.x - self.cx) / self.fx, (pixel.y - self.cy) / self.fy);
+        let point = self.distortion.undistort(&distorted)?;
+        Some(point.push(1.0).normalize().into())
+    }
+
+    fn project_jacobian(&self, point: &CameraPoint<f64>) -> Matrix2x3<f64> {
+        let z_inverse = point.z.recip();
+        let normalized = point.coords.xy() * z_inverse;
+        let normalized_jacobian = Matrix2x3::new(
+            z_inverse,
+            0.0,
+            -normalized.x * z_inverse,
+            0.0,
+            z_inverse,
+            -normalized.y * z_inverse,
+        );
+        focal_matrix(self.fx, self.fy)
+            * self.distortion.distort_jacobian(&normalized)
+            * normalized_jacobian
+    }
+}
+
+/// The equidistant fisheye model of Kannala-Brandt, which supports field of view over 180°.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EquidistantCamera {
+    pub width: usize,
+    pub height: usize,
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    #[serde(default)]
+    pub k1: f64,
+    #[serde(default)]
+    pub k2: f64,
+    #[serde(default)]
+    pub k3: f64,
+    #[serde(default)]
+    pub k4: f64,
+}
+
+impl EquidistantCamera {
+    /// The distorted incidence angle `theta`, and its derivative.
+    fn distort(&self, theta: f64) -> (f64, f64) {
+        let Self { k1, k2, k3, k4, .. } = *self;
+        let theta2 = theta * theta;
+        let polynomial = 1.0 + theta2 * (k1 + theta2 * (k2 + theta2 * (k3 + theta2 * k4)));
+        let derivative = 1.0
+            + theta2 * (3.0 * k1 + theta2 * (5.0 * k2 + theta2 * (7.0 * k3 + theta2 * 9.0 * k4)));
+        (theta * polynomial, derivative)
+    }
+}
+
+impl CameraModel for EquidistantCamera {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn project(&self, point: &CameraPoint<f64>) -> Option<Vector2<f64>> {
+        let radius = point.coords.xy().norm();
+        let normalized = if radius < f64::EPSILON {
+            if point.z <= 0.0 {
+                return None;
+            }
+            point.coords.xy() / point.z
+        } else {
+            let (distorted, _) = self.distort(radius.atan2(point.z));
+            point.coords.xy() * (distorted / radius)
+        };
+        Some(Vector2::new(
+            self.fx * normalized.x + self.cx,
+            self.fy * normalized.y + self.cy,
+        ))
+    }
+
+    fn unproject(&self, pixel: &Vector2<f64>) -> Option<CameraPoint<f64>> {
+        let normalized = Vector2::new((pixel.x - self.cx) / self.fx, (pixel.y - self.cy) / self.fy);
+        let distorted = normalized.norm();
+        if distorted < f64::EPSILON {
+            return Some(Vector3::z().into());
+        }
+
+        let mut theta = distorted;
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let (value, derivative) = self.distort(theta);
+            let step = (value - distorted) / derivative;
+            theta -= step;
+            if step.abs() < 1e-12 {
+                break;
+            }
+        }
+        if !theta.is_finite() {
+            return None;
+        }
+        let direction = normalized / distorted * theta.sin();
+        Some(direction.push(theta.cos()).into())
+    }
+
+    fn project_jacobian(&self, point: &CameraPoint<f64>) -> Matrix2x3<f64> {
+        let radius = point.coords.xy().norm();
+        if radius < f64::EPSILON {
+            let z_inverse = point.z.recip();
+            return focal_matrix(self.fx, self.fy)
+                * Matrix2x3::new(z_inverse, 0.0, 0.0, 0.0, z_inverse, 0.0);
+        }
+        let norm2 = point.coords.norm_squared();
+        let theta = radius.atan2(point.z);
+        let (distorted, distorted_derivative) = self.distort(theta);
+
+        let theta_jacobian = RowVector3::new(
+            point.z * point.x / (radius * norm2),
+            point.z * point.y / (radius * norm2),
+            -radius / norm2,
+        );
+        let radius_jacobian = RowVector3::new(point.x / radius, point.y / radius, 0.0);
+        // the jacobian of the scale `distorted / radius`
+        let scale_jacobian = (theta_jacobian * (distorted_derivative * radius)
+            - radius_jacobian * distorted)
+            / radius.powi(2);
+
+        let scale = distorted / radius;
+        let normalized_jacobian =
+            Matrix2x3::new(scale, 0.0, 0.0, 0.0, scale, 0.0) + point.coords.xy() * scale_jacobian;
+        focal_matrix(self.fx, self.fy) * normalized_jacobian
+    }
+}
+
+/// The unified omnidirectional model of Mei, with radial-tangential distortion.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OmniCamera {
+    pub width: usize,
+    pub height: usize,
+    /// the offset of the projection center from the unit sphere center
+    pub xi: f64,
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    #[serde(default)]
+    pub distortion: RadialTangential,
+}
+
+impl CameraModel for OmniCamera {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn project(&self, point: &CameraPoint<f64>) -> Option<Vector2<f64>> {
+        let denominator = point.z + self.xi * point.coords.norm();
+        if denominator <= f64::EPSILON {
+            return None;
+        }
+        let distorted = self.distortion.distort(&(point.coords.xy() / denominator));
+        Some(Vector2::new(
+            self.fx * distorted.x + self.cx,
+            self.fy * distorted.y + self.cy,
+        ))
+    }
+
+    fn unproject(&self, pixel: &Vector2<f64>) -> Option<CameraPoint<f64>> {
+        let distorted = Vector2::new((pixel.x - self.cx) / self.fx, (pixel.y - self.cy) / self.fy);
+        let point = self.distortion.undistort(&distorted)?;
+        let r2 = point.norm_squared();
+        let discriminant = 1.0 + (1.0 - self.xi.powi(2)) * r2;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let factor = (self.xi + discriminant.sqrt()) / (1.0 + r2);
+        Some(
+            Vector3::new(factor * point.x, factor * point.y, factor - self.xi)
+                .normalize()
+                .into(),
+        )
+    }
+
+    fn project_jacobian(&self, point: &CameraPoint<f64>) -> Matrix2x3<f64> {
+        let norm = point.coords.norm();
+        let denominator = point.z + self.xi * norm;
+        let normalized = point.coords.xy() / denominator;
+        let denominator_jacobian = (Vector3::z() + point.coords * (self.xi / norm)).transpose();
+        let normalized_jacobian =
+            Matrix2x3::new(denominator.recip(), 0.0, 0.0, 0.0, denominator.recip(), 0.0)
+                - normalized * denominator_jacobian / denominator;
+        focal_matrix(self.fx, self.fy)
+            * self.distortion.distort_jacobian(&normalized)
+            * normalized_jacobian
+    }
+}
+
+fn focal_matrix(fx: f64, fy: f64) -> Matrix2<f64> {
+    Matrix2::new(fx, 0.0, 0.0, fy)
+}
+
+/// A camera model in the config files, tagged by its `model`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum CameraConfig {
+    Pinhole(PinholeCamera),
+    Equidistant(EquidistantCamera),
+    Omni(OmniCamera),
+}
+
+impl CameraConfig {
+    pub fn into_model(self) -> Box<dyn CameraModel> {
+        match self {
+            Self::Pinhole(camera) => Box::new(camera),
+            Self::Equidistant(camera) => Box::new(camera),
+            Self::Omni(camera) => Box::new(camera),
+        }
+    }
+
+    /// Check the image size and the focal lengths.
+    pub(crate) fn validate(&self) -> Result<(), &'static str> {
+        let (width, height, fx, fy) = match self {
+            Self::Pinhole(camera) => (camera.width, camera.height, camera.fx, camera.fy),
+            Self::Equidistant(camera) => (camera.width, camera.height, camera.fx, camera.fy),
+            Self::Omni(camera) => (camera.width, camera.height, camera.fx, camera.fy),
+        };
+        if width == 0 || height == 0 {
+            return Err("the image size should be positive");
+        }
+        if !(fx > 0.0 && fy > 0.0) {
+            return Err("the focal lengths should be positive");
+        }
+        Ok(())
+    }
+}
+
+/// Deserialize a [`CameraConfig`] into its model.
+pub(crate) fn deserialize_model<'de, D>(deserializer: D) -> Result<Box<dyn CameraModel>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let config = CameraConfig::deserialize(deserializer)?;
+    config.validate().map_err(D::Error::custom)?;
+    Ok(config.into_model())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distortion() -> RadialTangential {
+        RadialTangential {
+            k1: -0.28,
+            k2: 0.07,
+            p1: 1e-4,
+            p2: -2e-4,
+            k3: 0.01,
+        }
+    }
+
+    fn cameras() -> Vec<Box<dyn CameraModel>> {
+        vec![
+            Box::new(PinholeCamera {
+                width: 752,
+                height: 480,
+                fx: 458.6,
+                fy: 457.3,
+                cx: 367.2,
+                cy: 248.4,
+                distortion: distortion(),
+            }),
+            Box::new(EquidistantCamera {
+                width: 1024,
+                height: 1024,
+                fx: 300.0,
+                fy: 300.5,
+                cx: 512.0,
+                cy: 510.0,
+                k1: 0.05,
+                k2: -0.01,
+                k3: 0.002,
+                k4: -0.0003,
+            }),
+            Box::new(OmniCamera {
+                width: 1024,
+                height: 1024,
+                xi: 1.8,
+                fx: 700.0,
+                fy: 701.0,
+                cx: 512.0,
+                cy: 515.0,
+                distortion: distortion(),
+            }),
+        ]
+    }
+
+    fn points() -> impl Iterator<Item = CameraPoint<f64>> {
+        [
+            Vector3::new(0.0, 0.0, 2.0),
+            Vector3::new(0.3, -0.2, 1.5),
+            Vector3::new(-0.5, 0.4, 3.0),
+            Vector3::new(0.1, 0.05, 0.8),
+        ]
+        .into_iter()
+        .map(Into::into)
+    }
+
+    #[test]
+    fn unproject_inverts_project() {
+        for camera in cameras() {
+            for point in points() {
+                let pixel = camera.project(&point).unwrap();
+                let bearing = camera.unproject(&pixel).unwrap();
+                assert!((bearing.coords - point.coords.normalize()).norm() < 1e-8);
+            }
+        }
+    }
+
+    #[test]
+    fn fisheye_projects_points_behind() {
+        let point = Vector3::new(1.0, 0.5, -0.1).into();
+        for camera in &cameras()[1..] {
+            let pixel = camera.project(&point).unwrap();
+            let bearing = camera.unproject(&pixel).unwrap();
+            assert!((bearing.coords - point.coords.normalize()).norm() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn project_jacobian_matches_numeric() {
+        let step = 1e-6;
+        for camera in cameras() {
+            for point in points() {
+                let jacobian = camera.project_jacobian(&point);
+                for axis in 0..3 {
+                    let offset = Vector3::ith(axis, step);
+                    let forward = camera.project(&(point.coords + offset).into()).unwrap();
+                    let backward = camera.project(&(point.coords - offset).into()).unwrap();
+                    let numeric = (forward - backward) / (2.0 * step);
+                    assert!((jacobian.column(axis) - numeric).norm() < 1e-4);
+                }
+            }
+        }
+    }
+}
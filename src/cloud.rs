@@ -0,0 +1,521 @@
+//! Point cloud files, to feed recorded scans to the odometry and to inspect the registered points.
+//!
+//! The [`pcd`] and [`ply`] formats are supported, the coordinates are written as `f32` like PCL.
+pub mod pcd;
+pub mod ply;
+
+mod lzf;
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    frame::{Body, FramedPoint},
+    measurement::{LidarPoint, LidarScan},
+    preprocess::RawPoint,
+};
+
+/// The points of a cloud with their optional fields, each field has one value per point.
+#[derive(Debug)]
+pub struct PointCloud<F> {
+    pub points: Vec<FramedPoint<f64, F>>,
+    pub intensities: Option<Vec<f32>>,
+    /// time offsets of the points, in seconds
+    pub times: Option<Vec<f64>>,
+    pub rings: Option<Vec<u16>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CloudError {
+    #[error("failed to access the cloud file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unsupported cloud file {}, expected .pcd or .ply", .0.display())]
+    UnsupportedFormat(std::path::PathBuf),
+    #[error("invalid header: {0}")]
+    Header(String),
+    #[error("invalid data of point {index}: {message}")]
+    Data { index: usize, message: String },
+    #[error("the {field} have {len} values, but there are {points} points")]
+    FieldLength {
+        field: &'static str,
+        len: usize,
+        points: usize,
+    },
+}
+
+impl<F> PointCloud<F> {
+    /// A cloud without the optional fields.
+    pub fn new(points: Vec<FramedPoint<f64, F>>) -> Self {
+        Self {
+            points,
+            intensities: None,
+            times: None,
+            rings: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Load the cloud file, the format is chosen by the extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, CloudError> {
+        let path = path.as_ref();
+        let reader = || Ok::<_, CloudError>(BufReader::new(File::open(path)?));
+        match path.extension().and_then(OsStr::to_str) {
+            Some("pcd") => pcd::read(reader()?),
+            Some("ply") => ply::read(reader()?),
+            _ => Err(CloudError::UnsupportedFormat(path.to_owned())),
+        }
+    }
+
+    /// Write the cloud in binary, the format is chosen by the extension.
+    pub fn write_path(&self, path: impl AsRef<Path>) -> Result<(), CloudError> {
+        let path = path.as_ref();
+        let writer = || Ok::<_, CloudError>(BufWriter::new(File::create(path)?));
+        let mut writer = match path.extension().and_then(OsStr::to_str) {
+            Some("pcd") => {
+                let mut writer = writer()?;
+                pcd::write(&mut writer, self, pcd::Encoding::Binary)?;
+                writer
+            }
+            Some("ply") => {
+                let mut writer = writer()?;
+                ply::write(&mut writer, self, ply::Encoding::BinaryLittleEndian)?;
+                writer
+            }
+            _ => return Err(CloudError::UnsupportedFormat(path.to_owned())),
+        };
+        Ok(writer.flush()?)
+    }
+
+    /// Check that every optional field has one value per point.
+    pub fn validate(&self) -> Result<(), CloudError> {
+        let points = self.points.len();
+        let check = |field, len: Option<usize>| match len {
+            Some(len) if len != points => Err(CloudError::FieldLength { field, len, points }),
+            _ => Ok(()),
+        };
+        check("intensities", self.intensities.as_ref().map(Vec::len))?;
+        check("times", self.times.as_ref().map(Vec::len))?;
+        check("rings", self.rings.as_ref().map(Vec::len))
+    }
+
+    /// The fields of the cloud in the order they are written.
+    fn fields(&self) -> Vec<Field> {
+        let mut fields = vec![Field::X, Field::Y, Field::Z];
+        fields.extend(self.intensities.as_ref().map(|_| Field::Intensity));
+        fields.extend(self.times.as_ref().map(|_| Field::Time));
+        fields.extend(self.rings.as_ref().map(|_| Field::Ring));
+        fields
+    }
+
+    /// The value of the field of the point at `index`.
+    fn value(&self, field: Field, index: usize) -> f64 {
+        match field {
+            Field::X => self.points[index].x,
+            Field::Y => self.points[index].y,
+            Field::Z => self.points[index].z,
+            Field::Intensity => self
+                .intensities
+                .as_ref()
+                .map_or(0.0, |intensities| intensities[index] as f64),
+            Field::Time => self.times.as_ref().map_or(0.0, |times| times[index]),
+            Field::Ring => self.rings.as_ref().map_or(0.0, |rings| rings[index] as f64),
+        }
+    }
+}
+
+impl PointCloud<Body> {
+    /// The lidar scan starting at `timestamp`, the missing fields are zero.
+    pub fn into_scan(self, timestamp: f64) -> LidarScan {
+        let Self {
+            points,
+            intensities,
+            times,
+            rings,
+        } = self;
+        let points = points
+            .into_iter()
+            .enumerate()
+            .map(|(index, point)| LidarPoint {
+                point,
+                offset_time: value_at(&times, index),
+                intensity: value_at(&intensities, index),
+                ring: value_at(&rings, index),
+            })
+            .collect();
+        LidarScan { timestamp, points }
+    }
+
+    /// The points of a cloud read with the raw times of its driver, to be decoded by
+    /// [`preprocess::decode`](crate::preprocess::decode).
+    pub fn into_raw_points(self) -> Vec<RawPoint> {
+        let Self {
+            points,
+            intensities,
+            times,
+            rings,
+        } = self;
+        points
+            .into_iter()
+            .enumerate()
+            .map(|(index, point)| RawPoint {
+                point,
+                time: value_at(&times, index),
+                intensity: value_at(&intensities, index),
+                ring: value_at(&rings, index),
+                tag: 0,
+            })
+            .collect()
+    }
+}
+
+impl From<&LidarScan> for PointCloud<Body> {
+    fn from(scan: &LidarScan) -> Self {
+        let points = &scan.points;
+        Self {
+            points: points.iter().map(|point| point.point.clone()).collect(),
+            intensities: Some(points.iter().map(|point| point.intensity).collect()),
+            times: Some(points.iter().map(|point| point.offset_time).collect()),
+            rings: Some(points.iter().map(|point| point.ring).collect()),
+        }
+    }
+}
+
+/// The fields known by the readers and the writers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Field {
+    X,
+    Y,
+    Z,
+    Intensity,
+    Time,
+    Ring,
+}
+
+impl Field {
+    /// The field of a property name, the time names are those of Velodyne, Ouster, Livox and Hesai drivers.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "x" => Some(Self::X),
+            "y" => Some(Self::Y),
+            "z" => Some(Self::Z),
+            "intensity" | "reflectivity" => Some(Self::Intensity),
+            "time" | "t" | "offset_time" | "timestamp" => Some(Self::Time),
+            "ring" | "line" => Some(Self::Ring),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::X => "x",
+            Self::Y => "y",
+            Self::Z => "z",
+            Self::Intensity => "intensity",
+            Self::Time => "time",
+            Self::Ring => "ring",
+        }
+    }
+
+    /// The type the field is written with.
+    fn written_type(self) -> ScalarType {
+        match self {
+            Self::X | Self::Y | Self::Z | Self::Intensity => ScalarType::F32,
+            Self::Time => ScalarType::F64,
+            Self::Ring => ScalarType::U16,
+        }
+    }
+}
+
+/// The scalar types of the fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    pub(crate) fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::I64 | Self::U64 | Self::F64 => 8,
+        }
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, Self::F32 | Self::F64)
+    }
+
+    /// Decode a value from its `size()` bytes.
+    pub(crate) fn decode(self, bytes: &[u8], big_endian: bool) -> f64 {
+        macro_rules! decode {
+            ($ty:ty) => {{
+                let bytes = bytes.try_into().expect("the value should have its size");
+                (if big_endian {
+                    <$ty>::from_be_bytes(bytes)
+                } else {
+                    <$ty>::from_le_bytes(bytes)
+                }) as f64
+            }};
+        }
+        match self {
+            Self::I8 => decode!(i8),
+            Self::U8 => decode!(u8),
+            Self::I16 => decode!(i16),
+            Self::U16 => decode!(u16),
+            Self::I32 => decode!(i32),
+            Self::U32 => decode!(u32),
+            Self::I64 => decode!(i64),
+            Self::U64 => decode!(u64),
+            Self::F32 => decode!(f32),
+            Self::F64 => decode!(f64),
+        }
+    }
+
+    fn encode(self, value: f64, big_endian: bool, bytes: &mut Vec<u8>) {
+        macro_rules! encode {
+            ($ty:ty) => {{
+                let value = value as $ty;
+                if big_endian {
+                    bytes.extend_from_slice(&value.to_be_bytes())
+                } else {
+                    bytes.extend_from_slice(&value.to_le_bytes())
+                }
+            }};
+        }
+        match self {
+            Self::I8 => encode!(i8),
+            Self::U8 => encode!(u8),
+            Self::I16 => encode!(i16),
+            Self::U16 => encode!(u16),
+            Self::I32 => encode!(i32),
+            Self::U32 => encode!(u32),
+            Self::I64 => encode!(i64),
+            Self::U64 => encode!(u64),
+            Self::F32 => encode!(f32),
+            Self::F64 => encode!(f64),
+        }
+    }
+
+    /// Parse a value of the ascii encodings, rounded to the precision of the type.
+    fn parse(self, token: &str) -> Option<f64> {
+        match self {
+            Self::F32 => token.parse::<f32>().ok().map(f64::from),
+            _ => token.parse().ok(),
+        }
+    }
+
+    /// Format a value for the ascii encodings, the floats keep their precision.
+    fn format(self, value: f64) -> String {
+        match self {
+            Self::F32 => (value as f32).to_string(),
+            Self::F64 => value.to_string(),
+            _ => (value as i64).to_string(),
+        }
+    }
+}
+
+/// A field of a file, `None` if it is not known and skipped.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Property {
+    pub(crate) field: Option<Field>,
+    pub(crate) ty: ScalarType,
+    /// the number of values, only the first one is used
+    pub(crate) count: usize,
+}
+
+/// Collects the decoded values of the known fields.
+pub(crate) struct CloudBuilder<F> {
+    pub(crate) cloud: PointCloud<F>,
+    /// the field of each property, only the first property of a field is used,
+    /// e.g. the `intensity` of Ouster and not its `reflectivity`
+    fields: Vec<Option<Field>>,
+    /// the integer times are in nanoseconds, like the `t` field of Ouster
+    time_divisor: f64,
+}
+
+impl<F> CloudBuilder<F> {
+    /// With `raw_times`, the times are kept in the unit of the driver to be decoded by a
+    /// [`Lidar`](crate::preprocess::Lidar), otherwise the integer times are taken as nanoseconds.
+    pub(crate) fn new(
+        properties: &[Property],
+        capacity: usize,
+        raw_times: bool,
+    ) -> Result<Self, CloudError> {
+        let fields: Vec<_> = properties
+            .iter()
+            .enumerate()
+            .map(|(index, property)| {
+                property.field.filter(|&field| {
+                    properties[..index]
+                        .iter()
+                        .all(|previous| previous.field != Some(field))
+                })
+            })
+            .collect();
+        let has = |field| fields.contains(&Some(field));
+        if let Some(field) = [Field::X, Field::Y, Field::Z]
+            .into_iter()
+            .find(|&field| !has(field))
+        {
+            return Err(CloudError::Header(format!(
+                "the field {} is missing",
+                field.name()
+            )));
+        }
+        let time_is_float = properties
+            .iter()
+            .find(|property| property.field == Some(Field::Time))
+            .is_none_or(|property| property.ty.is_float());
+        Ok(Self {
+            cloud: PointCloud {
+                points: Vec::with_capacity(capacity),
+                intensities: has(Field::Intensity).then(|| Vec::with_capacity(capacity)),
+                times: has(Field::Time).then(|| Vec::with_capacity(capacity)),
+                rings: has(Field::Ring).then(|| Vec::with_capacity(capacity)),
+            },
+            fields,
+            time_divisor: if raw_times || time_is_float { 1.0 } else { 1e9 },
+        })
+    }
+
+    /// Push a point from the values of the properties, the unknown ones are ignored.
+    ///
+    /// The points with a non-finite coordinate are skipped, like the invalid points of organized clouds.
+    pub(crate) fn push(&mut self, values: &[f64]) {
+        let mut point = [f64::NAN; 3];
+        self.fields
+            .iter()
+            .zip(values)
+            .for_each(|(field, &value)| match field {
+                Some(Field::X) => point[0] = value,
+                Some(Field::Y) => point[1] = value,
+                Some(Field::Z) => point[2] = value,
+                _ => {}
+            });
+        if !point.iter().all(|value| value.is_finite()) {
+            return;
+        }
+        self.fields
+            .iter()
+            .zip(values)
+            .for_each(|(field, &value)| match field {
+                Some(Field::Intensity) => push(&mut self.cloud.intensities, value as f32),
+                Some(Field::Time) => push(&mut self.cloud.times, value / self.time_divisor),
+                Some(Field::Ring) => push(&mut self.cloud.rings, value as u16),
+                _ => {}
+            });
+        let [x, y, z] = point;
+        self.cloud
+            .points
+            .push(nalgebra::Vector3::new(x, y, z).into());
+    }
+}
+
+fn value_at<T: Copy + Default>(values: &Option<Vec<T>>, index: usize) -> T {
+    values.as_ref().map_or(T::default(), |values| values[index])
+}
+
+fn push<T>(values: &mut Option<Vec<T>>, value: T) {
+    if let Some(values) = values {
+        values.push(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::frame::World;
+
+    /// A cloud with every field, the values are exact in `f32`.
+    pub(super) fn cloud() -> PointCloud<World> {
+        let points = (0..50)
+            .map(|i| Vector3::new(i as f64 * 0.25, -(i as f64) * 0.5, 10.0 + i as f64).into())
+            .collect();
+        PointCloud {
+            points,
+            intensities: Some((0..50).map(|i| i as f32 * 2.0).collect()),
+            times: Some((0..50).map(|i| i as f64 * 1e-4).collect()),
+            rings: Some((0..50).map(|i| i % 6).collect()),
+        }
+    }
+
+    pub(super) fn assert_same<F: std::fmt::Debug>(read: &PointCloud<F>, written: &PointCloud<F>) {
+        assert_eq!(read.points, written.points);
+        assert_eq!(read.intensities, written.intensities);
+        assert_eq!(read.times, written.times);
+        assert_eq!(read.rings, written.rings);
+    }
+
+    #[test]
+    fn scan_round_trip() {
+        let cloud = PointCloud::<Body> {
+            points: vec![Vector3::new(1.0, 2.0, 3.0).into(); 2],
+            intensities: Some(vec![5.0, 6.0]),
+            times: None,
+            rings: Some(vec![1, 2]),
+        };
+        let scan = cloud.into_scan(10.0);
+        assert_eq!(scan.points[1].intensity, 6.0);
+        assert_eq!(scan.points[1].offset_time, 0.0);
+
+        let cloud = PointCloud::from(&scan);
+        assert_eq!(cloud.rings, Some(vec![1, 2]));
+        assert_eq!(cloud.times, Some(vec![0.0, 0.0]));
+    }
+
+    #[test]
+    fn write_and_read_files() {
+        let directory = std::env::temp_dir().join(format!("cloud-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let written = cloud();
+        ["cloud.pcd", "cloud.ply"].into_iter().for_each(|name| {
+            let path = directory.join(name);
+            written.write_path(&path).unwrap();
+            assert_same(&PointCloud::from_path(&path).unwrap(), &written);
+        });
+        assert!(matches!(
+            written.write_path(directory.join("cloud.xyz")),
+            Err(CloudError::UnsupportedFormat(_))
+        ));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn first_property_of_a_field() {
+        // the ouster driver has an intensity and a reflectivity
+        let properties = ["x", "y", "z", "intensity", "reflectivity"].map(|name| Property {
+            field: Field::from_name(name),
+            ty: ScalarType::F32,
+            count: 1,
+        });
+        let mut builder = CloudBuilder::<Body>::new(&properties, 2, false).unwrap();
+        builder.push(&[1.0, 2.0, 3.0, 10.0, 200.0]);
+        builder.push(&[4.0, 5.0, 6.0, 20.0, 100.0]);
+        let cloud = builder.cloud;
+        assert_eq!(cloud.intensities, Some(vec![10.0, 20.0]));
+        cloud.validate().unwrap();
+    }
+}
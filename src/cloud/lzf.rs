@@ -0,0 +1,123 @@
+//! The LZF compression of the `binary_compressed` PCD files.
+//!
+//! A control byte below 32 starts a run of `control + 1` literal bytes,
+//! otherwise its top 3 bits and an optional extra byte are the length of a back reference,
+//! and its low 5 bits with the next byte are the offset.
+
+const HASH_LOG: u32 = 14;
+const MAX_LITERALS: usize = 1 << 5;
+const MAX_OFFSET: usize = 1 << 13;
+const MAX_REFERENCE: usize = (1 << 8) + (1 << 3);
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / MAX_LITERALS + 1);
+    // the latest position of each hashed 3-byte sequence
+    let mut table = vec![usize::MAX; 1 << HASH_LOG];
+    let mut literals_start = 0;
+    let mut position = 0;
+    while position + 2 < input.len() {
+        let sequence = &input[position..position + 3];
+        let candidate = std::mem::replace(&mut table[hash(sequence)], position);
+        let matched = candidate != usize::MAX
+            && position - candidate <= MAX_OFFSET
+            && &input[candidate..candidate + 3] == sequence;
+        if !matched {
+            position += 1;
+            continue;
+        }
+
+        let max_length = (input.len() - position).min(MAX_REFERENCE);
+        let length = (3..max_length)
+            .find(|&length| input[candidate + length] != input[position + length])
+            .unwrap_or(max_length);
+        push_literals(&mut output, &input[literals_start..position]);
+
+        let offset = position - candidate - 1;
+        let length_code = length - 2;
+        if length_code < 7 {
+            output.push(((length_code << 5) | (offset >> 8)) as u8);
+        } else {
+            output.push(((7 << 5) | (offset >> 8)) as u8);
+            output.push((length_code - 7) as u8);
+        }
+        output.push(offset as u8);
+        position += length;
+        literals_start = position;
+    }
+    push_literals(&mut output, &input[literals_start..]);
+    output
+}
+
+/// Decompress `input`, which should expand to exactly `len` bytes.
+pub fn decompress(input: &[u8], len: usize) -> Result<Vec<u8>, &'static str> {
+    const TRUNCATED: &str = "the compressed data is truncated";
+
+    let mut output = Vec::with_capacity(len);
+    let mut bytes = input.iter().map(|&byte| byte as usize);
+    while let Some(control) = bytes.next() {
+        if control < MAX_LITERALS {
+            let start = input.len() - bytes.len();
+            let literals = input.get(start..start + control + 1).ok_or(TRUNCATED)?;
+            output.extend_from_slice(literals);
+            bytes.nth(control);
+        } else {
+            let mut length = control >> 5;
+            if length == 7 {
+                length += bytes.next().ok_or(TRUNCATED)?;
+            }
+            let offset = ((control & 0x1f) << 8 | bytes.next().ok_or(TRUNCATED)?) + 1;
+            let start = output
+                .len()
+                .checked_sub(offset)
+                .ok_or("a back reference is before the start")?;
+            // the reference may overlap the bytes it produces
+            (start..start + length + 2).for_each(|index| output.push(output[index]));
+        }
+        if output.len() > len {
+            return Err("the decompressed data is longer than expected");
+        }
+    }
+    if output.len() != len {
+        return Err("the decompressed data is shorter than expected");
+    }
+    Ok(output)
+}
+
+fn hash(sequence: &[u8]) -> usize {
+    let value = u32::from_be_bytes([0, sequence[0], sequence[1], sequence[2]]);
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+fn push_literals(output: &mut Vec<u8>, literals: &[u8]) {
+    literals.chunks(MAX_LITERALS).for_each(|chunk| {
+        output.push((chunk.len() - 1) as u8);
+        output.extend_from_slice(chunk);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let repeated: Vec<u8> = (0..5000).map(|i| (i % 7) as u8).collect();
+        let noisy: Vec<u8> = (0u32..3000)
+            .map(|i| (i.wrapping_mul(2_246_822_519) >> 13) as u8)
+            .collect();
+        [Vec::new(), vec![42], repeated.clone(), noisy]
+            .into_iter()
+            .for_each(|input| {
+                let compressed = compress(&input);
+                assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+            });
+        assert!(compress(&repeated).len() < repeated.len() / 20);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(decompress(&[5, 1, 2], 6).is_err());
+        assert!(decompress(&[0x20, 0x00], 2).is_err());
+        assert!(decompress(&[0, 1], 2).is_err());
+    }
+}
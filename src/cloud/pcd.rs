@@ -0,0 +1,449 @@
+//! The PCD files of PCL, see <https://pointclouds.org/documentation/tutorials/pcd_file_format.html>.
+use std::io::{BufRead, Write};
+
+use super::{CloudBuilder, CloudError, Field, PointCloud, Property, ScalarType, lzf};
+
+/// The encoding of the point data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Ascii,
+    /// the points one after another
+    Binary,
+    /// the values of each field one after another, compressed with LZF
+    BinaryCompressed,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Ascii => "ascii",
+            Self::Binary => "binary",
+            Self::BinaryCompressed => "binary_compressed",
+        }
+    }
+}
+
+struct Header {
+    properties: Vec<Property>,
+    points: usize,
+    encoding: Encoding,
+}
+
+/// Read a PCD file, the points with a non-finite coordinate are skipped.
+pub fn read<F>(reader: impl BufRead) -> Result<PointCloud<F>, CloudError> {
+    read_cloud(reader, false)
+}
+
+/// Read a PCD file with the point times in the unit of the driver,
+/// to be decoded by a [`Lidar`](crate::preprocess::Lidar).
+pub fn read_raw<F>(reader: impl BufRead) -> Result<PointCloud<F>, CloudError> {
+    read_cloud(reader, true)
+}
+
+fn read_cloud<F>(mut reader: impl BufRead, raw_times: bool) -> Result<PointCloud<F>, CloudError> {
+    let header = read_header(&mut reader)?;
+    let mut builder = CloudBuilder::new(&header.properties, header.points, raw_times)?;
+    match header.encoding {
+        Encoding::Ascii => read_ascii(reader, &header, &mut builder)?,
+        Encoding::Binary => {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            read_binary(&data, &header, &mut builder)?;
+        }
+        Encoding::BinaryCompressed => {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            read_compressed(&data, &header, &mut builder)?;
+        }
+    }
+    Ok(builder.cloud)
+}
+
+/// Write a PCD file with the given encoding, as an unorganized cloud.
+pub fn write<F>(
+    mut writer: impl Write,
+    cloud: &PointCloud<F>,
+    encoding: Encoding,
+) -> Result<(), CloudError> {
+    cloud.validate()?;
+    let fields = cloud.fields();
+    let join = |value: fn(Field) -> String| fields.iter().copied().map(value).collect::<Vec<_>>();
+    let names = join(|field| field.name().to_owned()).join(" ");
+    let sizes = join(|field| field.written_type().size().to_string()).join(" ");
+    let types = join(|field| type_name(field.written_type()).to_owned()).join(" ");
+    let counts = join(|_| "1".to_owned()).join(" ");
+    let len = cloud.len();
+    write!(
+        writer,
+        "# .PCD v0.7 - Point Cloud Data file format\n\
+         VERSION 0.7\n\
+         FIELDS {names}\n\
+         SIZE {sizes}\n\
+         TYPE {types}\n\
+         COUNT {counts}\n\
+         WIDTH {len}\n\
+         HEIGHT 1\n\
+         VIEWPOINT 0 0 0 1 0 0 0\n\
+         POINTS {len}\n\
+         DATA {}\n",
+        encoding.name()
+    )?;
+
+    match encoding {
+        Encoding::Ascii => (0..len).try_for_each(|index| {
+            let values = fields
+                .iter()
+                .map(|&field| field.written_type().format(cloud.value(field, index)))
+                .collect::<Vec<_>>();
+            writeln!(writer, "{}", values.join(" "))
+        })?,
+        Encoding::Binary => {
+            let mut data = Vec::new();
+            (0..len).for_each(|index| {
+                fields.iter().for_each(|&field| {
+                    let value = cloud.value(field, index);
+                    field.written_type().encode(value, false, &mut data);
+                })
+            });
+            writer.write_all(&data)?;
+        }
+        Encoding::BinaryCompressed => {
+            let mut data = Vec::new();
+            fields.iter().for_each(|&field| {
+                (0..len).for_each(|index| {
+                    let value = cloud.value(field, index);
+                    field.written_type().encode(value, false, &mut data);
+                })
+            });
+            let compressed = lzf::compress(&data);
+            writer.write_all(&(compressed.len() as u32).to_le_bytes())?;
+            writer.write_all(&(data.len() as u32).to_le_bytes())?;
+            writer.write_all(&compressed)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_header(reader: &mut impl BufRead) -> Result<Header, CloudError> {
+    let header = |message: &str| CloudError::Header(message.to_owned());
+    let numbers = |key: &str, values: &[&str]| {
+        values
+            .iter()
+            .map(|value| value.parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| CloudError::Header(format!("{key} should be non-negative integers")))
+    };
+    let number = |key: &str, values: &[&str]| match numbers(key, values)?.as_slice() {
+        &[value] => Ok(value),
+        _ => Err(CloudError::Header(format!("{key} should be one integer"))),
+    };
+
+    let (mut names, mut sizes, mut types, mut counts) = (None, None, None, None);
+    let (mut width, mut height, mut points) = (None, None, None);
+    let mut line = String::new();
+    let encoding = loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(header("DATA is missing"));
+        }
+        let mut tokens = line.split_whitespace();
+        let Some(key) = tokens.next().filter(|key| !key.starts_with('#')) else {
+            continue;
+        };
+        let values: Vec<_> = tokens.collect();
+        match key {
+            "VERSION" | "VIEWPOINT" => {}
+            "FIELDS" => names = Some(values.iter().map(|&name| name.to_owned()).collect()),
+            "SIZE" => sizes = Some(numbers(key, &values)?),
+            "TYPE" => types = Some(values.iter().map(|&ty| ty.to_owned()).collect()),
+            "COUNT" => counts = Some(numbers(key, &values)?),
+            "WIDTH" => width = Some(number(key, &values)?),
+            "HEIGHT" => height = Some(number(key, &values)?),
+            "POINTS" => points = Some(number(key, &values)?),
+            "DATA" => {
+                break match values.as_slice() {
+                    ["ascii"] => Encoding::Ascii,
+                    ["binary"] => Encoding::Binary,
+                    ["binary_compressed"] => Encoding::BinaryCompressed,
+                    _ => return Err(header("DATA should be ascii, binary or binary_compressed")),
+                };
+            }
+            _ => return Err(CloudError::Header(format!("unknown key {key}"))),
+        }
+    };
+
+    let names: Vec<String> = names.ok_or_else(|| header("FIELDS is missing"))?;
+    let sizes = sizes.ok_or_else(|| header("SIZE is missing"))?;
+    let types: Vec<String> = types.ok_or_else(|| header("TYPE is missing"))?;
+    let counts = counts.unwrap_or_else(|| vec![1; names.len()]);
+    if [sizes.len(), types.len(), counts.len()] != [names.len(); 3] {
+        return Err(header(
+            "FIELDS, SIZE, TYPE and COUNT should have the same length",
+        ));
+    }
+    // the fields without values take no space
+    let properties = names
+        .iter()
+        .zip(types.iter().zip(sizes))
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|((name, (ty, size)), count)| {
+            let ty = scalar_type(ty, size).ok_or_else(|| {
+                CloudError::Header(format!("unsupported TYPE {ty} with SIZE {size} of {name}"))
+            })?;
+            Ok(Property {
+                field: Field::from_name(name),
+                ty,
+                count,
+            })
+        })
+        .collect::<Result<_, CloudError>>()?;
+
+    let points = match (points, width, height) {
+        (Some(points), _, _) => points,
+        (None, Some(width), Some(height)) => width * height,
+        _ => return Err(header("POINTS is missing")),
+    };
+    Ok(Header {
+        properties,
+        points,
+        encoding,
+    })
+}
+
+fn read_ascii<F>(
+    reader: impl BufRead,
+    header: &Header,
+    builder: &mut CloudBuilder<F>,
+) -> Result<(), CloudError> {
+    let offsets = offsets(&header.properties, |property| property.count);
+    let values_len: usize = header
+        .properties
+        .iter()
+        .map(|property| property.count)
+        .sum();
+    let mut values = vec![0.0; header.properties.len()];
+    let mut lines = reader
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()));
+    (0..header.points).try_for_each(|index| {
+        let data = |message: &str| CloudError::Data {
+            index,
+            message: message.to_owned(),
+        };
+        let line = lines
+            .next()
+            .ok_or_else(|| data("the data is truncated"))??;
+        let tokens: Vec<_> = line.split_whitespace().collect();
+        if tokens.len() != values_len {
+            return Err(data(&format!("expected {values_len} values")));
+        }
+        values
+            .iter_mut()
+            .zip(&offsets)
+            .zip(&header.properties)
+            .try_for_each(|((value, &offset), property)| {
+                *value = property
+                    .ty
+                    .parse(tokens[offset])
+                    .ok_or_else(|| data(&format!("{:?} is not a number", tokens[offset])))?;
+                Ok::<_, CloudError>(())
+            })?;
+        builder.push(&values);
+        Ok(())
+    })
+}
+
+fn read_binary<F>(
+    data: &[u8],
+    header: &Header,
+    builder: &mut CloudBuilder<F>,
+) -> Result<(), CloudError> {
+    let offsets = offsets(&header.properties, |property| {
+        property.ty.size() * property.count
+    });
+    let point_size: usize = header
+        .properties
+        .iter()
+        .map(|property| property.ty.size() * property.count)
+        .sum();
+    let truncated = data.len() / point_size.max(1);
+    if truncated < header.points {
+        return Err(CloudError::Data {
+            index: truncated,
+            message: "the data is truncated".into(),
+        });
+    }
+    let mut values = vec![0.0; header.properties.len()];
+    data.chunks_exact(point_size)
+        .take(header.points)
+        .for_each(|point| {
+            values
+                .iter_mut()
+                .zip(&header.properties)
+                .zip(&offsets)
+                .for_each(|((value, property), &offset)| {
+                    *value = property
+                        .ty
+                        .decode(&point[offset..offset + property.ty.size()], false);
+                });
+            builder.push(&values);
+        });
+    Ok(())
+}
+
+fn read_compressed<F>(
+    data: &[u8],
+    header: &Header,
+    builder: &mut CloudBuilder<F>,
+) -> Result<(), CloudError> {
+    let invalid = |message: &str| CloudError::Data {
+        index: 0,
+        message: message.to_owned(),
+    };
+    let size = |range: std::ops::Range<usize>| {
+        let bytes = data
+            .get(range)
+            .ok_or_else(|| invalid("the sizes are missing"))?;
+        Ok::<_, CloudError>(u32::from_le_bytes(bytes.try_into().expect("4 bytes")) as usize)
+    };
+    let (compressed_len, len) = (size(0..4)?, size(4..8)?);
+    let compressed = data
+        .get(8..8 + compressed_len)
+        .ok_or_else(|| invalid("the data is truncated"))?;
+    let data = lzf::decompress(compressed, len).map_err(invalid)?;
+
+    // the values of each field are stored one after another
+    let offsets = offsets(&header.properties, |property| {
+        property.ty.size() * property.count * header.points
+    });
+    let point_size: usize = header
+        .properties
+        .iter()
+        .map(|property| property.ty.size() * property.count)
+        .sum();
+    if data.len() < point_size * header.points {
+        return Err(invalid("the decompressed data is shorter than the points"));
+    }
+    let mut values = vec![0.0; header.properties.len()];
+    (0..header.points).for_each(|index| {
+        values
+            .iter_mut()
+            .zip(&header.properties)
+            .zip(&offsets)
+            .for_each(|((value, property), &offset)| {
+                let size = property.ty.size();
+                let start = offset + index * size * property.count;
+                *value = property.ty.decode(&data[start..start + size], false);
+            });
+        builder.push(&values);
+    });
+    Ok(())
+}
+
+/// The offset of the first value of each property, with the length of a property given by `len`.
+fn offsets(properties: &[Property], len: impl Fn(&Property) -> usize) -> Vec<usize> {
+    properties
+        .iter()
+        .scan(0, |offset, property| {
+            let start = *offset;
+            *offset += len(property);
+            Some(start)
+        })
+        .collect()
+}
+
+fn scalar_type(ty: &str, size: usize) -> Option<ScalarType> {
+    match (ty, size) {
+        ("I", 1) => Some(ScalarType::I8),
+        ("U", 1) => Some(ScalarType::U8),
+        ("I", 2) => Some(ScalarType::I16),
+        ("U", 2) => Some(ScalarType::U16),
+        ("I", 4) => Some(ScalarType::I32),
+        ("U", 4) => Some(ScalarType::U32),
+        ("I", 8) => Some(ScalarType::I64),
+        ("U", 8) => Some(ScalarType::U64),
+        ("F", 4) => Some(ScalarType::F32),
+        ("F", 8) => Some(ScalarType::F64),
+        _ => None,
+    }
+}
+
+fn type_name(ty: ScalarType) -> &'static str {
+    match ty {
+        ScalarType::I8 | ScalarType::I16 | ScalarType::I32 | ScalarType::I64 => "I",
+        ScalarType::U8 | ScalarType::U16 | ScalarType::U32 | ScalarType::U64 => "U",
+        ScalarType::F32 | ScalarType::F64 => "F",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cloud::tests::{assert_same, cloud},
+        frame::Body,
+    };
+
+    #[test]
+    fn round_trip() {
+        let written = cloud();
+        [
+            Encoding::Ascii,
+            Encoding::Binary,
+            Encoding::BinaryCompressed,
+        ]
+        .into_iter()
+        .for_each(|encoding| {
+            let mut data = Vec::new();
+            write(&mut data, &written, encoding).unwrap();
+            assert_same(&read(data.as_slice()).unwrap(), &written);
+        });
+    }
+
+    #[test]
+    fn ouster_fields() {
+        // the padding field, the nanosecond time and the second intensity field of the ouster
+        // driver, and an invalid point
+        let content = "\
+# .PCD v0.7 - Point Cloud Data file format
+VERSION 0.7
+FIELDS x y z _ intensity t reflectivity ring
+SIZE 4 4 4 1 4 4 2 2
+TYPE F F F U F U U U
+COUNT 1 1 1 4 1 1 1 1
+WIDTH 2
+HEIGHT 1
+POINTS 2
+DATA ascii
+1.5 2 3 0 0 0 0 10 2000000 120 7
+nan nan nan 0 0 0 0 0 0 0 0
+";
+        let cloud = read::<Body>(content.as_bytes()).unwrap();
+        assert_eq!(cloud.len(), 1);
+        assert_eq!(cloud.points[0].x, 1.5);
+        assert_eq!(cloud.intensities, Some(vec![10.0]));
+        assert_eq!(cloud.times, Some(vec![0.002]));
+        assert_eq!(cloud.rings, Some(vec![7]));
+    }
+
+    #[test]
+    fn invalid() {
+        let header = "FIELDS x y\nSIZE 4 4\nTYPE F F\nPOINTS 1\nDATA ascii\n1 2\n";
+        assert!(matches!(
+            read::<Body>(header.as_bytes()),
+            Err(CloudError::Header(_))
+        ));
+        let truncated = "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 2\nDATA binary\n";
+        assert!(matches!(
+            read::<Body>([truncated.as_bytes(), &[0; 20]].concat().as_slice()),
+            Err(CloudError::Data { index: 1, .. })
+        ));
+        let mut mismatched = cloud();
+        mismatched.rings.as_mut().unwrap().pop();
+        assert!(matches!(
+            write(Vec::new(), &mismatched, Encoding::Binary),
+            Err(CloudError::FieldLength { field: "rings", .. })
+        ));
+    }
+}
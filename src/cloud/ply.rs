@@ -0,0 +1,348 @@
+//! The PLY files, see <https://paulbourke.net/dataformats/ply/>.
+//!
+//! The points are the `vertex` elements, the other elements like the faces are skipped.
+use std::io::{BufRead, Write};
+
+use super::{CloudBuilder, CloudError, Field, PointCloud, Property, ScalarType};
+
+/// The encoding of the elements.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Ascii => "ascii",
+            Self::BinaryLittleEndian => "binary_little_endian",
+            Self::BinaryBigEndian => "binary_big_endian",
+        }
+    }
+
+    fn is_big_endian(self) -> bool {
+        self == Self::BinaryBigEndian
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PropertyType {
+    Scalar(ScalarType),
+    /// a list with its length before its items
+    List {
+        len: ScalarType,
+        item: ScalarType,
+    },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, PropertyType)>,
+}
+
+/// Read a PLY file, the points with a non-finite coordinate are skipped.
+pub fn read<F>(mut reader: impl BufRead) -> Result<PointCloud<F>, CloudError> {
+    let (encoding, elements) = read_header(&mut reader)?;
+    let vertex = elements
+        .iter()
+        .position(|element| element.name == "vertex")
+        .ok_or_else(|| CloudError::Header("the vertex element is missing".into()))?;
+    let properties = elements[vertex]
+        .properties
+        .iter()
+        .map(|(name, ty)| match ty {
+            PropertyType::Scalar(ty) => Ok(Property {
+                field: Field::from_name(name),
+                ty: *ty,
+                count: 1,
+            }),
+            PropertyType::List { .. } => Err(CloudError::Header(format!(
+                "the list property {name} of the vertices is not supported"
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let points = elements[vertex].count;
+    let mut builder = CloudBuilder::new(&properties, points, false)?;
+    let mut values = vec![0.0; properties.len()];
+
+    if encoding == Encoding::Ascii {
+        let mut lines = reader.lines();
+        // each element is on its own line
+        let skipped = elements[..vertex].iter().map(|element| element.count).sum();
+        lines
+            .by_ref()
+            .take(skipped)
+            .try_for_each(|line| line.map(drop))?;
+        return (0..points)
+            .try_for_each(|index| {
+                let data = |message: &str| CloudError::Data {
+                    index,
+                    message: message.to_owned(),
+                };
+                let line = lines
+                    .next()
+                    .ok_or_else(|| data("the data is truncated"))??;
+                let tokens: Vec<_> = line.split_whitespace().collect();
+                if tokens.len() < properties.len() {
+                    return Err(data(&format!("expected {} values", properties.len())));
+                }
+                values
+                    .iter_mut()
+                    .zip(&properties)
+                    .zip(tokens)
+                    .try_for_each(|((value, property), token)| {
+                        *value = property
+                            .ty
+                            .parse(token)
+                            .ok_or_else(|| data(&format!("{token:?} is not a number")))?;
+                        Ok::<_, CloudError>(())
+                    })?;
+                builder.push(&values);
+                Ok(())
+            })
+            .map(|()| builder.cloud);
+    }
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    let big_endian = encoding.is_big_endian();
+    let mut position = elements[..vertex]
+        .iter()
+        .try_fold(0, |position, element| {
+            skip(&data, position, element, big_endian)
+        })
+        .ok_or_else(|| CloudError::Data {
+            index: 0,
+            message: "the elements before the vertices are truncated".into(),
+        })?;
+    (0..points).try_for_each(|index| {
+        values
+            .iter_mut()
+            .zip(&properties)
+            .try_for_each(|(value, property)| {
+                let size = property.ty.size();
+                let bytes = data.get(position..position + size)?;
+                *value = property.ty.decode(bytes, big_endian);
+                position += size;
+                Some(())
+            })
+            .ok_or_else(|| CloudError::Data {
+                index,
+                message: "the data is truncated".into(),
+            })?;
+        builder.push(&values);
+        Ok::<_, CloudError>(())
+    })?;
+    Ok(builder.cloud)
+}
+
+/// Write the points as the vertices of a PLY file with the given encoding.
+pub fn write<F>(
+    mut writer: impl Write,
+    cloud: &PointCloud<F>,
+    encoding: Encoding,
+) -> Result<(), CloudError> {
+    cloud.validate()?;
+    let fields = cloud.fields();
+    let len = cloud.len();
+    writeln!(writer, "ply\nformat {} 1.0", encoding.name())?;
+    writeln!(writer, "element vertex {len}")?;
+    fields.iter().try_for_each(|field| {
+        let ty = type_name(field.written_type());
+        writeln!(writer, "property {ty} {}", field.name())
+    })?;
+    writeln!(writer, "end_header")?;
+
+    if encoding == Encoding::Ascii {
+        return (0..len)
+            .try_for_each(|index| {
+                let values = fields
+                    .iter()
+                    .map(|&field| field.written_type().format(cloud.value(field, index)))
+                    .collect::<Vec<_>>();
+                writeln!(writer, "{}", values.join(" "))
+            })
+            .map_err(CloudError::from);
+    }
+    let mut data = Vec::new();
+    (0..len).for_each(|index| {
+        fields.iter().for_each(|&field| {
+            let value = cloud.value(field, index);
+            field
+                .written_type()
+                .encode(value, encoding.is_big_endian(), &mut data);
+        })
+    });
+    Ok(writer.write_all(&data)?)
+}
+
+fn read_header(reader: &mut impl BufRead) -> Result<(Encoding, Vec<Element>), CloudError> {
+    let header = |message: String| CloudError::Header(message);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != "ply" {
+        return Err(header("the file should start with ply".into()));
+    }
+
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(header("end_header is missing".into()));
+        }
+        let tokens: Vec<_> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            [] | ["comment" | "obj_info", ..] => {}
+            ["end_header"] => break,
+            ["format", format, _version] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(header(format!("unknown format {format}"))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| header(format!("the count of {name} should be an integer")))?,
+                properties: Vec::new(),
+            }),
+            ["property", rest @ ..] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| header("a property is before the elements".into()))?;
+                let scalar = |name: &str| {
+                    scalar_type(name).ok_or_else(|| header(format!("unknown type {name}")))
+                };
+                let property = match rest {
+                    ["list", len, item, name] => (
+                        name.to_string(),
+                        PropertyType::List {
+                            len: scalar(len)?,
+                            item: scalar(item)?,
+                        },
+                    ),
+                    [ty, name] => (name.to_string(), PropertyType::Scalar(scalar(ty)?)),
+                    _ => return Err(header(format!("invalid property: {}", line.trim_end()))),
+                };
+                element.properties.push(property);
+            }
+            _ => return Err(header(format!("invalid line: {}", line.trim_end()))),
+        }
+    }
+    let encoding = encoding.ok_or_else(|| header("format is missing".into()))?;
+    Ok((encoding, elements))
+}
+
+/// The position after the binary instances of `element`, `None` if they are truncated.
+fn skip(data: &[u8], mut position: usize, element: &Element, big_endian: bool) -> Option<usize> {
+    (0..element.count).try_for_each(|_| {
+        element.properties.iter().try_for_each(|(_, ty)| {
+            position += match *ty {
+                PropertyType::Scalar(ty) => ty.size(),
+                PropertyType::List { len, item } => {
+                    let bytes = data.get(position..position + len.size())?;
+                    len.size() + len.decode(bytes, big_endian) as usize * item.size()
+                }
+            };
+            Some(())
+        })
+    })?;
+    (position <= data.len()).then_some(position)
+}
+
+fn scalar_type(name: &str) -> Option<ScalarType> {
+    match name {
+        "char" | "int8" => Some(ScalarType::I8),
+        "uchar" | "uint8" => Some(ScalarType::U8),
+        "short" | "int16" => Some(ScalarType::I16),
+        "ushort" | "uint16" => Some(ScalarType::U16),
+        "int" | "int32" => Some(ScalarType::I32),
+        "uint" | "uint32" => Some(ScalarType::U32),
+        "float" | "float32" => Some(ScalarType::F32),
+        "double" | "float64" => Some(ScalarType::F64),
+        _ => None,
+    }
+}
+
+fn type_name(ty: ScalarType) -> &'static str {
+    match ty {
+        ScalarType::I8 => "char",
+        ScalarType::U8 => "uchar",
+        ScalarType::I16 => "short",
+        ScalarType::U16 => "ushort",
+        ScalarType::I32 | ScalarType::I64 => "int",
+        ScalarType::U32 | ScalarType::U64 => "uint",
+        ScalarType::F32 => "float",
+        ScalarType::F64 => "double",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cloud::tests::{assert_same, cloud},
+        frame::World,
+    };
+
+    #[test]
+    fn round_trip() {
+        let written = cloud();
+        [
+            Encoding::Ascii,
+            Encoding::BinaryLittleEndian,
+            Encoding::BinaryBigEndian,
+        ]
+        .into_iter()
+        .for_each(|encoding| {
+            let mut data = Vec::new();
+            write(&mut data, &written, encoding).unwrap();
+            assert_same(&read(data.as_slice()).unwrap(), &written);
+        });
+    }
+
+    #[test]
+    fn skips_other_elements() {
+        let content = "\
+ply
+format ascii 1.0
+comment exported by CloudCompare
+element face 1
+property list uchar int vertex_indices
+element vertex 2
+property double x
+property double y
+property double z
+property uchar red
+end_header
+3 0 1 1
+0.5 1 2 255
+3 4 5 0
+";
+        let cloud = read::<World>(content.as_bytes()).unwrap();
+        assert_eq!(cloud.len(), 2);
+        assert_eq!(cloud.points[1].z, 5.0);
+        assert!(cloud.intensities.is_none());
+
+        let mut binary = b"ply\nformat binary_little_endian 1.0\nelement face 1\n\
+property list uchar int vertex_indices\nelement vertex 1\nproperty float x\n\
+property float y\nproperty float z\nend_header\n"
+            .to_vec();
+        binary.push(2);
+        [0i32, 1]
+            .iter()
+            .for_each(|index| binary.extend(index.to_le_bytes()));
+        [1.0f32, 2.0, 3.0]
+            .iter()
+            .for_each(|value| binary.extend(value.to_le_bytes()));
+        let cloud = read::<World>(binary.as_slice()).unwrap();
+        assert_eq!(cloud.points[0].y, 2.0);
+    }
+}
@@ -1,4 +1,359 @@
+//! The configuration of the odometry, loaded from TOML or YAML files.
+//!
+//! A file may start from a [`Preset`] with the `preset` key, the other keys override its values,
+//! and the missing keys fall back to the defaults of FAST-LIVO2.
+//! The parameter files of the C++ FAST-LIVO2 are converted by [`fast_livo2::import`].
+pub mod fast_livo2;
+
+use std::{ffi::OsStr, fs, path::Path, str::FromStr};
+
+use nalgebra::{IsometryMatrix3, Matrix3, Rotation3, Vector3};
+use serde::{Deserialize, Deserializer, de::Error};
+use toml::{Table, Value};
+
+use crate::{esikf, imu, preprocess, vio, voxel_map};
+
+/// The configuration of [`crate::odometry::Odometry`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default)]
+    pub esikf: esikf::Config,
+    #[serde(default)]
+    pub imu: imu::Config,
+    #[serde(default)]
+    pub voxel_map: voxel_map::Config,
+    #[serde(default)]
+    pub preprocess: preprocess::Config,
+    /// the visual update is disabled if `None`, and the images are dropped
+    #[serde(default)]
+    pub vio: Option<vio::Config>,
+    /// the maximum time difference between an image and the scan end to be processed together, in s
+    #[serde(default = "default_image_tolerance")]
+    pub image_tolerance: f64,
+}
+
+fn default_image_tolerance() -> f64 {
+    0.01
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            esikf: esikf::Config::default(),
+            imu: imu::Config::default(),
+            voxel_map: voxel_map::Config::default(),
+            preprocess: preprocess::Config::default(),
+            vio: None,
+            image_tolerance: default_image_tolerance(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read the config file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("unsupported config file {}, expected .toml, .yaml or .yml", .0.display())]
+    UnsupportedFormat(std::path::PathBuf),
+    #[error("invalid toml: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("invalid yaml: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid `{key}`: {message}")]
+    Invalid { key: String, message: String },
+}
+
+/// The lidar presets of FAST-LIVO2, the camera is specific to the rig and not included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Livox Avia, see FAST-LIVO2's `avia.yaml`
+    Avia,
+    /// Livox Mid-360, see FAST-LIVO2's `mid360.yaml`
+    Mid360,
+}
+
+impl Preset {
+    fn table(self) -> Table {
+        let content = match self {
+            Self::Avia => include_str!("../config/avia.toml"),
+            Self::Mid360 => include_str!("../config/mid360.toml"),
+        };
+        content.parse().expect("the presets should be valid toml")
+    }
+}
+
+impl FromStr for Preset {
+    type Err = ConfigError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "avia" => Ok(Self::Avia),
+            "mid360" => Ok(Self::Mid360),
+            _ => Err(ConfigError::Invalid {
+                key: "preset".into(),
+                message: format!("unknown preset {name:?}, expected \"avia\" or \"mid360\""),
+            }),
+        }
+    }
+}
+
+impl Config {
+    pub fn preset(preset: Preset) -> Self {
+        Self::from_table(preset.table()).expect("the presets should be valid")
+    }
+
+    /// Load the config file, the format is chosen by the extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        match path.extension().and_then(OsStr::to_str) {
+            Some("toml") => Self::from_toml_str(&fs::read_to_string(path)?),
+            Some("yaml" | "yml") => Self::from_yaml_str(&fs::read_to_string(path)?),
+            _ => Err(ConfigError::UnsupportedFormat(path.to_owned())),
+        }
+    }
+
+    pub fn from_toml_str(content: &str) -> Result<Self, ConfigError> {
+        Self::from_table(content.parse()?)
+    }
+
+    pub fn from_yaml_str(content: &str) -> Result<Self, ConfigError> {
+        Self::from_table(serde_yaml::from_str(content)?)
+    }
+
+    fn from_table(mut table: Table) -> Result<Self, ConfigError> {
+        let mut merged = match table.remove("preset") {
+            None => Table::new(),
+            Some(Value::String(name)) => name.parse::<Preset>()?.table(),
+            Some(_) => {
+                return Err(ConfigError::Invalid {
+                    key: "preset".into(),
+                    message: "should be a string".into(),
+                });
+            }
+        };
+        merge(&mut merged, table);
 
+        let config: Self =
+            serde_path_to_error::deserialize(Value::Table(merged)).map_err(|error| {
+                ConfigError::Invalid {
+                    key: error.path().to_string(),
+                    message: error.into_inner().to_string(),
+                }
+            })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the values that can not be rejected by their types.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.esikf.validate("esikf")?;
+        self.imu.validate("imu")?;
+        self.voxel_map.validate("voxel_map")?;
+        self.preprocess.validate("preprocess")?;
+        if let Some(vio) = &self.vio {
+            vio.validate("vio")?;
+        }
+        ensure(
+            self.image_tolerance >= 0.0,
+            "",
+            "image_tolerance",
+            "should not be negative",
+        )
+    }
+}
+
+/// Override the values of `base` with `overrides`, the nested tables are merged recursively.
+fn merge(base: &mut Table, overrides: Table) {
+    overrides
+        .into_iter()
+        .for_each(|(key, value)| match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overrides)) => merge(base, overrides),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        });
+}
+
+/// Fails with an error naming `section.field` if the value is not `valid`.
+pub(crate) fn ensure(
+    valid: bool,
+    section: &str,
+    field: &str,
+    message: &str,
+) -> Result<(), ConfigError> {
+    if valid {
+        return Ok(());
+    }
+    let key = if section.is_empty() {
+        field.to_owned()
+    } else {
+        format!("{section}.{field}")
+    };
+    Err(ConfigError::Invalid {
+        key,
+        message: message.to_owned(),
+    })
+}
+
+/// An isometry in the config files, the rotation matrix is in row-major order.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IsometryConfig {
+    #[serde(default = "identity_rotation")]
+    rotation: [f64; 9],
+    #[serde(default)]
+    translation: [f64; 3],
+}
+
+fn identity_rotation() -> [f64; 9] {
+    [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]
+}
+
+/// Deserialize an isometry from its `rotation` and `translation`, the rotation should be orthonormal.
+pub(crate) fn deserialize_isometry<'de, D>(
+    deserializer: D,
+) -> Result<IsometryMatrix3<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let IsometryConfig {
+        rotation,
+        translation,
+    } = IsometryConfig::deserialize(deserializer)?;
+
+    isometry(&rotation, &translation).map_err(D::Error::custom)
 }
 
+/// The isometry of the row-major rotation matrix and the translation,
+/// fails if the rotation is not orthonormal.
+pub(crate) fn isometry(
+    rotation: &[f64; 9],
+    translation: &[f64; 3],
+) -> Result<IsometryMatrix3<f64>, &'static str> {
+    let rotation = Matrix3::from_row_slice(rotation);
+    let orthonormal = (rotation.transpose() * rotation - Matrix3::identity()).amax() < 1e-3
+        && rotation.determinant() > 0.0;
+    if !orthonormal {
+        return Err("the rotation should be orthonormal");
+    }
+    Ok(IsometryMatrix3::from_parts(
+        Vector3::from(*translation).into(),
+        Rotation3::from_matrix(&rotation),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_key(result: Result<Config, ConfigError>) -> String {
+        match result {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            Err(error) => panic!("unexpected error: {error}"),
+            Ok(_) => panic!("the config should be invalid"),
+        }
+    }
+
+    #[test]
+    fn presets() {
+        let avia = Config::preset(Preset::Avia);
+        assert_eq!(
+            avia.imu.body_to_imu.translation.vector,
+            Vector3::new(0.04165, 0.02326, -0.0284)
+        );
+        assert!(avia.vio.is_none());
+
+        let mid360 = Config::preset(Preset::Mid360);
+        assert_eq!(
+            mid360.imu.body_to_imu.translation.vector,
+            Vector3::new(-0.011, -0.02329, 0.04412)
+        );
+        assert_eq!(mid360.preprocess.lidar, Some(preprocess::Lidar::Mid360));
+        assert_eq!(mid360.preprocess.scan_lines, 4);
+    }
+
+    #[test]
+    fn toml_overrides_preset() {
+        let config = Config::from_toml_str(
+            r#"
+            preset = "mid360"
+
+            [voxel_map]
+            voxel_size = 0.4
+
+            [imu.body_to_imu]
+            rotation = [0, -1, 0, 1, 0, 0, 0, 0, 1]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.voxel_map.voxel_size, 0.4);
+        assert_eq!(config.voxel_map.max_points_num, 50);
+        assert!(
+            (config.imu.body_to_imu.rotation.angle() - std::f64::consts::FRAC_PI_2).abs() < 1e-9
+        );
+        // the translation is kept from the preset
+        assert_eq!(
+            config.imu.body_to_imu.translation.vector,
+            Vector3::new(-0.011, -0.02329, 0.04412)
+        );
+    }
+
+    #[test]
+    fn yaml_with_camera() {
+        let config = Config::from_yaml_str(
+            r#"
+            imu:
+              gyro_noise: 0.1
+            vio:
+              camera:
+                model: pinhole
+                width: 640
+                height: 512
+                fx: 600
+                fy: 600
+                cx: 320
+                cy: 256
+                distortion: { k1: -0.1 }
+              time_offset_estimation: true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.imu.gyro_noise, 0.1);
+        assert_eq!(config.imu.acc_noise, imu::Config::default().acc_noise);
+        let vio = config.vio.unwrap();
+        assert_eq!(vio.camera.width(), 640);
+        assert!(vio.time_offset_estimation);
+        assert_eq!(vio.patch_half_size, 4);
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        assert_eq!(
+            invalid_key(Config::from_toml_str("[imu]\ngyro_noise = \"high\"")),
+            "imu.gyro_noise"
+        );
+        assert_eq!(
+            invalid_key(Config::from_toml_str("[voxel_map]\nvoxel_size = -0.5")),
+            "voxel_map.voxel_size"
+        );
+        assert_eq!(
+            invalid_key(Config::from_toml_str("[esikf]\nmax_iteration = 3")),
+            "esikf.max_iteration"
+        );
+        assert_eq!(
+            invalid_key(Config::from_toml_str(
+                "[imu.body_to_imu]\nrotation = [1, 0, 0, 0, 1, 0, 0, 0, 2]"
+            )),
+            "imu.body_to_imu"
+        );
+        assert_eq!(
+            invalid_key(Config::from_yaml_str("vio: { camera: { model: fisheye } }")),
+            "vio.camera.model"
+        );
+        assert_eq!(
+            invalid_key(Config::from_toml_str("preset = \"velodyne\"")),
+            "preset"
+        );
+    }
+}
@@ -0,0 +1,728 @@
+//! Import of the ROS parameter files of the C++ FAST-LIVO2, and the `mapping` section of FAST-LIO.
+//!
+//! The camera intrinsics of FAST-LIVO2 are in a separate vikit camera file,
+//! the visual update is only enabled if it is given.
+use std::{collections::HashMap, fs, path::Path};
+
+use nalgebra::IsometryMatrix3;
+use serde_yaml::{Mapping, Value};
+
+use super::{Config, ConfigError, isometry};
+use crate::{
+    camera::{CameraConfig, EquidistantCamera, PinholeCamera, RadialTangential},
+    preprocess::Lidar,
+    vio,
+};
+
+/// The default `lio.max_layer` of FAST-LIVO2.
+const DEFAULT_MAX_LAYER: usize = 2;
+
+/// The converted config, with the warnings about the keys that are not supported.
+pub struct Import {
+    pub config: Config,
+    pub warnings: Vec<String>,
+}
+
+/// Convert the parameter file and the optional camera file, the warnings are also logged.
+pub fn import(parameters: &str, camera: Option<&str>) -> Result<Import, ConfigError> {
+    let mut importer = Importer::default();
+    importer.vio = camera
+        .map(|camera| importer.camera(camera))
+        .transpose()?
+        .map(|camera| vio::Config::new(camera.into_model()));
+
+    let parameters = ros2_parameters(serde_yaml::from_str(parameters)?);
+    parameters.into_iter().try_for_each(|(section, values)| {
+        let section = name(&section, "")?;
+        let Value::Mapping(values) = values else {
+            importer.warn(&section, "is not a section, ignored");
+            return Ok(());
+        };
+        if !SECTIONS.contains(&section.as_str()) {
+            importer.warn(&section, "is not supported, the section is ignored");
+            return Ok(());
+        }
+        values.into_iter().try_for_each(|(key, value)| {
+            let key = name(&key, &section)?;
+            importer.apply(&section, &key, &value)
+        })
+    })?;
+    importer.finish()
+}
+
+/// Like [`import`], but reads the files.
+pub fn import_files(parameters: &Path, camera: Option<&Path>) -> Result<Import, ConfigError> {
+    let camera = camera.map(fs::read_to_string).transpose()?;
+    import(&fs::read_to_string(parameters)?, camera.as_deref())
+}
+
+/// The keys of the converted config that are validated, by the FAST-LIVO2 keys that set them.
+const VALIDATED_KEYS: [(&str, &str); 21] = [
+    ("time_offset.img_time_offset", "vio.time_offset"),
+    ("vio.outlier_threshold", "vio.outlier_threshold"),
+    ("vio.img_point_cov", "vio.photometric_noise"),
+    ("vio.patch_size", "vio.patch_half_size"),
+    ("vio.inv_expo_cov", "vio.exposure_prior_variance"),
+    ("imu.acc_cov", "imu.acc_noise"),
+    ("imu.gyr_cov", "imu.gyro_noise"),
+    ("imu.b_acc_cov", "imu.bias_acc_noise"),
+    ("imu.b_gyr_cov", "imu.bias_gyro_noise"),
+    ("mapping.acc_cov", "imu.acc_noise"),
+    ("mapping.gyr_cov", "imu.gyro_noise"),
+    ("mapping.b_acc_cov", "imu.bias_acc_noise"),
+    ("mapping.b_gyr_cov", "imu.bias_gyro_noise"),
+    ("lio.max_iterations", "esikf.max_iterations"),
+    ("lio.dept_err", "voxel_map.beam_err"),
+    ("lio.beam_err", "voxel_map.dept_err"),
+    ("lio.min_eigen_value", "voxel_map.planer_threshold"),
+    ("lio.voxel_size", "voxel_map.voxel_size"),
+    ("lio.max_points_num", "voxel_map.max_points_num"),
+    ("preprocess.scan_line", "preprocess.scan_lines"),
+    ("preprocess.blind", "preprocess.blind"),
+];
+
+const SECTIONS: [&str; 9] = [
+    "common",
+    "extrin_calib",
+    "time_offset",
+    "preprocess",
+    "vio",
+    "imu",
+    "lio",
+    "local_map",
+    "mapping",
+];
+
+/// The parameters of a ROS 2 file are nested in `<node>.ros__parameters`.
+fn ros2_parameters(document: Mapping) -> Mapping {
+    let nested = match document.iter().next() {
+        Some((_, Value::Mapping(node))) if document.len() == 1 => node.get("ros__parameters"),
+        _ => None,
+    };
+    match nested {
+        Some(Value::Mapping(parameters)) => parameters.clone(),
+        _ => document,
+    }
+}
+
+fn name(key: &Value, section: &str) -> Result<String, ConfigError> {
+    key.as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| ConfigError::Invalid {
+            key: section.to_owned(),
+            message: format!("the key {key:?} should be a string"),
+        })
+}
+
+fn invalid(key: &str, message: &str) -> ConfigError {
+    ConfigError::Invalid {
+        key: key.to_owned(),
+        message: message.to_owned(),
+    }
+}
+
+fn float(key: &str, value: &Value) -> Result<f64, ConfigError> {
+    value
+        .as_f64()
+        .ok_or_else(|| invalid(key, "should be a number"))
+}
+
+fn unsigned(key: &str, value: &Value) -> Result<usize, ConfigError> {
+    value
+        .as_u64()
+        .map(|value| value as usize)
+        .ok_or_else(|| invalid(key, "should be a non-negative integer"))
+}
+
+/// FAST-LIVO2 uses both `true`/`false` and `1`/`0` for the switches.
+fn boolean(key: &str, value: &Value) -> Result<bool, ConfigError> {
+    value
+        .as_bool()
+        .or_else(|| value.as_u64().map(|value| value != 0))
+        .ok_or_else(|| invalid(key, "should be a boolean"))
+}
+
+fn floats<const N: usize>(key: &str, value: &Value) -> Result<[f64; N], ConfigError> {
+    let message = format!("should be a sequence of {N} numbers");
+    let values = value
+        .as_sequence()
+        .filter(|values| values.len() == N)
+        .ok_or_else(|| invalid(key, &message))?;
+    let mut array = [0.0; N];
+    array
+        .iter_mut()
+        .zip(values)
+        .try_for_each(|(element, value)| {
+            *element = value.as_f64().ok_or_else(|| invalid(key, &message))?;
+            Ok::<_, ConfigError>(())
+        })?;
+    Ok(array)
+}
+
+/// A rotation and a translation given by separate keys.
+#[derive(Default)]
+struct Extrinsic {
+    rotation: Option<[f64; 9]>,
+    translation: Option<[f64; 3]>,
+}
+
+impl Extrinsic {
+    fn is_given(&self) -> bool {
+        self.rotation.is_some() || self.translation.is_some()
+    }
+
+    fn isometry(&self, key: &str) -> Result<IsometryMatrix3<f64>, ConfigError> {
+        let rotation = self
+            .rotation
+            .unwrap_or([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        isometry(&rotation, &self.translation.unwrap_or_default())
+            .map_err(|message| invalid(key, message))
+    }
+}
+
+#[derive(Default)]
+struct Importer {
+    config: Config,
+    vio: Option<vio::Config>,
+    image_enabled: Option<bool>,
+    lidar_to_imu: Extrinsic,
+    lidar_to_camera: Extrinsic,
+    max_layer: Option<usize>,
+    layer_init_num: Option<Vec<usize>>,
+    vio_max_iterations: Option<u32>,
+    /// the FAST-LIVO2 key that last set each validated key
+    sources: HashMap<&'static str, String>,
+    warnings: Vec<String>,
+}
+
+impl Importer {
+    fn warn(&mut self, key: &str, message: &str) {
+        let warning = format!("`{key}` {message}");
+        log::warn!("{warning}");
+        self.warnings.push(warning);
+    }
+
+    fn unsupported(&mut self, key: &str) {
+        self.warn(key, "is not supported, ignored");
+    }
+
+    fn apply(&mut self, section: &str, key: &str, value: &Value) -> Result<(), ConfigError> {
+        let path = format!("{section}.{key}");
+        let path = path.as_str();
+        if let Some((_, field)) = VALIDATED_KEYS.iter().find(|(source, _)| *source == path) {
+            self.sources.insert(field, path.to_owned());
+        }
+        let imu = &mut self.config.imu;
+        let voxel_map = &mut self.config.voxel_map;
+        let preprocess = &mut self.config.preprocess;
+
+        match (section, key) {
+            ("common", "img_topic" | "lid_topic" | "imu_topic") => {}
+            ("common", "img_en") => self.image_enabled = Some(boolean(path, value)?),
+            ("common", "lidar_en") => {
+                if !boolean(path, value)? {
+                    self.warn(path, "is disabled, but the lidar is required, ignored");
+                }
+            }
+            ("extrin_calib" | "mapping", "extrinsic_T") => {
+                self.lidar_to_imu.translation = Some(floats(path, value)?);
+            }
+            ("extrin_calib" | "mapping", "extrinsic_R") => {
+                self.lidar_to_imu.rotation = Some(floats(path, value)?);
+            }
+            ("extrin_calib", "Pcl") => {
+                self.lidar_to_camera.translation = Some(floats(path, value)?)
+            }
+            ("extrin_calib", "Rcl") => self.lidar_to_camera.rotation = Some(floats(path, value)?),
+            ("time_offset", "img_time_offset") => {
+                let time_offset = float(path, value)?;
+                if let Some(vio) = &mut self.vio {
+                    vio.time_offset = time_offset;
+                }
+            }
+            ("time_offset", "imu_time_offset" | "lidar_time_offset" | "exposure_time_init") => {
+                if float(path, value)? != 0.0 {
+                    self.unsupported(path);
+                }
+            }
+            ("vio", "max_iterations") => {
+                self.vio_max_iterations = Some(unsigned(path, value)? as u32);
+            }
+            ("vio", "outlier_threshold") => {
+                let outlier_threshold = float(path, value)?;
+                if let Some(vio) = &mut self.vio {
+                    vio.outlier_threshold = outlier_threshold;
+                }
+            }
+            ("vio", "img_point_cov") => {
+                let photometric_noise = float(path, value)?;
+                if let Some(vio) = &mut self.vio {
+                    vio.photometric_noise = photometric_noise;
+                }
+            }
+            ("vio", "patch_size") => {
+                let patch_size = unsigned(path, value)?;
+                if patch_size % 2 == 1 {
+                    self.warn(path, "is odd, the patch is one pixel larger");
+                }
+                if let Some(vio) = &mut self.vio {
+                    vio.patch_half_size = patch_size.div_ceil(2);
+                }
+            }
+            ("vio", "exposure_estimate_en") => {
+                let exposure_estimation = boolean(path, value)?;
+                if let Some(vio) = &mut self.vio {
+                    vio.exposure_estimation = exposure_estimation;
+                }
+            }
+            ("vio", "inv_expo_cov") => {
+                let exposure_prior_variance = float(path, value)?;
+                if let Some(vio) = &mut self.vio {
+                    vio.exposure_prior_variance = exposure_prior_variance;
+                }
+            }
+            ("vio", "normal_en") => {
+                if !boolean(path, value)? {
+                    self.warn(path, "is disabled, but the plane normals are always used");
+                }
+            }
+            ("vio", "raycast_en" | "inverse_composition_en") => {
+                if boolean(path, value)? {
+                    self.unsupported(path);
+                }
+            }
+            ("imu", "imu_en") => {
+                if !boolean(path, value)? {
+                    self.warn(path, "is disabled, but the imu is required, ignored");
+                }
+            }
+            ("imu" | "mapping", "acc_cov") => imu.acc_noise = float(path, value)?,
+            ("imu" | "mapping", "gyr_cov") => imu.gyro_noise = float(path, value)?,
+            ("imu" | "mapping", "b_acc_cov") => imu.bias_acc_noise = float(path, value)?,
+            ("imu" | "mapping", "b_gyr_cov") => imu.bias_gyro_noise = float(path, value)?,
+            ("mapping", "extrinsic_est_en") => imu.extrinsic_estimation = boolean(path, value)?,
+            ("lio", "max_iterations") => {
+                self.config.esikf.max_iterations = unsigned(path, value)? as u32;
+            }
+            // the ranging error is named `dept_err` and the bearing error `beam_err` in FAST-LIVO2
+            ("lio", "dept_err") => voxel_map.beam_err = float(path, value)?,
+            ("lio", "beam_err") => voxel_map.dept_err = float(path, value)?,
+            ("lio", "min_eigen_value") => voxel_map.planer_threshold = float(path, value)?,
+            ("lio", "voxel_size") => voxel_map.voxel_size = float(path, value)?,
+            ("lio", "max_points_num") => voxel_map.max_points_num = unsigned(path, value)?,
+            ("lio", "max_layer") => self.max_layer = Some(unsigned(path, value)?),
+            ("lio", "layer_init_num") => {
+                let layer_init_num = value
+                    .as_sequence()
+                    .and_then(|values| values.iter().map(|value| value.as_u64()).collect())
+                    .ok_or_else(|| invalid(path, "should be a sequence of integers"))?;
+                self.layer_init_num = Some(
+                    Vec::<u64>::into_iter(layer_init_num)
+                        .map(|value| value as usize)
+                        .collect(),
+                );
+            }
+            ("preprocess", "lidar_type") => {
+                // the lidar types of FAST-LIVO2, the Mid-360 is an Avia with 4 lines
+                let lidar = match unsigned(path, value)? {
+                    1 => Some(Lidar::Avia),
+                    2 => Some(Lidar::Velodyne),
+                    3 => Some(Lidar::Ouster),
+                    5 => Some(Lidar::Hesai),
+                    _ => None,
+                };
+                if lidar.is_none() {
+                    self.unsupported(path);
+                }
+                self.config.preprocess.lidar = lidar;
+            }
+            ("preprocess", "scan_line") => preprocess.scan_lines = unsigned(path, value)? as u16,
+            ("preprocess", "point_filter_num") => {
+                preprocess.point_filter_num = unsigned(path, value)?;
+            }
+            ("preprocess", "blind") => preprocess.blind = float(path, value)?,
+            ("preprocess", "filter_size_surf") => preprocess.downsample_size = float(path, value)?,
+            ("local_map", "map_sliding_en") => {
+                if boolean(path, value)? {
+                    self.warn(path, "is enabled, but the map is not slid, ignored");
+                }
+            }
+            ("local_map", "half_map_size" | "sliding_thresh") => {}
+            _ => self.unsupported(path),
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Import, ConfigError> {
+        if self.lidar_to_imu.is_given() {
+            self.config.imu.body_to_imu = self.lidar_to_imu.isometry("extrin_calib.extrinsic_R")?;
+        }
+
+        let max_layer = self.max_layer.unwrap_or(DEFAULT_MAX_LAYER);
+        if self.max_layer.is_some() || self.layer_init_num.is_some() {
+            let default_threshold = self.config.voxel_map.layer_init_threshold[0];
+            let layer_init_num = self
+                .layer_init_num
+                .take()
+                .unwrap_or_else(|| vec![default_threshold; max_layer + 1]);
+            let layer_init_threshold = layer_init_num.get(..=max_layer).ok_or_else(|| {
+                invalid("lio.layer_init_num", "should have `max_layer + 1` values")
+            })?;
+            self.config.voxel_map.layer_init_threshold = layer_init_threshold.to_vec().into();
+        }
+
+        if let Some(vio_max_iterations) = self.vio_max_iterations
+            && vio_max_iterations != self.config.esikf.max_iterations
+        {
+            self.warn(
+                "vio.max_iterations",
+                "differs from `lio.max_iterations`, which is used by both updates",
+            );
+        }
+
+        match (self.image_enabled, self.vio.is_some()) {
+            (Some(false), _) => self.vio = None,
+            (Some(true), false) => self.warn(
+                "common.img_en",
+                "is enabled, but the camera file is not given, the visual update is disabled",
+            ),
+            _ => {}
+        }
+        if self.vio.is_some() {
+            if !self.lidar_to_camera.is_given() {
+                self.warn(
+                    "extrin_calib.Rcl",
+                    "is not given, the camera-imu extrinsic is identity",
+                );
+            }
+            let lidar_to_camera = self.lidar_to_camera.isometry("extrin_calib.Rcl")?;
+            let body_to_imu = self.config.imu.body_to_imu;
+            if let Some(vio) = &mut self.vio {
+                vio.camera_to_imu = body_to_imu * lidar_to_camera.inverse();
+            }
+        }
+
+        self.config.vio = self.vio;
+        // the errors name the keys of the parameter file rather than the converted ones
+        let sources = self.sources;
+        self.config.validate().map_err(|error| match error {
+            ConfigError::Invalid { key, message } => ConfigError::Invalid {
+                key: sources.get(key.as_str()).cloned().unwrap_or(key),
+                message,
+            },
+            error => error,
+        })?;
+        Ok(Import {
+            config: self.config,
+            warnings: self.warnings,
+        })
+    }
+
+    /// Convert the vikit camera file of FAST-LIVO2, the intrinsics are multiplied by its `scale`.
+    fn camera(&mut self, content: &str) -> Result<CameraConfig, ConfigError> {
+        let parameters = ros2_parameters(serde_yaml::from_str(content)?);
+        let get = |key: &str| parameters.get(key);
+        let required = |key: &str| {
+            let path = format!("camera.{key}");
+            get(key)
+                .ok_or_else(|| invalid(&path, "is missing"))
+                .and_then(|value| float(&path, value))
+        };
+        let optional =
+            |key: &str| get(key).map_or(Ok(0.0), |value| float(&format!("camera.{key}"), value));
+
+        let scale = get("scale").map_or(Ok(1.0), |value| float("camera.scale", value))?;
+        let size = |key| Ok::<_, ConfigError>((required(key)? * scale).round() as usize);
+        let (width, height) = (size("cam_width")?, size("cam_height")?);
+        let (fx, fy) = (required("cam_fx")? * scale, required("cam_fy")? * scale);
+        let (cx, cy) = (required("cam_cx")? * scale, required("cam_cy")? * scale);
+        let [d0, d1, d2, d3, d4] = ["cam_d0", "cam_d1", "cam_d2", "cam_d3", "cam_d4"]
+            .map(optional)
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .try_into()
+            .expect("there are 5 distortion coefficients");
+
+        let model = get("cam_model")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("camera.cam_model", "should be a string"))?;
+        let camera = match model {
+            "Pinhole" => CameraConfig::Pinhole(PinholeCamera {
+                width,
+                height,
+                fx,
+                fy,
+                cx,
+                cy,
+                distortion: RadialTangential {
+                    k1: d0,
+                    k2: d1,
+                    p1: d2,
+                    p2: d3,
+                    k3: d4,
+                },
+            }),
+            "EquidistantCamera" => CameraConfig::Equidistant(EquidistantCamera {
+                width,
+                height,
+                fx,
+                fy,
+                cx,
+                cy,
+                k1: d0,
+                k2: d1,
+                k3: d2,
+                k4: d3,
+            }),
+            _ => {
+                return Err(invalid(
+                    "camera.cam_model",
+                    &format!("{model:?} is not supported, expected Pinhole or EquidistantCamera"),
+                ));
+            }
+        };
+        camera
+            .validate()
+            .map_err(|message| invalid("camera", message))?;
+
+        if scale != 1.0 {
+            self.warn(
+                "camera.scale",
+                "is applied to the intrinsics, the images should be resized by it",
+            );
+        }
+        parameters
+            .keys()
+            .filter_map(Value::as_str)
+            .filter(|key| {
+                !matches!(
+                    *key,
+                    "cam_model"
+                        | "scale"
+                        | "cam_width"
+                        | "cam_height"
+                        | "cam_fx"
+                        | "cam_fy"
+                        | "cam_cx"
+                        | "cam_cy"
+                        | "cam_d0"
+                        | "cam_d1"
+                        | "cam_d2"
+                        | "cam_d3"
+                        | "cam_d4"
+                )
+            })
+            .map(|key| format!("camera.{key}"))
+            .collect::<Vec<_>>()
+            .iter()
+            .for_each(|key| self.unsupported(key));
+        Ok(camera)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector3};
+
+    use super::*;
+
+    const AVIA: &str = r#"
+common:
+  img_topic: "/left_camera/image"
+  lid_topic: "/livox/lidar"
+  imu_topic: "/livox/imu"
+  img_en: 1
+  lidar_en: 1
+  ros_driver_bug_fix: false
+
+extrin_calib:
+  extrinsic_T: [0.04165, 0.02326, -0.0284]
+  extrinsic_R: [1, 0, 0, 0, 1, 0, 0, 0, 1]
+  Rcl: [0.00610193, -0.999863, -0.0154172,
+        -0.00615449, 0.0153796, -0.999863,
+        0.999962, 0.00619598, -0.0060598]
+  Pcl: [0.0194384, 0.104689, -0.0251952]
+
+time_offset:
+  imu_time_offset: 0.0
+  img_time_offset: 0.1
+  exposure_time_init: 0.0
+
+preprocess:
+  point_filter_num: 1
+  filter_size_surf: 0.1
+  lidar_type: 1
+  scan_line: 6
+  blind: 0.8
+
+vio:
+  max_iterations: 5
+  outlier_threshold: 1000
+  img_point_cov: 100
+  patch_size: 8
+  patch_pyrimid_level: 4
+  normal_en: true
+  raycast_en: false
+  inverse_composition_en: false
+  exposure_estimate_en: true
+  inv_expo_cov: 0.1
+
+imu:
+  imu_en: true
+  imu_int_frame: 30
+  acc_cov: 0.5
+  gyr_cov: 0.3
+  b_acc_cov: 0.0001
+  b_gyr_cov: 0.0001
+
+lio:
+  max_iterations: 5
+  dept_err: 0.02
+  beam_err: 0.05
+  min_eigen_value: 0.0025
+  voxel_size: 0.5
+  max_layer: 2
+  max_points_num: 50
+  layer_init_num: [5, 5, 5, 5, 5]
+
+local_map:
+  map_sliding_en: false
+  half_map_size: 100
+  sliding_thresh: 8
+
+publish:
+  dense_map_en: true
+"#;
+
+    const CAMERA: &str = r#"
+cam_model: Pinhole
+cam_width: 1280
+cam_height: 1024
+scale: 0.5
+cam_fx: 1293.56944
+cam_fy: 1293.3155
+cam_cx: 626.91359
+cam_cy: 522.799224
+cam_d0: -0.076160
+cam_d1: 0.123001
+cam_d2: -0.00113
+cam_d3: 0.000251
+"#;
+
+    #[test]
+    fn avia_with_camera() {
+        let Import { config, warnings } = import(AVIA, Some(CAMERA)).unwrap();
+
+        assert_eq!(
+            config.imu.body_to_imu.translation.vector,
+            Vector3::new(0.04165, 0.02326, -0.0284)
+        );
+        assert_eq!(config.imu.gyro_noise, 0.3);
+        assert_eq!(config.voxel_map.beam_err, 0.02);
+        assert_eq!(config.voxel_map.dept_err, 0.05);
+        assert_eq!(&*config.voxel_map.layer_init_threshold, &[5, 5, 5]);
+        assert_eq!(config.preprocess.lidar, Some(Lidar::Avia));
+        assert_eq!(config.preprocess.blind, 0.8);
+        assert_eq!(config.preprocess.downsample_size, 0.1);
+
+        let vio = config.vio.expect("the visual update should be enabled");
+        assert_eq!(vio.camera.width(), 640);
+        assert_eq!(vio.patch_half_size, 4);
+        assert_eq!(vio.time_offset, 0.1);
+
+        // a lidar point is mapped to the same camera point through the imu
+        let lidar_point = Point3::new(5.0, 0.3, -0.2);
+        let lidar_to_camera = isometry(
+            &[
+                0.00610193,
+                -0.999863,
+                -0.0154172,
+                -0.00615449,
+                0.0153796,
+                -0.999863,
+                0.999962,
+                0.00619598,
+                -0.0060598,
+            ],
+            &[0.0194384, 0.104689, -0.0251952],
+        )
+        .unwrap();
+        let imu_point = config.imu.body_to_imu * lidar_point;
+        let camera_point = vio.camera_to_imu.inverse() * imu_point;
+        assert!((camera_point - lidar_to_camera * lidar_point).norm() < 1e-9);
+
+        [
+            "`common.ros_driver_bug_fix`",
+            "`vio.patch_pyrimid_level`",
+            "`imu.imu_int_frame`",
+            "`publish`",
+            "`camera.scale`",
+        ]
+        .into_iter()
+        .for_each(|key| {
+            assert!(
+                warnings.iter().any(|warning| warning.starts_with(key)),
+                "no warning about {key} in {warnings:?}"
+            )
+        });
+    }
+
+    #[test]
+    fn without_camera() {
+        let Import { config, warnings } = import(AVIA, None).unwrap();
+        assert!(config.vio.is_none());
+        assert!(
+            warnings
+                .iter()
+                .any(|warning| warning.starts_with("`common.img_en`"))
+        );
+    }
+
+    #[test]
+    fn ros2_fast_lio() {
+        let parameters = r#"
+/**:
+  ros__parameters:
+    mapping:
+      acc_cov: 0.1
+      extrinsic_est_en: true
+      extrinsic_T: [0.0, 0.0, 0.1]
+    lio:
+      max_layer: 1
+"#;
+        let Import { config, .. } = import(parameters, None).unwrap();
+        assert_eq!(config.imu.acc_noise, 0.1);
+        assert!(config.imu.extrinsic_estimation);
+        assert_eq!(config.imu.body_to_imu.translation.z, 0.1);
+        assert_eq!(&*config.voxel_map.layer_init_threshold, &[5, 5]);
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        let key = |parameters, camera| match import(parameters, camera) {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            Err(error) => panic!("unexpected error: {error}"),
+            Ok(_) => panic!("the parameters should be invalid"),
+        };
+        assert_eq!(key("lio: { voxel_size: big }", None), "lio.voxel_size");
+        assert_eq!(key("lio: { voxel_size: -1 }", None), "lio.voxel_size");
+        assert_eq!(key("lio: { dept_err: -1 }", None), "lio.dept_err");
+        assert_eq!(key("mapping: { acc_cov: -1 }", None), "mapping.acc_cov");
+        assert_eq!(
+            key("vio: { patch_size: 0 }", Some(CAMERA)),
+            "vio.patch_size"
+        );
+        assert_eq!(
+            key("extrin_calib: { extrinsic_T: [0, 0] }", None),
+            "extrin_calib.extrinsic_T"
+        );
+        assert_eq!(
+            key("lio: { max_layer: 4, layer_init_num: [5, 5] }", None),
+            "lio.layer_init_num"
+        );
+        assert_eq!(
+            key("{}", Some("cam_model: Ocam\ncam_width: 1")),
+            "camera.cam_height"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Offline datasets, read into the [`SensorMessage`](crate::odometry::SensorMessage)s of the odometry.
+pub mod kitti;
+pub mod mcap;
+pub mod newer_college;
+pub mod ntu_viral;
+
+pub(crate) mod csv;
+mod ros;
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cloud::CloudError,
+    measurement::{ImuSample, LidarScan},
+    odometry::SensorMessage,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DatasetError {
+    #[error("failed to read the dataset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid {format} file: {message}")]
+    Format {
+        format: &'static str,
+        message: String,
+    },
+    #[error("failed to decode a message of {topic}: {message}")]
+    Decode { topic: String, message: String },
+    #[error("failed to read a scan: {0}")]
+    Cloud(#[from] CloudError),
+}
+
+/// Loads the scan of a file, starting at the given timestamp.
+type ScanLoader = Box<dyn Fn(&Path, f64) -> Result<LidarScan, DatasetError>>;
+
+enum Event {
+    Imu(ImuSample),
+    Scan { timestamp: f64, path: PathBuf },
+}
+
+impl Event {
+    fn timestamp(&self) -> f64 {
+        match self {
+            Self::Imu(sample) => sample.timestamp,
+            Self::Scan { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
+/// The messages of a dataset stored as one file per scan, in time order.
+///
+/// The imu samples are held in memory, the scans are loaded when they are due.
+pub struct Messages {
+    events: std::vec::IntoIter<Event>,
+    load: ScanLoader,
+}
+
+impl Messages {
+    fn new(imu_samples: Vec<ImuSample>, scans: Vec<(f64, PathBuf)>, load: ScanLoader) -> Self {
+        let mut events: Vec<_> = imu_samples
+            .into_iter()
+            .map(Event::Imu)
+            .chain(
+                scans
+                    .into_iter()
+                    .map(|(timestamp, path)| Event::Scan { timestamp, path }),
+            )
+            .collect();
+        // stable, the imu samples stay before the scans of the same timestamp
+        events.sort_by(|a, b| a.timestamp().total_cmp(&b.timestamp()));
+        Self {
+            events: events.into_iter(),
+            load,
+        }
+    }
+}
+
+impl Iterator for Messages {
+    type Item = Result<SensorMessage, DatasetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(match self.events.next()? {
+            Event::Imu(sample) => Ok(SensorMessage::Imu(sample)),
+            Event::Scan { timestamp, path } => {
+                (self.load)(&path, timestamp).map(SensorMessage::Lidar)
+            }
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.events.size_hint()
+    }
+}
@@ -0,0 +1,298 @@
+//! The CSV files of the imu samples and the ground truths, their columns are found by name.
+//!
+//! The names are matched without case and without the leading `#` or `%` of the header,
+//! which covers the exports of `rostopic echo -p`, the EuRoC-like files and the plain ones.
+use std::io::BufRead;
+
+use nalgebra::{IsometryMatrix3, Quaternion, Translation3, UnitQuaternion, Vector3};
+
+use super::DatasetError;
+use crate::{
+    measurement::ImuSample,
+    trajectory::{StampedPose, Trajectory},
+};
+
+/// The timestamp columns in seconds or nanoseconds, by priority.
+const TIME: &[&str] = &[
+    "field.header.stamp",
+    "timestamp",
+    "timestamp [ns]",
+    "time",
+    "t",
+];
+const ANGULAR_VELOCITY: &[&str] = &[
+    "w{}",
+    "gyro_{}",
+    "angular_velocity.{}",
+    "field.angular_velocity.{}",
+    "w_rs_s_{} [rad s^-1]",
+];
+const LINEAR_ACCELERATION: &[&str] = &[
+    "a{}",
+    "acc_{}",
+    "linear_acceleration.{}",
+    "field.linear_acceleration.{}",
+    "a_rs_s_{} [m s^-2]",
+];
+const POSITION: &[&str] = &[
+    "{}",
+    "t{}",
+    "p{}",
+    "position.{}",
+    "field.pose.position.{}",
+    "field.pose.pose.position.{}",
+    "field.point.{}",
+    "p_rs_r_{} [m]",
+];
+const ORIENTATION: &[&str] = &[
+    "q{}",
+    "orientation.{}",
+    "field.pose.orientation.{}",
+    "field.pose.pose.orientation.{}",
+    "q_rs_{} []",
+];
+
+pub(crate) struct Csv {
+    format: &'static str,
+    columns: Vec<String>,
+    /// the line numbers and the values of the rows
+    rows: Vec<(usize, Vec<String>)>,
+}
+
+impl Csv {
+    /// Read the header and the rows, the empty lines are skipped.
+    pub(crate) fn read(reader: impl BufRead, format: &'static str) -> Result<Self, DatasetError> {
+        let mut lines = reader
+            .lines()
+            .enumerate()
+            .map(|(index, line)| line.map(|line| (index + 1, line)))
+            .filter(|line| !matches!(line, Ok((_, line)) if line.trim().is_empty()));
+        let (_, header) = lines
+            .next()
+            .transpose()?
+            .ok_or_else(|| DatasetError::Format {
+                format,
+                message: "the header is missing".into(),
+            })?;
+        let columns = split(&header)
+            .map(|name| name.trim_start_matches(['#', '%']).trim().to_lowercase())
+            .collect();
+        let rows = lines
+            .map(|line| {
+                line.map(|(number, line)| (number, split(&line).map(str::to_owned).collect()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            format,
+            columns,
+            rows,
+        })
+    }
+
+    /// The imu samples of the rows, the angular velocities in rad/s and the accelerations in m/s².
+    pub(super) fn imu_samples(&self) -> Result<Vec<ImuSample>, DatasetError> {
+        let times = self.timestamps()?;
+        let angular = self.required(ANGULAR_VELOCITY, ["x", "y", "z"])?;
+        let linear = self.required(LINEAR_ACCELERATION, ["x", "y", "z"])?;
+        self.rows
+            .iter()
+            .zip(times)
+            .map(|(row, timestamp)| {
+                Ok(ImuSample {
+                    timestamp,
+                    angular_velocity: Vector3::from(self.values(row, angular)?),
+                    linear_acceleration: Vector3::from(self.values(row, linear)?),
+                })
+            })
+            .collect()
+    }
+
+    /// The poses of the rows, the orientation is the identity if there is no quaternion
+    /// like the positions of a total station.
+    pub(crate) fn trajectory(&self) -> Result<Trajectory, DatasetError> {
+        let times = self.timestamps()?;
+        let position = self.required(POSITION, ["x", "y", "z"])?;
+        let orientation = self.columns(ORIENTATION, ["x", "y", "z", "w"]);
+        let poses = self
+            .rows
+            .iter()
+            .zip(times)
+            .map(|(row, timestamp)| {
+                let rotation = match orientation {
+                    Some(orientation) => {
+                        let [x, y, z, w] = self.values(row, orientation)?;
+                        UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z))
+                            .to_rotation_matrix()
+                    }
+                    None => Default::default(),
+                };
+                let translation = Translation3::from(self.values(row, position)?);
+                Ok(StampedPose::new(
+                    timestamp,
+                    IsometryMatrix3::from_parts(translation, rotation),
+                ))
+            })
+            .collect::<Result<_, DatasetError>>()?;
+        Ok(Trajectory::new(poses))
+    }
+
+    /// The timestamps of the rows in seconds, from the `sec` and `nsec` columns or from a single
+    /// column in seconds or in nanoseconds.
+    fn timestamps(&self) -> Result<Vec<f64>, DatasetError> {
+        let seconds = self.column(&["sec", "secs", "field.header.stamp.secs"]);
+        let nanoseconds = self.column(&["nsec", "nsecs", "field.header.stamp.nsecs"]);
+        if let (Some(seconds), Some(nanoseconds)) = (seconds, nanoseconds) {
+            return self
+                .rows
+                .iter()
+                .map(|row| {
+                    let [seconds, nanoseconds] = self.values(row, [seconds, nanoseconds])?;
+                    Ok(seconds + nanoseconds / 1e9)
+                })
+                .collect();
+        }
+        let column = self
+            .column(TIME)
+            .ok_or_else(|| self.invalid(0, "the timestamp column is missing"))?;
+        self.rows
+            .iter()
+            .map(|(number, values)| {
+                let token = values.get(column).map_or("", String::as_str);
+                match token.parse::<u64>() {
+                    // nanoseconds, split to keep their precision
+                    Ok(time) if time > 1_000_000_000_000 => {
+                        Ok((time / 1_000_000_000) as f64 + (time % 1_000_000_000) as f64 / 1e9)
+                    }
+                    _ => token
+                        .parse()
+                        .map_err(|_| self.invalid(*number, &format!("{token:?} is not a time"))),
+                }
+            })
+            .collect()
+    }
+
+    /// The first column named like one of the aliases, by priority.
+    fn column(&self, aliases: &[&str]) -> Option<usize> {
+        aliases
+            .iter()
+            .find_map(|alias| self.columns.iter().position(|column| column == alias))
+    }
+
+    /// The columns of the axes named by the first template having all of them,
+    /// `{}` in a template is replaced by the axis.
+    fn columns<const N: usize>(&self, templates: &[&str], axes: [&str; N]) -> Option<[usize; N]> {
+        templates.iter().find_map(|template| {
+            let columns = axes.map(|axis| self.column(&[&template.replace("{}", axis)]));
+            columns
+                .iter()
+                .all(Option::is_some)
+                .then(|| columns.map(Option::unwrap))
+        })
+    }
+
+    fn required<const N: usize>(
+        &self,
+        templates: &[&str],
+        axes: [&str; N],
+    ) -> Result<[usize; N], DatasetError> {
+        self.columns(templates, axes).ok_or_else(|| {
+            let names = axes.map(|axis| templates[0].replace("{}", axis));
+            self.invalid(0, &format!("the columns {} are missing", names.join(", ")))
+        })
+    }
+
+    fn values<const N: usize>(
+        &self,
+        (number, values): &(usize, Vec<String>),
+        columns: [usize; N],
+    ) -> Result<[f64; N], DatasetError> {
+        let mut parsed = [0.0; N];
+        parsed
+            .iter_mut()
+            .zip(columns)
+            .try_for_each(|(value, column)| {
+                let token = values.get(column).map_or("", String::as_str);
+                *value = token
+                    .parse()
+                    .map_err(|_| self.invalid(*number, &format!("{token:?} is not a number")))?;
+                Ok::<_, DatasetError>(())
+            })?;
+        Ok(parsed)
+    }
+
+    /// An error at the line, 0 for the header.
+    fn invalid(&self, line: usize, message: &str) -> DatasetError {
+        DatasetError::Format {
+            format: self.format,
+            message: match line {
+                0 => message.to_owned(),
+                line => format!("line {line}: {message}"),
+            },
+        }
+    }
+}
+
+fn split(line: &str) -> impl Iterator<Item = &str> {
+    line.split(',').map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imu_samples() {
+        let content = "\
+#timestamp [ns],w_RS_S_x [rad s^-1],w_RS_S_y [rad s^-1],w_RS_S_z [rad s^-1],\
+a_RS_S_x [m s^-2],a_RS_S_y [m s^-2],a_RS_S_z [m s^-2]
+1403636579758555392,-0.099,0.14,0.025,8.1,-1.9,-0.5
+
+1403636579763555584,-0.098,0.14,0.029,8.3,-1.9,-0.6
+";
+        let samples = Csv::read(content.as_bytes(), "imu")
+            .unwrap()
+            .imu_samples()
+            .unwrap();
+        assert_eq!(samples.len(), 2);
+        assert!((samples[1].timestamp - 1403636579.7635555).abs() < 1e-6);
+        assert!((samples[1].timestamp - samples[0].timestamp - 0.005).abs() < 1e-6);
+        assert_eq!(
+            samples[0].angular_velocity,
+            Vector3::new(-0.099, 0.14, 0.025)
+        );
+        assert_eq!(samples[1].linear_acceleration.x, 8.3);
+    }
+
+    #[test]
+    fn trajectories() {
+        let content = "\
+#sec,nsec,x,y,z,qx,qy,qz,qw
+1583836591,182590976,1,2,3,0,0,0.7071068,0.7071068
+";
+        let trajectory = Csv::read(content.as_bytes(), "ground truth")
+            .unwrap()
+            .trajectory()
+            .unwrap();
+        let pose = &trajectory.poses[0];
+        assert!((pose.timestamp - 1583836591.182591).abs() < 1e-6);
+        assert_eq!(pose.pose.translation.vector, Vector3::new(1.0, 2.0, 3.0));
+        assert!((pose.pose.rotation * Vector3::x() - Vector3::y()).norm() < 1e-6);
+
+        let content = "\
+%time,field.header.seq,field.header.stamp,field.header.frame_id,field.point.x,field.point.y,field.point.z
+1583836591200000000,1,1583836591182590976,leica,4.5,-1.25,0.5
+1583836591300000000,2,1583836591282590976,leica,4.5,-1.25,x
+";
+        let csv = Csv::read(content.as_bytes(), "ground truth").unwrap();
+        let error = csv.trajectory().unwrap_err().to_string();
+        assert!(error.contains("line 3"), "{error}");
+        let csv = Csv {
+            rows: csv.rows[..1].to_vec(),
+            ..csv
+        };
+        let pose = &csv.trajectory().unwrap().poses[0];
+        assert!((pose.timestamp - 1583836591.182591).abs() < 1e-6);
+        assert_eq!(pose.pose.translation.vector, Vector3::new(4.5, -1.25, 0.5));
+        assert_eq!(pose.pose.rotation, Default::default());
+    }
+}
@@ -0,0 +1,298 @@
+//! The KITTI odometry benchmark, see <https://www.cvlibs.net/datasets/kitti/eval_odometry.php>.
+//!
+//! A sequence has the velodyne scans `sequences/NN/velodyne/*.bin`, their timestamps `times.txt`
+//! and the calibration `calib.txt`, its ground truth `poses/NN.txt` is the pose of the left camera.
+//! The benchmark has no imu, the 10 Hz OXTS samples of the matching raw drive can be added with
+//! [`KittiOdometry::with_oxts`].
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use nalgebra::{IsometryMatrix3, Vector3};
+
+use super::{DatasetError, Messages};
+use crate::{
+    config,
+    measurement::{ImuSample, LidarPoint, LidarScan},
+    trajectory::{StampedPose, Trajectory},
+};
+
+/// A sequence of the KITTI odometry benchmark.
+pub struct KittiOdometry {
+    /// the timestamps of the scans, in seconds from the start of the sequence
+    times: Vec<f64>,
+    scans: Vec<PathBuf>,
+    poses: PathBuf,
+    /// the `Tr` of the calibration, from the velodyne to the left camera
+    lidar_to_camera: IsometryMatrix3<f64>,
+    imu_samples: Vec<ImuSample>,
+}
+
+impl KittiOdometry {
+    /// Open the sequence of the dataset directory having `sequences` and `poses`.
+    pub fn open(root: impl AsRef<Path>, sequence: u32) -> Result<Self, DatasetError> {
+        let root = root.as_ref();
+        let directory = root.join("sequences").join(format!("{sequence:02}"));
+        let times = lines(&directory.join("times.txt"))?
+            .iter()
+            .map(|(number, line)| {
+                line.trim().parse().map_err(|_| {
+                    invalid(&format!("times.txt line {number}: {line:?} is not a time"))
+                })
+            })
+            .collect::<Result<Vec<f64>, _>>()?;
+        let scans = (0..times.len())
+            .map(|index| directory.join("velodyne").join(format!("{index:06}.bin")))
+            .collect();
+        let calibration = lines(&directory.join("calib.txt"))?;
+        let transform = calibration
+            .iter()
+            .find_map(|(_, line)| line.strip_prefix("Tr:"))
+            .ok_or_else(|| invalid("the Tr of calib.txt is missing"))?;
+        let lidar_to_camera = transform_3x4(transform)
+            .map_err(|message| invalid(&format!("the Tr of calib.txt: {message}")))?;
+        Ok(Self {
+            times,
+            scans,
+            poses: root.join("poses").join(format!("{sequence:02}.txt")),
+            lidar_to_camera,
+            imu_samples: Vec::new(),
+        })
+    }
+
+    /// Add the OXTS samples of the raw drive, `start_frame` is the frame of the first scan
+    /// in the raw drive, e.g. 0 for the sequence 00 in `2011_10_03_drive_0027`.
+    ///
+    /// The samples are in the OXTS frame, its extrinsic is in `calib_imu_to_velo_cam.txt`.
+    pub fn with_oxts(
+        mut self,
+        oxts: impl AsRef<Path>,
+        start_frame: usize,
+    ) -> Result<Self, DatasetError> {
+        let oxts = oxts.as_ref();
+        let timestamps = lines(&oxts.join("timestamps.txt"))?
+            .iter()
+            .map(|(number, line)| {
+                time_of_day(line).ok_or_else(|| {
+                    invalid(&format!(
+                        "oxts timestamps.txt line {number}: {line:?} is not a time"
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let start = *timestamps
+            .get(start_frame)
+            .ok_or_else(|| invalid(&format!("the oxts have no frame {start_frame}")))?;
+        self.imu_samples = timestamps
+            .iter()
+            .enumerate()
+            .map(|(index, timestamp)| {
+                let path = oxts.join("data").join(format!("{index:010}.txt"));
+                let mut content = String::new();
+                File::open(&path)?.read_to_string(&mut content)?;
+                let values = content
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<Vec<f64>, _>>()
+                    .ok()
+                    .filter(|values| values.len() == 30)
+                    .ok_or_else(|| {
+                        invalid(&format!("{} should have 30 numbers", path.display()))
+                    })?;
+                Ok(ImuSample {
+                    timestamp: timestamp - start,
+                    angular_velocity: Vector3::new(values[17], values[18], values[19]),
+                    linear_acceleration: Vector3::new(values[11], values[12], values[13]),
+                })
+            })
+            .collect::<Result<_, DatasetError>>()?;
+        Ok(self)
+    }
+
+    /// The messages of the sequence, the scans have no point times.
+    pub fn messages(&self) -> Messages {
+        let scans = self.times.iter().copied().zip(self.scans.iter().cloned());
+        Messages::new(
+            self.imu_samples.clone(),
+            scans.collect(),
+            Box::new(read_scan),
+        )
+    }
+
+    /// The ground truth of the velodyne, starting at the identity like the camera poses.
+    pub fn ground_truth(&self) -> Result<Trajectory, DatasetError> {
+        let camera_to_lidar = self.lidar_to_camera.inverse();
+        let poses = lines(&self.poses)?
+            .iter()
+            .zip(&self.times)
+            .map(|((number, line), &timestamp)| {
+                let camera = transform_3x4(line)
+                    .map_err(|message| invalid(&format!("poses line {number}: {message}")))?;
+                Ok(StampedPose::new(
+                    timestamp,
+                    camera_to_lidar * camera * self.lidar_to_camera,
+                ))
+            })
+            .collect::<Result<_, DatasetError>>()?;
+        Ok(Trajectory::new(poses))
+    }
+}
+
+/// Read a velodyne scan, the points are the little endian `f32` x, y, z and reflectance.
+pub fn read_scan(path: &Path, timestamp: f64) -> Result<LidarScan, DatasetError> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    if data.len() % 16 != 0 {
+        return Err(invalid(&format!("{} is truncated", path.display())));
+    }
+    let points = data
+        .chunks_exact(16)
+        .map(|point| {
+            let [x, y, z, reflectance] = std::array::from_fn(|index| {
+                f32::from_le_bytes(point[index * 4..][..4].try_into().unwrap())
+            });
+            LidarPoint {
+                point: Vector3::new(x, y, z).cast().into(),
+                offset_time: 0.0,
+                intensity: reflectance,
+                ring: 0,
+            }
+        })
+        .collect();
+    Ok(LidarScan { timestamp, points })
+}
+
+/// The non-empty lines of the file with their numbers.
+fn lines(path: &Path) -> Result<Vec<(usize, String)>, DatasetError> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .enumerate()
+        .map(|(index, line)| line.map(|line| (index + 1, line)))
+        .filter(|line| !matches!(line, Ok((_, line)) if line.trim().is_empty()))
+        .collect::<Result<_, _>>()
+        .map_err(DatasetError::from)
+}
+
+/// The isometry of the 12 numbers of a row-major 3x4 matrix.
+fn transform_3x4(line: &str) -> Result<IsometryMatrix3<f64>, String> {
+    let values = line
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|error| error.to_string())?;
+    let [r00, r01, r02, tx, r10, r11, r12, ty, r20, r21, r22, tz] = values[..]
+        .try_into()
+        .map_err(|_| format!("expected 12 numbers, found {}", values.len()))?;
+    config::isometry(
+        &[r00, r01, r02, r10, r11, r12, r20, r21, r22],
+        &[tx, ty, tz],
+    )
+    .map_err(str::to_owned)
+}
+
+/// The seconds since midnight of a raw timestamp like `2011-10-03 12:55:34.956056064`,
+/// the drives do not cross midnight.
+fn time_of_day(line: &str) -> Option<f64> {
+    let (_, time) = line.trim().split_once(' ')?;
+    let mut parts = time.split(':');
+    let hours: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next()?.parse().ok()?;
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+fn invalid(message: &str) -> DatasetError {
+    DatasetError::Format {
+        format: "kitti",
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::odometry::SensorMessage;
+
+    #[test]
+    fn sequence() {
+        let root = std::env::temp_dir().join(format!("kitti-{}", std::process::id()));
+        let directory = root.join("sequences/07");
+        let oxts = root.join("oxts");
+        [
+            &directory.join("velodyne"),
+            &root.join("poses"),
+            &oxts.join("data"),
+        ]
+        .into_iter()
+        .for_each(|directory| std::fs::create_dir_all(directory).unwrap());
+        let write = |path: PathBuf, content: &str| std::fs::write(path, content).unwrap();
+        write(directory.join("times.txt"), "0.0\n1.036e-01\n");
+        // the velodyne axes in the camera frame: x right, y down, z forward
+        write(
+            directory.join("calib.txt"),
+            "P0: 1 0 0 0 0 1 0 0 0 0 1 0\nTr: 0 -1 0 0 0 0 -1 -0.08 1 0 0 -0.27\n",
+        );
+        write(
+            root.join("poses/07.txt"),
+            "1 0 0 0 0 1 0 0 0 0 1 0\n1 0 0 0 0 1 0 0 0 0 1 1.5\n",
+        );
+        [[1.0f32, 2.0, 3.0, 0.5], [4.0, 5.0, 6.0, 0.25]]
+            .iter()
+            .enumerate()
+            .for_each(|(index, point)| {
+                let data: Vec<u8> = point.iter().flat_map(|value| value.to_le_bytes()).collect();
+                std::fs::write(directory.join(format!("velodyne/{index:06}.bin")), data).unwrap();
+            });
+        write(
+            oxts.join("timestamps.txt"),
+            "2011-09-30 12:40:20.000000000\n2011-09-30 12:40:20.050000000\n2011-09-30 12:40:20.150000000\n",
+        );
+        (0..3).for_each(|index| {
+            let mut values = [0.0; 30];
+            values[11] = 9.8;
+            values[19] = index as f64;
+            let values: Vec<_> = values.iter().map(f64::to_string).collect();
+            write(
+                oxts.join(format!("data/{index:010}.txt")),
+                &values.join(" "),
+            );
+        });
+
+        let sequence = KittiOdometry::open(&root, 7)
+            .unwrap()
+            .with_oxts(&oxts, 1)
+            .unwrap();
+        let messages = sequence.messages().collect::<Result<Vec<_>, _>>().unwrap();
+        let kinds: Vec<_> = messages
+            .iter()
+            .map(|message| match message {
+                SensorMessage::Imu(sample) => {
+                    assert_eq!(sample.linear_acceleration.x, 9.8);
+                    "imu"
+                }
+                SensorMessage::Lidar(_) => "lidar",
+                SensorMessage::Camera(_) => "camera",
+            })
+            .collect();
+        assert_eq!(kinds, ["imu", "imu", "lidar", "imu", "lidar"]);
+        let SensorMessage::Lidar(scan) = &messages[4] else {
+            unreachable!()
+        };
+        assert_eq!(scan.timestamp, 1.036e-01);
+        assert_eq!(scan.points[0].point.z, 6.0);
+        assert_eq!(scan.points[0].intensity, 0.25);
+
+        let ground_truth = sequence.ground_truth().unwrap();
+        assert_eq!(ground_truth.len(), 2);
+        // the camera moves forward, along the x axis of the velodyne
+        let moved = ground_truth.poses[1].pose;
+        assert!((moved.translation.vector - Vector3::new(1.5, 0.0, 0.0)).norm() < 1e-9);
+        assert!((moved.rotation.matrix() - nalgebra::Matrix3::identity()).amax() < 1e-9);
+
+        std::fs::remove_file(directory.join("velodyne/000001.bin")).unwrap();
+        assert!(sequence.messages().any(|message| message.is_err()));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,622 @@
+//! The MCAP files recorded by ROS 2, or converted from ROS 1 bags, see <https://mcap.dev/spec>.
+//!
+//! The messages are read in the order of their log time, the chunks are loaded when their first
+//! message is due. They are found by the chunk indexes of the summary, or by scanning the file if
+//! it has no summary. The chunks may be compressed with lz4 or zstd.
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
+    fs::File,
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use super::{
+    DatasetError,
+    ros::{MessageType, Serialization},
+};
+use crate::{odometry::SensorMessage, preprocess::Lidar};
+
+const MAGIC: &[u8; 8] = b"\x89MCAP0\r\n";
+const FOOTER: u8 = 0x02;
+const SCHEMA: u8 = 0x03;
+const CHANNEL: u8 = 0x04;
+const MESSAGE: u8 = 0x05;
+const CHUNK: u8 = 0x06;
+const CHUNK_INDEX: u8 = 0x08;
+const DATA_END: u8 = 0x0f;
+/// the opcode, the length and the 20 bytes of the footer record
+const FOOTER_LEN: u64 = 1 + 8 + 20;
+
+/// The topics to read, the messages of the other topics are skipped.
+#[derive(Debug, Clone)]
+pub struct Topics {
+    /// a `sensor_msgs/PointCloud2` or Livox `CustomMsg` topic
+    pub lidar: String,
+    /// a `sensor_msgs/Imu` topic
+    pub imu: String,
+    /// a `sensor_msgs/Image` or `sensor_msgs/CompressedImage` topic
+    pub camera: Option<String>,
+}
+
+impl Topics {
+    /// The sensor of the topic, `None` if it is not read.
+    fn sensor(&self, topic: &str) -> Option<&'static str> {
+        if topic == self.lidar {
+            Some("lidar")
+        } else if topic == self.imu {
+            Some("imu")
+        } else if self.camera.as_deref() == Some(topic) {
+            Some("camera")
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the [`SensorMessage`]s of the [`Topics`] from an MCAP file.
+pub struct McapReader<R> {
+    reader: R,
+    topics: Topics,
+    /// the lidar decoding the point times of the `PointCloud2` scans
+    lidar: Option<Lidar>,
+    /// the names of the schemas by their id
+    schemas: HashMap<u16, String>,
+    /// the channels of the read topics by their id
+    channels: HashMap<u16, Channel>,
+    /// the chunks not loaded yet, by their start time
+    chunks: VecDeque<ChunkIndex>,
+    /// the messages of the loaded chunks, the earliest first
+    pending: BinaryHeap<Reverse<PendingMessage>>,
+    /// the number of messages loaded, to keep the file order of the messages logged at the same time
+    loaded: u64,
+}
+
+struct Channel {
+    topic: String,
+    message_type: MessageType,
+    serialization: Serialization,
+}
+
+struct ChunkIndex {
+    start_time: u64,
+    /// the offset of the chunk record in the file
+    offset: u64,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct PendingMessage {
+    log_time: u64,
+    order: u64,
+    channel: u16,
+    data: Vec<u8>,
+}
+
+impl McapReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>, topics: Topics) -> Result<Self, DatasetError> {
+        Self::new(BufReader::new(File::open(path)?), topics)
+    }
+}
+
+impl<R: Read + Seek> McapReader<R> {
+    pub fn new(mut reader: R, topics: Topics) -> Result<Self, DatasetError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("the magic is missing"));
+        }
+        let mut mcap = Self {
+            reader,
+            topics,
+            lidar: None,
+            schemas: HashMap::new(),
+            channels: HashMap::new(),
+            chunks: VecDeque::new(),
+            pending: BinaryHeap::new(),
+            loaded: 0,
+        };
+        if !mcap.read_summary()? {
+            mcap.scan_data()?;
+        }
+        mcap.chunks
+            .make_contiguous()
+            .sort_by_key(|chunk| chunk.start_time);
+        Ok(mcap)
+    }
+
+    /// Decode the point times of the `PointCloud2` scans with the configured lidar, like
+    /// [`preprocess::Config::lidar`](crate::preprocess::Config::lidar). Without one, the float
+    /// times are offsets in s and the integer ones in ns.
+    pub fn with_lidar(mut self, lidar: Option<Lidar>) -> Self {
+        self.lidar = lidar;
+        self
+    }
+
+    /// Read the schemas, the channels and the chunk indexes of the summary,
+    /// `false` if there is no summary or it has no chunk index.
+    fn read_summary(&mut self) -> Result<bool, DatasetError> {
+        let len = self.reader.seek(SeekFrom::End(0))?;
+        if len < 2 * MAGIC.len() as u64 + FOOTER_LEN {
+            return Err(invalid("the footer is missing"));
+        }
+        self.reader
+            .seek(SeekFrom::Start(len - MAGIC.len() as u64 - FOOTER_LEN))?;
+        let (opcode, footer) = self.read_record()?;
+        if opcode != FOOTER {
+            return Err(invalid("the footer is missing"));
+        }
+        let summary_start = Fields::new(&footer).u64()?;
+        if summary_start == 0 {
+            return Ok(false);
+        }
+
+        self.reader.seek(SeekFrom::Start(summary_start))?;
+        loop {
+            let (opcode, content) = self.read_record()?;
+            match opcode {
+                FOOTER => break,
+                CHUNK_INDEX => {
+                    let mut fields = Fields::new(&content);
+                    let start_time = fields.u64()?;
+                    let _end_time = fields.u64()?;
+                    let offset = fields.u64()?;
+                    self.chunks.push_back(ChunkIndex { start_time, offset });
+                }
+                _ => self.handle_record(opcode, &content)?,
+            }
+        }
+        Ok(!self.chunks.is_empty())
+    }
+
+    /// Find the chunks and read the records out of the chunks in the data section,
+    /// which may be truncated if the recording was interrupted.
+    fn scan_data(&mut self) -> Result<(), DatasetError> {
+        let mut offset = self.reader.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        loop {
+            let (opcode, len) = match self.read_record_header() {
+                Err(DatasetError::Io(error)) if error.kind() == ErrorKind::UnexpectedEof => break,
+                result => result?,
+            };
+            let end = offset + 1 + 8 + len;
+            match opcode {
+                DATA_END | FOOTER => break,
+                CHUNK => {
+                    let mut start_time = [0; 8];
+                    self.reader.read_exact(&mut start_time)?;
+                    self.chunks.push_back(ChunkIndex {
+                        start_time: u64::from_le_bytes(start_time),
+                        offset,
+                    });
+                }
+                SCHEMA | CHANNEL | MESSAGE => {
+                    let content = self.read_content(len)?;
+                    self.handle_record(opcode, &content)?;
+                }
+                _ => {}
+            }
+            offset = self.reader.seek(SeekFrom::Start(end))?;
+        }
+        Ok(())
+    }
+
+    fn load_chunk(&mut self, chunk: ChunkIndex) -> Result<(), DatasetError> {
+        self.reader.seek(SeekFrom::Start(chunk.offset))?;
+        let (opcode, content) = self.read_record()?;
+        if opcode != CHUNK {
+            return Err(invalid("a chunk index does not point to a chunk"));
+        }
+        let mut fields = Fields::new(&content);
+        let _start_time = fields.u64()?;
+        let _end_time = fields.u64()?;
+        let uncompressed_len = fields.u64()? as usize;
+        let _crc = fields.u32()?;
+        let compression = fields.string()?;
+        let records_len = fields.u64()? as usize;
+        let records = fields.bytes(records_len)?;
+
+        let mut decompressed = Vec::with_capacity(uncompressed_len);
+        let records = match compression {
+            "" => records,
+            "lz4" => {
+                lz4_flex::frame::FrameDecoder::new(records).read_to_end(&mut decompressed)?;
+                &decompressed
+            }
+            "zstd" => {
+                ruzstd::decoding::StreamingDecoder::new(records)
+                    .map_err(|error| invalid(&error.to_string()))?
+                    .read_to_end(&mut decompressed)?;
+                &decompressed
+            }
+            _ => return Err(invalid(&format!("unsupported compression {compression}"))),
+        };
+        if records.len() != uncompressed_len {
+            return Err(invalid("the size of a decompressed chunk is wrong"));
+        }
+
+        let mut fields = Fields::new(records);
+        while !fields.is_empty() {
+            let opcode = fields.u8()?;
+            let len = fields.u64()? as usize;
+            self.handle_record(opcode, fields.bytes(len)?)?;
+        }
+        Ok(())
+    }
+
+    /// Read a schema, a channel or a message, the other records are ignored.
+    fn handle_record(&mut self, opcode: u8, content: &[u8]) -> Result<(), DatasetError> {
+        let mut fields = Fields::new(content);
+        match opcode {
+            SCHEMA => {
+                let id = fields.u16()?;
+                let name = fields.string()?;
+                self.schemas.insert(id, name.to_owned());
+            }
+            CHANNEL => {
+                let id = fields.u16()?;
+                let schema_id = fields.u16()?;
+                let topic = fields.string()?;
+                let message_encoding = fields.string()?;
+                let Some(sensor) = self.topics.sensor(topic) else {
+                    return Ok(());
+                };
+                let unsupported = |message: String| DatasetError::Decode {
+                    topic: topic.to_owned(),
+                    message,
+                };
+                let schema = self.schemas.get(&schema_id).map_or("", String::as_str);
+                let message_type = MessageType::from_schema(schema)
+                    .filter(|message_type| message_type.sensor() == sensor)
+                    .ok_or_else(|| {
+                        unsupported(format!("unsupported {sensor} schema {schema:?}"))
+                    })?;
+                let serialization = Serialization::from_message_encoding(message_encoding)
+                    .ok_or_else(|| {
+                        unsupported(format!("unsupported message encoding {message_encoding:?}"))
+                    })?;
+                self.channels.insert(
+                    id,
+                    Channel {
+                        topic: topic.to_owned(),
+                        message_type,
+                        serialization,
+                    },
+                );
+            }
+            MESSAGE => {
+                let channel = fields.u16()?;
+                let _sequence = fields.u32()?;
+                let log_time = fields.u64()?;
+                let _publish_time = fields.u64()?;
+                if self.channels.contains_key(&channel) {
+                    self.pending.push(Reverse(PendingMessage {
+                        log_time,
+                        order: self.loaded,
+                        channel,
+                        data: fields.rest().to_vec(),
+                    }));
+                    self.loaded += 1;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn read_record_header(&mut self) -> Result<(u8, u64), DatasetError> {
+        let mut header = [0; 9];
+        self.reader.read_exact(&mut header)?;
+        let len = u64::from_le_bytes(header[1..].try_into().expect("8 bytes"));
+        Ok((header[0], len))
+    }
+
+    fn read_content(&mut self, len: u64) -> Result<Vec<u8>, DatasetError> {
+        let mut content = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut content)?;
+        if content.len() as u64 != len {
+            return Err(invalid("a record is truncated"));
+        }
+        Ok(content)
+    }
+
+    fn read_record(&mut self) -> Result<(u8, Vec<u8>), DatasetError> {
+        let (opcode, len) = self.read_record_header()?;
+        Ok((opcode, self.read_content(len)?))
+    }
+}
+
+impl<R: Read + Seek> Iterator for McapReader<R> {
+    type Item = Result<SensorMessage, DatasetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let chunk_due = self.chunks.front().is_some_and(|chunk| {
+                self.pending
+                    .peek()
+                    .is_none_or(|Reverse(message)| chunk.start_time <= message.log_time)
+            });
+            if !chunk_due {
+                break;
+            }
+            let chunk = self.chunks.pop_front()?;
+            if let Err(error) = self.load_chunk(chunk) {
+                return Some(Err(error));
+            }
+        }
+        let Reverse(message) = self.pending.pop()?;
+        let channel = &self.channels[&message.channel];
+        let decoded = channel
+            .message_type
+            .decode(channel.serialization, &message.data, self.lidar)
+            .map_err(|error| DatasetError::Decode {
+                topic: channel.topic.clone(),
+                message: error,
+            });
+        Some(decoded)
+    }
+}
+
+fn invalid(message: &str) -> DatasetError {
+    DatasetError::Format {
+        format: "mcap",
+        message: message.to_owned(),
+    }
+}
+
+/// The little endian fields of a record.
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DatasetError> {
+        if self.data.len() < len {
+            return Err(invalid("a record is truncated"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DatasetError> {
+        Ok(self
+            .bytes(N)?
+            .try_into()
+            .expect("the bytes have the length"))
+    }
+
+    fn u8(&mut self) -> Result<u8, DatasetError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DatasetError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, DatasetError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, DatasetError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<&'a str, DatasetError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.bytes(len)?).map_err(|_| invalid("a string is not utf-8"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+    use crate::dataset::ros::tests::{hesai_message, imu_message, velodyne_message};
+
+    const HEADER: u8 = 0x01;
+
+    fn string(content: &mut Vec<u8>, value: &str) {
+        content.extend((value.len() as u32).to_le_bytes());
+        content.extend(value.as_bytes());
+    }
+
+    fn record(opcode: u8, content: &[u8]) -> Vec<u8> {
+        let mut record = vec![opcode];
+        record.extend((content.len() as u64).to_le_bytes());
+        record.extend(content);
+        record
+    }
+
+    fn schema(id: u16, name: &str) -> Vec<u8> {
+        let mut content = id.to_le_bytes().to_vec();
+        string(&mut content, name);
+        string(&mut content, "ros2msg");
+        content.extend(0u32.to_le_bytes());
+        record(SCHEMA, &content)
+    }
+
+    fn channel(id: u16, schema_id: u16, topic: &str) -> Vec<u8> {
+        let mut content = id.to_le_bytes().to_vec();
+        content.extend(schema_id.to_le_bytes());
+        string(&mut content, topic);
+        string(&mut content, "cdr");
+        content.extend(0u32.to_le_bytes());
+        record(CHANNEL, &content)
+    }
+
+    fn message(channel: u16, log_time: u64, data: &[u8]) -> Vec<u8> {
+        let mut content = channel.to_le_bytes().to_vec();
+        content.extend(0u32.to_le_bytes());
+        content.extend(log_time.to_le_bytes());
+        content.extend(log_time.to_le_bytes());
+        content.extend(data);
+        record(MESSAGE, &content)
+    }
+
+    fn chunk(start_time: u64, records: &[u8], compression: &str) -> Vec<u8> {
+        let compressed = match compression {
+            "lz4" => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(records).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => records.to_vec(),
+        };
+        let mut content = start_time.to_le_bytes().to_vec();
+        content.extend(start_time.to_le_bytes());
+        content.extend((records.len() as u64).to_le_bytes());
+        content.extend(0u32.to_le_bytes());
+        string(&mut content, compression);
+        content.extend((compressed.len() as u64).to_le_bytes());
+        content.extend(compressed);
+        record(CHUNK, &content)
+    }
+
+    fn chunk_index(start_time: u64, offset: u64) -> Vec<u8> {
+        let mut content = start_time.to_le_bytes().to_vec();
+        content.extend(start_time.to_le_bytes());
+        content.extend(offset.to_le_bytes());
+        content.extend([0; 8 + 4 + 8]);
+        string(&mut content, "");
+        content.extend([0; 16]);
+        record(CHUNK_INDEX, &content)
+    }
+
+    /// Two overlapping chunks, the imu messages of the first one surround the scan of the second.
+    fn file(with_summary: bool) -> Vec<u8> {
+        let second = 1_000_000_000;
+        let definitions = [
+            schema(1, "sensor_msgs/msg/Imu"),
+            schema(2, "sensor_msgs/msg/PointCloud2"),
+            channel(1, 1, "/imu"),
+            channel(2, 2, "/points"),
+            channel(3, 1, "/other_imu"),
+        ]
+        .concat();
+        let first = [
+            definitions.clone(),
+            message(1, 10 * second, &imu_message(10, 0)),
+            message(3, 11 * second, &imu_message(11, 0)),
+            message(1, 12 * second, &imu_message(12, 0)),
+        ]
+        .concat();
+        let last = message(2, 11 * second, &velodyne_message(11));
+
+        let mut file = MAGIC.to_vec();
+        file.extend(record(HEADER, &[0; 8]));
+        let first_offset = file.len() as u64;
+        file.extend(chunk(10 * second, &first, "lz4"));
+        let last_offset = file.len() as u64;
+        file.extend(chunk(11 * second, &last, ""));
+        file.extend(record(DATA_END, &[0; 4]));
+
+        let summary_start = file.len() as u64;
+        file.extend(definitions);
+        file.extend(chunk_index(11 * second, last_offset));
+        file.extend(chunk_index(10 * second, first_offset));
+        let mut footer = if with_summary { summary_start } else { 0 }
+            .to_le_bytes()
+            .to_vec();
+        footer.extend([0; 12]);
+        file.extend(record(FOOTER, &footer));
+        file.extend(MAGIC);
+        file
+    }
+
+    #[test]
+    fn messages_in_time_order() {
+        [true, false].into_iter().for_each(|with_summary| {
+            let topics = Topics {
+                lidar: "/points".into(),
+                imu: "/imu".into(),
+                camera: None,
+            };
+            let messages = McapReader::new(Cursor::new(file(with_summary)), topics)
+                .unwrap()
+                .map(|message| match message.unwrap() {
+                    SensorMessage::Imu(sample) => ("imu", sample.timestamp),
+                    SensorMessage::Lidar(scan) => ("lidar", scan.timestamp.round()),
+                    SensorMessage::Camera(image) => ("camera", image.timestamp),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(
+                messages,
+                [("imu", 10.0), ("lidar", 11.0), ("imu", 12.0)],
+                "with summary: {with_summary}"
+            );
+        });
+    }
+
+    #[test]
+    fn mismatched_schema() {
+        let topics = Topics {
+            lidar: "/imu".into(),
+            imu: "/points".into(),
+            camera: None,
+        };
+        let error = McapReader::new(Cursor::new(file(true)), topics)
+            .err()
+            .expect("the schemas do not match the sensors");
+        assert!(matches!(error, DatasetError::Decode { topic, .. } if topic == "/imu"));
+    }
+
+    #[test]
+    fn hesai_times() {
+        let second = 1_000_000_000;
+        let stamp = 1_700_000_000;
+        let records = [
+            schema(1, "sensor_msgs/msg/PointCloud2"),
+            channel(1, 1, "/hesai/pandar"),
+            message(
+                1,
+                stamp * second,
+                &hesai_message(stamp as u32, [stamp as f64 + 0.02, stamp as f64 + 0.07]),
+            ),
+        ]
+        .concat();
+        let mut file = MAGIC.to_vec();
+        file.extend(record(HEADER, &[0; 8]));
+        file.extend(chunk(stamp * second, &records, ""));
+        file.extend(record(DATA_END, &[0; 4]));
+        file.extend(record(FOOTER, &[0; 20]));
+        file.extend(MAGIC);
+
+        let topics = Topics {
+            lidar: "/hesai/pandar".into(),
+            imu: "/imu".into(),
+            camera: None,
+        };
+        let scans: Vec<_> = McapReader::new(Cursor::new(file), topics)
+            .unwrap()
+            .with_lidar(Some(Lidar::Hesai))
+            .map(|message| match message.unwrap() {
+                SensorMessage::Lidar(scan) => scan,
+                _ => panic!("expected a lidar scan"),
+            })
+            .collect();
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].timestamp, stamp as f64);
+        let times: Vec<_> = scans[0]
+            .points
+            .iter()
+            .map(|point| point.offset_time)
+            .collect();
+        assert!(
+            (times[0] - 0.02).abs() < 1e-6 && (times[1] - 0.07).abs() < 1e-6,
+            "{times:?}"
+        );
+        assert_eq!(scans[0].points[1].ring, 5);
+    }
+}
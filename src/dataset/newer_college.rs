@@ -0,0 +1,152 @@
+//! The Newer College dataset, see <https://ori-drs.github.io/newer-college-dataset/>.
+//!
+//! The Ouster scans are PCD files named `cloud_<sec>_<nsec>.pcd` with the point times in their
+//! `t` field, the imu samples and the ground truth are CSV files.
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use super::{DatasetError, Messages, csv::Csv};
+use crate::{
+    cloud::pcd,
+    frame::Body,
+    measurement::{ImuSample, LidarScan},
+    preprocess::{self, Lidar},
+    trajectory::Trajectory,
+};
+
+/// The scans and the imu samples of a sequence.
+pub struct NewerCollege {
+    scans: Vec<(f64, PathBuf)>,
+    imu_samples: Vec<ImuSample>,
+    /// the lidar decoding the point times, the Ouster of the dataset by default
+    lidar: Option<Lidar>,
+}
+
+impl NewerCollege {
+    /// Open the directory of the scans and the imu CSV, its columns are found by name
+    /// like `timestamp [ns]` or `sec, nsec` and `wx` or `angular_velocity.x`.
+    pub fn open(scans: impl AsRef<Path>, imu: impl AsRef<Path>) -> Result<Self, DatasetError> {
+        let mut scans = std::fs::read_dir(scans)?
+            .map(|entry| {
+                let path = entry?.path();
+                let timestamp = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(scan_time);
+                Ok(timestamp.map(|timestamp| (timestamp, path)))
+            })
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>, DatasetError>>()?;
+        scans.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        let imu_samples = Csv::read(BufReader::new(File::open(imu)?), "imu csv")?.imu_samples()?;
+        Ok(Self {
+            scans,
+            imu_samples,
+            lidar: Some(Lidar::Ouster),
+        })
+    }
+
+    /// Decode the point times with the configured lidar instead of the Ouster.
+    pub fn with_lidar(mut self, lidar: Option<Lidar>) -> Self {
+        self.lidar = lidar;
+        self
+    }
+
+    /// The messages of the sequence, in time order.
+    pub fn messages(&self) -> Messages {
+        let lidar = self.lidar;
+        Messages::new(
+            self.imu_samples.clone(),
+            self.scans.clone(),
+            Box::new(move |path, timestamp| read_scan(path, timestamp, lidar)),
+        )
+    }
+}
+
+/// The ground truth CSV, `#sec,nsec,x,y,z,qx,qy,qz,qw` of the base frame.
+pub fn ground_truth(path: impl AsRef<Path>) -> Result<Trajectory, DatasetError> {
+    Csv::read(BufReader::new(File::open(path)?), "ground truth")?.trajectory()
+}
+
+/// Read a scan PCD stamped at `timestamp`, its point times are decoded by the `lidar`.
+pub fn read_scan(
+    path: &Path,
+    timestamp: f64,
+    lidar: Option<Lidar>,
+) -> Result<LidarScan, DatasetError> {
+    let reader = BufReader::new(File::open(path)?);
+    let cloud = match lidar {
+        Some(_) => pcd::read_raw::<Body>(reader)?,
+        None => pcd::read::<Body>(reader)?,
+    };
+    Ok(preprocess::decode(
+        lidar,
+        timestamp,
+        &cloud.into_raw_points(),
+    ))
+}
+
+/// The timestamp of a file name like `cloud_1583836591_182590976.pcd`.
+fn scan_time(name: &str) -> Option<f64> {
+    let (seconds, nanoseconds) = name
+        .strip_prefix("cloud_")?
+        .strip_suffix(".pcd")?
+        .split_once('_')?;
+    Some(seconds.parse::<u64>().ok()? as f64 + nanoseconds.parse::<u64>().ok()? as f64 / 1e9)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::odometry::SensorMessage;
+
+    #[test]
+    fn sequence() {
+        let directory = std::env::temp_dir().join(format!("newer-college-{}", std::process::id()));
+        let scans = directory.join("ouster_scan");
+        std::fs::create_dir_all(&scans).unwrap();
+        // the integer times of the ouster driver in ns
+        let cloud = "\
+FIELDS x y z t
+SIZE 4 4 4 4
+TYPE F F F U
+COUNT 1 1 1 1
+POINTS 2
+DATA ascii
+1 2 3 0
+4 5 6 50000000
+";
+        ["cloud_10_500000000.pcd", "cloud_10_0.pcd"]
+            .iter()
+            .for_each(|name| std::fs::write(scans.join(name), cloud).unwrap());
+        std::fs::write(scans.join("README.txt"), "").unwrap();
+        let imu = directory.join("imu.csv");
+        std::fs::write(
+            &imu,
+            "#sec,nsec,wx,wy,wz,ax,ay,az\n10,250000000,0,0,0.1,0,0,9.8\n",
+        )
+        .unwrap();
+
+        let messages = NewerCollege::open(&scans, &imu)
+            .unwrap()
+            .messages()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let timestamps: Vec<_> = messages
+            .iter()
+            .map(|message| match message {
+                SensorMessage::Imu(sample) => sample.timestamp,
+                SensorMessage::Lidar(scan) => {
+                    assert_eq!(scan.points[1].offset_time, 0.05);
+                    scan.timestamp
+                }
+                SensorMessage::Camera(image) => image.timestamp,
+            })
+            .collect();
+        assert_eq!(timestamps, [10.0, 10.25, 10.5]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
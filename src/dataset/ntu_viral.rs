@@ -0,0 +1,65 @@
+//! The NTU-VIRAL dataset, see <https://ntu-aris.github.io/ntu_viral_dataset/>.
+//!
+//! The sequences are ROS 1 bags, read from their MCAP conversion (`mcap convert`).
+//! The ground truth is the position of the prism tracked by a Leica total station,
+//! exported with `rostopic echo -p /leica/pose/relative`.
+use std::{fs::File, io::BufReader, path::Path};
+
+use super::{
+    DatasetError,
+    csv::Csv,
+    mcap::{McapReader, Topics},
+};
+use crate::{preprocess::Lidar, trajectory::Trajectory};
+
+/// The topics of the horizontal Ouster, the VN100 imu and the left camera.
+pub fn topics() -> Topics {
+    Topics {
+        lidar: "/os1_cloud_node1/points".into(),
+        imu: "/imu/imu".into(),
+        camera: Some("/left/image_raw".into()),
+    }
+}
+
+/// Open the MCAP conversion of a sequence bag with the default [`topics`],
+/// the point times are decoded like the Ouster ones.
+pub fn open(path: impl AsRef<Path>) -> Result<McapReader<BufReader<File>>, DatasetError> {
+    Ok(McapReader::open(path, topics())?.with_lidar(Some(Lidar::Ouster)))
+}
+
+/// The ground truth CSV, the orientations are the identity since only the positions are tracked.
+pub fn ground_truth(path: impl AsRef<Path>) -> Result<Trajectory, DatasetError> {
+    Csv::read(BufReader::new(File::open(path)?), "ground truth")?.trajectory()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leica_ground_truth() {
+        let directory = std::env::temp_dir().join(format!("ntu-viral-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("leica_pose.csv");
+        std::fs::write(
+            &path,
+            "%time,field.header.seq,field.header.stamp,field.header.frame_id,\
+             field.point.x,field.point.y,field.point.z\n\
+             1597000000150000000,1,1597000000100000000,leica,1.0,2.0,3.0\n\
+             1597000000250000000,2,1597000000200000000,leica,1.5,2.0,3.0\n",
+        )
+        .unwrap();
+
+        let trajectory = ground_truth(&path).unwrap();
+        assert_eq!(trajectory.poses.len(), 2);
+        // stamped by the header, not by the recording time
+        assert!((trajectory.poses[1].timestamp - 1_597_000_000.2).abs() < 1e-6);
+        let pose = &trajectory.poses[1].pose;
+        assert_eq!(
+            pose.translation.vector,
+            nalgebra::Vector3::new(1.5, 2.0, 3.0)
+        );
+        assert_eq!(pose.rotation, nalgebra::Rotation3::identity());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
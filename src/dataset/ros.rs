@@ -0,0 +1,596 @@
+//! Decoding of the ROS sensor messages, serialized in CDR by ROS 2 or in the ROS 1 format.
+use kornia::{
+    image::{
+        ImageSize,
+        allocator::CpuAllocator,
+        color_spaces::{Gray8, Rgb8, Rgba8},
+    },
+    io::{jpeg, png},
+};
+use nalgebra::Vector3;
+
+use crate::{
+    cloud::{CloudBuilder, Field, Property, ScalarType},
+    frame::Body,
+    imu::ImuSample,
+    measurement::{CameraImage, LidarScan},
+    odometry::SensorMessage,
+    preprocess::{self, Lidar, RawPoint},
+    vio::GrayImage,
+};
+
+/// The serialization of the messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Serialization {
+    /// ROS 2, the values are aligned to their size after a 4-byte encapsulation header
+    Cdr,
+    /// ROS 1, the values are packed in little endian
+    Ros1,
+}
+
+impl Serialization {
+    pub(crate) fn from_message_encoding(encoding: &str) -> Option<Self> {
+        match encoding {
+            "cdr" => Some(Self::Cdr),
+            "ros1" => Some(Self::Ros1),
+            _ => None,
+        }
+    }
+}
+
+/// The supported message types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MessageType {
+    PointCloud2,
+    /// the `CustomMsg` of `livox_ros_driver` and `livox_ros_driver2`
+    LivoxCustom,
+    Imu,
+    Image,
+    CompressedImage,
+}
+
+impl MessageType {
+    /// The type of a schema name, like `sensor_msgs/msg/Imu` in ROS 2 or `sensor_msgs/Imu` in ROS 1.
+    pub(crate) fn from_schema(name: &str) -> Option<Self> {
+        match name.replace("/msg/", "/").as_str() {
+            "sensor_msgs/PointCloud2" => Some(Self::PointCloud2),
+            "livox_ros_driver/CustomMsg" | "livox_ros_driver2/CustomMsg" => Some(Self::LivoxCustom),
+            "sensor_msgs/Imu" => Some(Self::Imu),
+            "sensor_msgs/Image" => Some(Self::Image),
+            "sensor_msgs/CompressedImage" => Some(Self::CompressedImage),
+            _ => None,
+        }
+    }
+
+    /// The sensor of the messages, `lidar`, `imu` or `camera`.
+    pub(crate) fn sensor(self) -> &'static str {
+        match self {
+            Self::PointCloud2 | Self::LivoxCustom => "lidar",
+            Self::Imu => "imu",
+            Self::Image | Self::CompressedImage => "camera",
+        }
+    }
+
+    /// Decode a message, the point times of the clouds are decoded by the `lidar`.
+    pub(crate) fn decode(
+        self,
+        serialization: Serialization,
+        data: &[u8],
+        lidar: Option<Lidar>,
+    ) -> Result<SensorMessage, String> {
+        let mut decoder = Decoder::new(data, serialization)?;
+        match self {
+            Self::PointCloud2 => point_cloud2(&mut decoder, lidar).map(SensorMessage::Lidar),
+            Self::LivoxCustom => livox_custom(&mut decoder).map(SensorMessage::Lidar),
+            Self::Imu => imu(&mut decoder).map(SensorMessage::Imu),
+            Self::Image => image(&mut decoder).map(SensorMessage::Camera),
+            Self::CompressedImage => compressed_image(&mut decoder).map(SensorMessage::Camera),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// the position the alignment is relative to
+    origin: usize,
+    big_endian: bool,
+    serialization: Serialization,
+}
+
+macro_rules! read {
+    ($name:ident, $ty:ty) => {
+        fn $name(&mut self) -> Result<$ty, String> {
+            const SIZE: usize = std::mem::size_of::<$ty>();
+            self.align(SIZE);
+            let bytes = self
+                .bytes(SIZE)?
+                .try_into()
+                .expect("the bytes have the size");
+            Ok(if self.big_endian {
+                <$ty>::from_be_bytes(bytes)
+            } else {
+                <$ty>::from_le_bytes(bytes)
+            })
+        }
+    };
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8], serialization: Serialization) -> Result<Self, String> {
+        let (origin, big_endian) = match serialization {
+            Serialization::Ros1 => (0, false),
+            Serialization::Cdr => match data.get(..2) {
+                Some([0, 0]) => (4, true),
+                Some([0, 1]) => (4, false),
+                _ => return Err("unsupported CDR encapsulation".into()),
+            },
+        };
+        Ok(Self {
+            data,
+            position: origin,
+            origin,
+            big_endian,
+            serialization,
+        })
+    }
+
+    fn align(&mut self, size: usize) {
+        if self.serialization == Serialization::Cdr {
+            let offset = self.position - self.origin;
+            self.position += (size - offset % size) % size;
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or("the message is truncated")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    read!(u8, u8);
+    read!(u32, u32);
+    read!(i32, i32);
+    read!(u64, u64);
+    read!(f64, f64);
+
+    fn vector3(&mut self) -> Result<Vector3<f64>, String> {
+        Ok(Vector3::new(self.f64()?, self.f64()?, self.f64()?))
+    }
+
+    fn string(&mut self) -> Result<&'a str, String> {
+        let len = self.u32()? as usize;
+        let bytes = self.bytes(len)?;
+        // the CDR strings end with a null
+        let bytes = match self.serialization {
+            Serialization::Cdr => bytes.strip_suffix(&[0]).unwrap_or(bytes),
+            Serialization::Ros1 => bytes,
+        };
+        std::str::from_utf8(bytes).map_err(|error| error.to_string())
+    }
+
+    fn byte_sequence(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    /// The stamp of a `std_msgs/Header` in seconds, the frame id is skipped.
+    fn header(&mut self) -> Result<f64, String> {
+        let (seconds, nanoseconds) = match self.serialization {
+            Serialization::Ros1 => {
+                let _sequence = self.u32()?;
+                (self.u32()? as f64, self.u32()?)
+            }
+            Serialization::Cdr => (self.i32()? as f64, self.u32()?),
+        };
+        self.string()?;
+        Ok(seconds + nanoseconds as f64 * 1e-9)
+    }
+
+    fn skip_f64s(&mut self, count: usize) -> Result<(), String> {
+        (0..count).try_for_each(|_| self.f64().map(drop))
+    }
+}
+
+fn imu(decoder: &mut Decoder) -> Result<ImuSample, String> {
+    let timestamp = decoder.header()?;
+    // the orientation and its covariance
+    decoder.skip_f64s(4 + 9)?;
+    let angular_velocity = decoder.vector3()?;
+    decoder.skip_f64s(9)?;
+    let linear_acceleration = decoder.vector3()?;
+    Ok(ImuSample {
+        timestamp,
+        angular_velocity,
+        linear_acceleration,
+    })
+}
+
+/// The fields are decoded like in the PCD files, the times are those of the driver with a `lidar`,
+/// otherwise the integer times are in nanoseconds.
+fn point_cloud2(decoder: &mut Decoder, lidar: Option<Lidar>) -> Result<LidarScan, String> {
+    let timestamp = decoder.header()?;
+    let height = decoder.u32()? as usize;
+    let width = decoder.u32()? as usize;
+    let fields = (0..decoder.u32()?)
+        .map(|_| {
+            let name = decoder.string()?;
+            let offset = decoder.u32()? as usize;
+            let datatype = decoder.u8()?;
+            let _count = decoder.u32()?;
+            let ty = point_field_type(datatype)
+                .ok_or_else(|| format!("unknown datatype {datatype} of the field {name}"))?;
+            let property = Property {
+                field: Field::from_name(name),
+                ty,
+                count: 1,
+            };
+            Ok((property, offset))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let big_endian = decoder.u8()? != 0;
+    let point_step = decoder.u32()? as usize;
+    let row_step = decoder.u32()? as usize;
+    let data = decoder.byte_sequence()?;
+
+    let properties: Vec<_> = fields.iter().map(|(property, _)| *property).collect();
+    let mut builder = CloudBuilder::<Body>::new(&properties, height * width, lidar.is_some())
+        .map_err(|error| error.to_string())?;
+    let mut values = vec![0.0; properties.len()];
+    (0..height).try_for_each(|row| {
+        (0..width).try_for_each(|column| {
+            let start = row * row_step + column * point_step;
+            values
+                .iter_mut()
+                .zip(&fields)
+                .try_for_each(|(value, (property, offset))| {
+                    let start = start + offset;
+                    let bytes = data
+                        .get(start..start + property.ty.size())
+                        .ok_or("the point data is truncated")?;
+                    *value = property.ty.decode(bytes, big_endian);
+                    Ok::<_, String>(())
+                })?;
+            builder.push(&values);
+            Ok::<_, String>(())
+        })
+    })?;
+    let points = builder.cloud.into_raw_points();
+    Ok(preprocess::decode(lidar, timestamp, &points))
+}
+
+/// The points are decoded like the ones of an Avia, the time offsets are in ns for every Livox.
+fn livox_custom(decoder: &mut Decoder) -> Result<LidarScan, String> {
+    let timestamp = decoder.header()?;
+    let _timebase = decoder.u64()?;
+    let _point_num = decoder.u32()?;
+    let _lidar_id = decoder.u8()?;
+    decoder.bytes(3)?;
+    let points = (0..decoder.u32()?)
+        .map(|_| {
+            let time = decoder.u32()? as f64;
+            let mut coordinate = || decoder.u32().map(f32::from_bits);
+            let point = Vector3::new(coordinate()?, coordinate()?, coordinate()?).cast();
+            Ok(RawPoint {
+                point: point.into(),
+                time,
+                intensity: decoder.u8()? as f32,
+                tag: decoder.u8()?,
+                ring: decoder.u8()? as u16,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(preprocess::decode(Some(Lidar::Avia), timestamp, &points))
+}
+
+fn image(decoder: &mut Decoder) -> Result<CameraImage, String> {
+    let timestamp = decoder.header()?;
+    let height = decoder.u32()? as usize;
+    let width = decoder.u32()? as usize;
+    let encoding = decoder.string()?;
+    let big_endian = decoder.u8()? != 0;
+    let step = decoder.u32()? as usize;
+    let data = decoder.byte_sequence()?;
+
+    // the byte offsets of the red, green and blue channels, and the pixel size
+    let (channels, pixel_size): (Option<[usize; 3]>, _) = match encoding {
+        "mono8" | "8UC1" => (None, 1),
+        "mono16" | "16UC1" => (None, 2),
+        "rgb8" => (Some([0, 1, 2]), 3),
+        "bgr8" => (Some([2, 1, 0]), 3),
+        "rgba8" => (Some([0, 1, 2]), 4),
+        "bgra8" => (Some([2, 1, 0]), 4),
+        _ => return Err(format!("unsupported image encoding {encoding}")),
+    };
+    if data.len() < step * height || step < width * pixel_size {
+        return Err("the image data is truncated".into());
+    }
+    let pixels = (0..height)
+        .flat_map(|row| (0..width).map(move |column| row * step + column * pixel_size))
+        .map(|start| {
+            let pixel = &data[start..start + pixel_size];
+            match (channels, pixel_size) {
+                (Some([red, green, blue]), _) => gray(pixel[red], pixel[green], pixel[blue]),
+                // the most significant byte of the 16-bit values
+                (None, 2) => pixel[if big_endian { 0 } else { 1 }],
+                (None, _) => pixel[0],
+            }
+        })
+        .collect();
+    let image = GrayImage::new(ImageSize { width, height }, pixels, CpuAllocator)
+        .map_err(|error| error.to_string())?;
+    Ok(CameraImage { timestamp, image })
+}
+
+fn compressed_image(decoder: &mut Decoder) -> Result<CameraImage, String> {
+    let timestamp = decoder.header()?;
+    let _format = decoder.string()?;
+    let data = decoder.byte_sequence()?;
+    let image = if data.starts_with(&[0xff, 0xd8]) {
+        let layout = jpeg::decode_image_jpeg_layout(data).map_err(|error| error.to_string())?;
+        let mut image = GrayImage::from_size_val(layout.image_size, 0, CpuAllocator)
+            .map_err(|error| error.to_string())?;
+        jpeg::decode_image_jpeg_mono8(data, &mut image).map_err(|error| error.to_string())?;
+        image
+    } else if data.starts_with(b"\x89PNG") {
+        decode_png(data).map_err(|error| error.to_string())?
+    } else {
+        return Err("the compressed image is neither jpeg nor png".into());
+    };
+    Ok(CameraImage { timestamp, image })
+}
+
+fn decode_png(data: &[u8]) -> Result<GrayImage, Box<dyn std::error::Error>> {
+    let layout = png::decode_image_png_layout(data)?;
+    let size = layout.image_size;
+    let image = match layout.channels {
+        1 => {
+            let mut gray = Gray8::from_size_val(size, 0, CpuAllocator)?;
+            png::decode_image_png_mono8(data, &mut gray)?;
+            gray.into_inner()
+        }
+        3 => {
+            let mut rgb = Rgb8::from_size_val(size, 0, CpuAllocator)?;
+            png::decode_image_png_rgb8(data, &mut rgb)?;
+            to_gray(rgb.as_slice(), 3, size)?
+        }
+        4 => {
+            let mut rgba = Rgba8::from_size_val(size, 0, CpuAllocator)?;
+            png::decode_image_png_rgba8(data, &mut rgba)?;
+            to_gray(rgba.as_slice(), 4, size)?
+        }
+        channels => return Err(format!("unsupported png with {channels} channels").into()),
+    };
+    Ok(image)
+}
+
+fn to_gray(
+    data: &[u8],
+    channels: usize,
+    size: ImageSize,
+) -> Result<GrayImage, kornia::image::ImageError> {
+    let pixels = data
+        .chunks_exact(channels)
+        .map(|pixel| gray(pixel[0], pixel[1], pixel[2]))
+        .collect();
+    GrayImage::new(size, pixels, CpuAllocator)
+}
+
+/// The luma of ITU-R BT.601, like OpenCV.
+fn gray(red: u8, green: u8, blue: u8) -> u8 {
+    ((299 * red as u32 + 587 * green as u32 + 114 * blue as u32 + 500) / 1000) as u8
+}
+
+/// The `sensor_msgs/PointField` datatypes.
+fn point_field_type(datatype: u8) -> Option<ScalarType> {
+    match datatype {
+        1 => Some(ScalarType::I8),
+        2 => Some(ScalarType::U8),
+        3 => Some(ScalarType::I16),
+        4 => Some(ScalarType::U16),
+        5 => Some(ScalarType::I32),
+        6 => Some(ScalarType::U32),
+        7 => Some(ScalarType::F32),
+        8 => Some(ScalarType::F64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Serializes the messages like ROS 2, in little endian CDR.
+    pub(crate) struct Encoder {
+        pub(crate) data: Vec<u8>,
+    }
+
+    impl Encoder {
+        pub(crate) fn new() -> Self {
+            Self {
+                data: vec![0, 1, 0, 0],
+            }
+        }
+
+        fn align(&mut self, size: usize) {
+            while !(self.data.len() - 4).is_multiple_of(size) {
+                self.data.push(0);
+            }
+        }
+
+        pub(crate) fn u8(&mut self, value: u8) -> &mut Self {
+            self.data.push(value);
+            self
+        }
+
+        pub(crate) fn u32(&mut self, value: u32) -> &mut Self {
+            self.align(4);
+            self.data.extend(value.to_le_bytes());
+            self
+        }
+
+        pub(crate) fn f32(&mut self, value: f32) -> &mut Self {
+            self.u32(value.to_bits())
+        }
+
+        pub(crate) fn u64(&mut self, value: u64) -> &mut Self {
+            self.align(8);
+            self.data.extend(value.to_le_bytes());
+            self
+        }
+
+        pub(crate) fn f64(&mut self, value: f64) -> &mut Self {
+            self.u64(value.to_bits())
+        }
+
+        pub(crate) fn string(&mut self, value: &str) -> &mut Self {
+            self.u32(value.len() as u32 + 1);
+            self.data.extend(value.as_bytes());
+            self.u8(0)
+        }
+
+        pub(crate) fn header(&mut self, seconds: u32, nanoseconds: u32) -> &mut Self {
+            self.u32(seconds).u32(nanoseconds).string("frame")
+        }
+    }
+
+    pub(crate) fn imu_message(seconds: u32, nanoseconds: u32) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.header(seconds, nanoseconds);
+        (0..13).for_each(|_| {
+            encoder.f64(0.0);
+        });
+        encoder.f64(0.1).f64(0.2).f64(0.3);
+        (0..9).for_each(|_| {
+            encoder.f64(0.0);
+        });
+        encoder.f64(0.0).f64(0.0).f64(9.81);
+        (0..9).for_each(|_| {
+            encoder.f64(0.0);
+        });
+        encoder.data
+    }
+
+    /// A velodyne cloud stamped at the scan end, with its x, y, z, intensity, ring and time fields.
+    pub(crate) fn velodyne_message(seconds: u32) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.header(seconds, 0).u32(1).u32(2).u32(6);
+        [("x", 0, 7), ("y", 4, 7), ("z", 8, 7), ("intensity", 12, 7)]
+            .into_iter()
+            .chain([("ring", 16, 4), ("time", 18, 7)])
+            .for_each(|(name, offset, datatype)| {
+                encoder.string(name).u32(offset).u8(datatype).u32(1);
+            });
+        encoder.u8(0).u32(24).u32(48).u32(48);
+        [(1.0f32, -0.1f32), (2.0, 0.0)]
+            .into_iter()
+            .for_each(|(x, time)| {
+                [x, 0.5, -0.5, 10.0].iter().for_each(|value| {
+                    encoder.data.extend(value.to_le_bytes());
+                });
+                encoder.data.extend(3u16.to_le_bytes());
+                encoder.data.extend(time.to_le_bytes());
+                encoder.data.extend([0, 0]);
+            });
+        encoder.u8(1);
+        encoder.data
+    }
+
+    /// A Hesai cloud with the absolute times of its points in s, stamped at `seconds`.
+    pub(crate) fn hesai_message(seconds: u32, times: [f64; 2]) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        encoder.header(seconds, 0).u32(1).u32(2).u32(6);
+        [("x", 0, 7), ("y", 4, 7), ("z", 8, 7), ("intensity", 12, 7)]
+            .into_iter()
+            .chain([("timestamp", 16, 8), ("ring", 24, 4)])
+            .for_each(|(name, offset, datatype)| {
+                encoder.string(name).u32(offset).u8(datatype).u32(1);
+            });
+        encoder.u8(0).u32(32).u32(64).u32(64);
+        times.into_iter().for_each(|time| {
+            [1.0f32, 0.5, -0.5, 10.0].iter().for_each(|value| {
+                encoder.data.extend(value.to_le_bytes());
+            });
+            encoder.data.extend(time.to_le_bytes());
+            encoder.data.extend(5u16.to_le_bytes());
+            encoder.data.extend([0; 6]);
+        });
+        encoder.u8(1);
+        encoder.data
+    }
+
+    #[test]
+    fn decode_messages() {
+        let SensorMessage::Imu(sample) = MessageType::Imu
+            .decode(Serialization::Cdr, &imu_message(10, 500_000_000), None)
+            .unwrap()
+        else {
+            panic!("expected an imu sample");
+        };
+        assert_eq!(sample.timestamp, 10.5);
+        assert_eq!(sample.angular_velocity, Vector3::new(0.1, 0.2, 0.3));
+        assert_eq!(sample.linear_acceleration.z, 9.81);
+
+        let SensorMessage::Lidar(scan) = MessageType::PointCloud2
+            .decode(Serialization::Cdr, &velodyne_message(20), None)
+            .unwrap()
+        else {
+            panic!("expected a lidar scan");
+        };
+        assert!((scan.timestamp - 19.9).abs() < 1e-6);
+        assert_eq!(scan.points.len(), 2);
+        assert!((scan.points[1].offset_time - 0.1).abs() < 1e-6);
+        assert_eq!(scan.points[1].point.x, 2.0);
+        assert_eq!(scan.points[0].ring, 3);
+        assert_eq!(scan.points[0].intensity, 10.0);
+
+        let mut encoder = Encoder::new();
+        encoder
+            .header(1, 0)
+            .u64(0)
+            .u32(1)
+            .u8(0)
+            .u8(0)
+            .u8(0)
+            .u8(0)
+            .u32(1);
+        encoder
+            .u32(1_000_000)
+            .f32(1.0)
+            .f32(2.0)
+            .f32(3.0)
+            .u8(50)
+            .u8(0x10)
+            .u8(4);
+        let SensorMessage::Lidar(scan) = MessageType::LivoxCustom
+            .decode(Serialization::Cdr, &encoder.data, None)
+            .unwrap()
+        else {
+            panic!("expected a lidar scan");
+        };
+        assert_eq!(scan.points[0].offset_time, 0.001);
+        assert_eq!(scan.points[0].point.z, 3.0);
+        assert_eq!(scan.points[0].ring, 4);
+
+        let mut encoder = Encoder::new();
+        encoder
+            .header(2, 0)
+            .u32(1)
+            .u32(2)
+            .string("bgr8")
+            .u8(0)
+            .u32(6)
+            .u32(6);
+        encoder.data.extend([255, 0, 0, 0, 0, 255]);
+        let SensorMessage::Camera(image) = MessageType::Image
+            .decode(Serialization::Cdr, &encoder.data, None)
+            .unwrap()
+        else {
+            panic!("expected an image");
+        };
+        assert_eq!(image.image.as_slice(), &[29, 76]);
+    }
+}
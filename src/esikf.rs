@@ -1,47 +1,249 @@
 //! Implementation of Error-State Iterated Kalman Filter
 
-use std::ops::Deref;
-
 use crate::{
-    frame::{Framed, Imu, World},
-    uncertain::Uncertainty3,
+    config::{ConfigError, ensure},
+    frame::{Body, Framed, FramedIsometry, Imu, World},
+    imu,
+    uncertain::{Uncertainty, Uncertainty1, Uncertainty3},
+    vio,
 };
-use nalgebra::{IsometryMatrix3, Rotation3, Vector3, Vector6, stack};
+use nalgebra::{IsometryMatrix3, OMatrix, OVector, Rotation3, U1, Vector3, Vector6, stack};
 use rust_livo2_macros::uncertainties;
+use serde::Deserialize;
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    max_iterations: u32,
+    pub max_iterations: u32,
+    /// the iteration is converged when the rotation steps of the pose and the extrinsic are less than this, in rad
+    pub rotation_threshold: f64,
+    /// and the translation steps are less than this, in m
+    pub translation_threshold: f64,
+}
+
+impl Config {
+    pub(crate) fn validate(&self, section: &str) -> Result<(), ConfigError> {
+        let positive = "should be positive";
+        ensure(self.max_iterations > 0, section, "max_iterations", positive)?;
+        ensure(
+            self.rotation_threshold > 0.0,
+            section,
+            "rotation_threshold",
+            positive,
+        )?;
+        ensure(
+            self.translation_threshold > 0.0,
+            section,
+            "translation_threshold",
+            positive,
+        )
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_iterations: 5,
+            rotation_threshold: 0.01_f64.to_radians(),
+            translation_threshold: 0.015e-2,
+        }
+    }
 }
 
+#[derive(Debug, Clone)]
 pub struct UncertainOdometer {
     /// estimated isometry, from imu frame to world frame
     pub isometry: Framed<IsometryMatrix3<f64>, fn(Imu) -> World>,
+    /// estimated imu state
+    pub imu: imu::State,
+    /// estimated visual state
+    pub vio: vio::State,
+    /// estimated extrinsic, from body frame to imu frame
+    pub body_to_imu: FramedIsometry<f64, Body, Imu>,
     /// odometer covariance
     pub covariance: OdometerUncertainties,
 }
 
+/// The error state of the odometer, in the same order as FAST-LIVO2,
+/// followed by the lidar-imu extrinsic, the camera-imu extrinsic and the camera time offset.
 #[uncertainties]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct OdometerUncertainties {
     pub rotation: RotationUncertainty,
     pub translation: TranslationUncertainty,
+    pub inverse_exposure_time: InverseExposureTimeUncertainty,
+    pub velocity: VelocityUncertainty,
+    pub bias_gyro: BiasUncertainty,
+    pub bias_acc: BiasUncertainty,
+    pub gravity: GravityUncertainty,
+    pub body_to_imu_rotation: RotationUncertainty,
+    pub body_to_imu_translation: TranslationUncertainty,
+    pub camera_to_imu_rotation: RotationUncertainty,
+    pub camera_to_imu_translation: TranslationUncertainty,
+    pub time_offset: TimeOffsetUncertainty,
 }
 
 type RotationUncertainty = Uncertainty3<f64>;
 type TranslationUncertainty = Uncertainty3<f64>;
+type InverseExposureTimeUncertainty = Uncertainty1<f64>;
+type VelocityUncertainty = Uncertainty3<f64>;
+type BiasUncertainty = Uncertainty3<f64>;
+type GravityUncertainty = Uncertainty3<f64>;
+type TimeOffsetUncertainty = Uncertainty1<f64>;
+
+/// Dimension of the odometer error state.
+pub type StateDim = <OdometerUncertainties as Uncertainty>::Dim;
+/// A vector in the odometer error state space.
+pub type StateVector = OVector<f64, StateDim>;
+/// A square matrix in the odometer error state space, e.g. the state transition matrix.
+pub type StateMatrix = OMatrix<f64, StateDim, StateDim>;
+/// The jacobian of a scalar measurement w.r.t. the odometer error state.
+pub type MeasurementJacobian = OMatrix<f64, U1, StateDim>;
+
+/// A scalar measurement for the iterated update.
+pub struct Measurement {
+    /// the observed value minus the predicted value
+    pub residual: f64,
+    /// the jacobian of the predicted value w.r.t. the odometer error state
+    pub jacobian: MeasurementJacobian,
+    /// the variance of the residual
+    pub variance: f64,
+}
 
 impl UncertainOdometer {
+    pub fn new(
+        isometry: Framed<IsometryMatrix3<f64>, fn(Imu) -> World>,
+        imu: imu::State,
+        vio: vio::State,
+        body_to_imu: FramedIsometry<f64, Body, Imu>,
+        covariance: OdometerUncertainties,
+    ) -> Self {
+        Self {
+            isometry,
+            imu,
+            vio,
+            body_to_imu,
+            covariance,
+        }
+    }
+
     pub fn rotation(&self) -> Rotation3<f64> {
         self.isometry.rotation
     }
 
+    /// Apply the error state `delta` to this odometer.
+    pub fn boxplus(&mut self, delta: &StateVector) {
+        type State = OdometerUncertainties;
+        let vector3 = |index| delta.fixed_rows::<3>(index).into_owned();
+
+        self.isometry.rotation *= Rotation3::from_scaled_axis(vector3(State::ROTATION_INDEX));
+        self.isometry.translation.vector += vector3(State::TRANSLATION_INDEX);
+        self.vio.inverse_exposure_time += delta[State::INVERSE_EXPOSURE_TIME_INDEX];
+        self.imu.velocity += vector3(State::VELOCITY_INDEX);
+        self.imu.bias_gyro += vector3(State::BIAS_GYRO_INDEX);
+        self.imu.bias_acc += vector3(State::BIAS_ACC_INDEX);
+        self.imu.gravity += vector3(State::GRAVITY_INDEX);
+        self.body_to_imu.rotation *=
+            Rotation3::from_scaled_axis(vector3(State::BODY_TO_IMU_ROTATION_INDEX));
+        self.body_to_imu.translation.vector += vector3(State::BODY_TO_IMU_TRANSLATION_INDEX);
+        self.vio.camera_to_imu.rotation *=
+            Rotation3::from_scaled_axis(vector3(State::CAMERA_TO_IMU_ROTATION_INDEX));
+        self.vio.camera_to_imu.translation.vector +=
+            vector3(State::CAMERA_TO_IMU_TRANSLATION_INDEX);
+        self.vio.time_offset += delta[State::TIME_OFFSET_INDEX];
+    }
+
+    /// The error state from `other` to this odometer.
+    pub fn boxminus(&self, other: &Self) -> StateVector {
+        type State = OdometerUncertainties;
+        let mut delta = StateVector::zeros();
+        let mut set_rows = |index, vector: Vector3<f64>| {
+            delta.fixed_rows_mut::<3>(index).copy_from(&vector);
+        };
+        let diff_vector = self.diff_vector(other);
+
+        set_rows(State::ROTATION_INDEX, diff_vector.fixed_rows::<3>(0).into());
+        set_rows(
+            State::TRANSLATION_INDEX,
+            diff_vector.fixed_rows::<3>(3).into(),
+        );
+        set_rows(
+            State::VELOCITY_INDEX,
+            self.imu.velocity - other.imu.velocity,
+        );
+        set_rows(
+            State::BIAS_GYRO_INDEX,
+            self.imu.bias_gyro - other.imu.bias_gyro,
+        );
+        set_rows(
+            State::BIAS_ACC_INDEX,
+            self.imu.bias_acc - other.imu.bias_acc,
+        );
+        set_rows(State::GRAVITY_INDEX, self.imu.gravity - other.imu.gravity);
+        set_rows(
+            State::BODY_TO_IMU_ROTATION_INDEX,
+            (other.body_to_imu.rotation.transpose() * self.body_to_imu.rotation).scaled_axis(),
+        );
+        set_rows(
+            State::BODY_TO_IMU_TRANSLATION_INDEX,
+            self.body_to_imu.translation.vector - other.body_to_imu.translation.vector,
+        );
+        set_rows(
+            State::CAMERA_TO_IMU_ROTATION_INDEX,
+            (other.vio.camera_to_imu.rotation.transpose() * self.vio.camera_to_imu.rotation)
+                .scaled_axis(),
+        );
+        set_rows(
+            State::CAMERA_TO_IMU_TRANSLATION_INDEX,
+            self.vio.camera_to_imu.translation.vector - other.vio.camera_to_imu.translation.vector,
+        );
+        delta[State::TIME_OFFSET_INDEX] = self.vio.time_offset - other.vio.time_offset;
+        delta[State::INVERSE_EXPOSURE_TIME_INDEX] =
+            self.vio.inverse_exposure_time - other.vio.inverse_exposure_time;
+        delta
+    }
+
+    /// Iterated update of the odometer with the measurements built by `measurement_model`,
+    /// which will be rebuilt with the updated odometer in each iteration.
+    ///
+    /// Returns `true` if the iteration is converged.
+    pub fn iterated_update<F, I>(&mut self, config: &Config, measurement_model: F) -> bool
+    where
+        F: FnMut(&Self) -> I,
+        I: IntoIterator<Item = Measurement>,
+    {
+        let Some(last) = self.iterate(config, measurement_model).last() else {
+            return false;
+        };
+        *self = last.odometer;
+        last.converged
+    }
+
+    /// Like [`Self::iterated_update`], but yields the estimate of every iteration,
+    /// so that the caller can stop early or inspect the convergence.
+    pub fn iterate<'a, F, I>(
+        &self,
+        config: &'a Config,
+        measurement_model: F,
+    ) -> IteratedUpdate<'a, F>
+    where
+        F: FnMut(&Self) -> I,
+        I: IntoIterator<Item = Measurement>,
+    {
+        IteratedUpdate {
+            config,
+            prior: self.clone(),
+            current: self.clone(),
+            measurement_model,
+            iteration: 0,
+            finished: false,
+        }
+    }
+
     pub fn diff_vector(&self, other: &Self) -> Vector6<f64> {
         let rotation = other.isometry.rotation.transpose() * self.isometry.rotation;
         let translation = self.isometry.translation.vector - other.isometry.translation.vector;
-        let rotation = rotation
-            .axis_angle()
-            .map(|(axis, angle)| axis.deref() * angle * angle / angle.sin())
-            .unwrap_or(Vector3::zeros());
+        let rotation = rotation.scaled_axis();
 
         #[expect(clippy::toplevel_ref_arg)]
         {
@@ -50,4 +252,214 @@ impl UncertainOdometer {
     }
 }
 
-pub trait KalmanFilterIterator: Iterator<Item = UncertainOdometer> {}
+/// The estimate of an iteration in the iterated update.
+#[derive(Debug, Clone)]
+pub struct Iteration {
+    /// the updated odometer, with the posterior covariance
+    pub odometer: UncertainOdometer,
+    /// the norm of the residuals before this iteration
+    pub residual_norm: f64,
+    /// the norm of the error state step of this iteration
+    pub step_norm: f64,
+    /// the number of measurements used in this iteration
+    pub measurements_count: usize,
+    /// whether the iteration is converged
+    pub converged: bool,
+}
+
+pub trait KalmanFilterIterator: Iterator<Item = Iteration> {}
+
+/// The iterator of the iterated update, see [`UncertainOdometer::iterate`].
+///
+/// It stops after the iteration is converged, the maximum iterations is reached,
+/// or there is no measurement.
+pub struct IteratedUpdate<'a, F> {
+    config: &'a Config,
+    prior: UncertainOdometer,
+    current: UncertainOdometer,
+    measurement_model: F,
+    iteration: u32,
+    finished: bool,
+}
+
+impl<F, I> Iterator for IteratedUpdate<'_, F>
+where
+    F: FnMut(&UncertainOdometer) -> I,
+    I: IntoIterator<Item = Measurement>,
+{
+    type Item = Iteration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        type State = OdometerUncertainties;
+
+        if self.finished || self.iteration >= self.config.max_iterations {
+            return None;
+        }
+        self.iteration += 1;
+
+        let (information, information_residual, residual_square_sum, measurements_count) =
+            (self.measurement_model)(&self.current).into_iter().fold(
+                (StateMatrix::zeros(), StateVector::zeros(), 0.0, 0_usize),
+                |(information, information_residual, residual_square_sum, count), measurement| {
+                    let weighted = measurement.jacobian.transpose() / measurement.variance;
+                    (
+                        information + weighted * measurement.jacobian,
+                        information_residual + weighted * measurement.residual,
+                        residual_square_sum + measurement.residual.powi(2),
+                        count + 1,
+                    )
+                },
+            );
+        if measurements_count == 0 {
+            self.finished = true;
+            return None;
+        }
+
+        let prior_covariance = *self.prior.covariance;
+        // (H^T * R^-1 * H + P^-1)^-1 = (P * H^T * R^-1 * H + I)^-1 * P
+        let Some(gain) = (prior_covariance * information + StateMatrix::identity())
+            .try_inverse()
+            .map(|inverse| inverse * prior_covariance)
+        else {
+            self.finished = true;
+            return None;
+        };
+        let gain_jacobian = gain * information;
+        let prior_delta = self.prior.boxminus(&self.current);
+        let step = gain * information_residual + prior_delta - gain_jacobian * prior_delta;
+
+        self.current.boxplus(&step);
+        self.current.covariance =
+            ((StateMatrix::identity() - gain_jacobian) * prior_covariance).into();
+
+        let below = |index, threshold| step.fixed_rows::<3>(index).norm() < threshold;
+        let converged = [
+            State::ROTATION_INDEX,
+            State::BODY_TO_IMU_ROTATION_INDEX,
+            State::CAMERA_TO_IMU_ROTATION_INDEX,
+        ]
+        .into_iter()
+        .all(|index| below(index, self.config.rotation_threshold))
+            && [
+                State::TRANSLATION_INDEX,
+                State::BODY_TO_IMU_TRANSLATION_INDEX,
+                State::CAMERA_TO_IMU_TRANSLATION_INDEX,
+            ]
+            .into_iter()
+            .all(|index| below(index, self.config.translation_threshold));
+        self.finished = converged;
+
+        Some(Iteration {
+            odometer: self.current.clone(),
+            residual_norm: residual_square_sum.sqrt(),
+            step_norm: step.norm(),
+            measurements_count,
+            converged,
+        })
+    }
+}
+
+impl<F, I> KalmanFilterIterator for IteratedUpdate<'_, F>
+where
+    F: FnMut(&UncertainOdometer) -> I,
+    I: IntoIterator<Item = Measurement>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    fn odometer() -> UncertainOdometer {
+        UncertainOdometer::new(
+            IsometryMatrix3::new(Vector3::new(1.0, 2.0, 3.0), Vector3::new(0.3, -0.2, 0.1)).into(),
+            imu::State::default(),
+            vio::State::default(),
+            IsometryMatrix3::translation(0.1, 0.0, 0.0).into(),
+            StateMatrix::from_diagonal_element(1e-2).into(),
+        )
+    }
+
+    /// Measures the translation directly, a linear measurement model.
+    fn translation_measurements(
+        target: Vector3<f64>,
+    ) -> impl FnMut(&UncertainOdometer) -> Vec<Measurement> {
+        move |odometer| {
+            let translation = odometer.isometry.translation.vector;
+            (0..3)
+                .map(|axis| {
+                    let mut jacobian = MeasurementJacobian::zeros();
+                    jacobian[OdometerUncertainties::TRANSLATION_INDEX + axis] = 1.0;
+                    Measurement {
+                        residual: target[axis] - translation[axis],
+                        jacobian,
+                        variance: 1e-6,
+                    }
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn boxplus_boxminus_round_trip() {
+        let odometer = odometer();
+        // the large rotations check the logarithm far from the identity
+        let delta = StateVector::from_fn(|index, _| (index as f64 * 0.7).sin() * 1.5);
+        let mut moved = odometer.clone();
+        moved.boxplus(&delta);
+        let difference = moved.boxminus(&odometer);
+        assert!((difference - delta).amax() < 1e-9, "{difference}");
+        assert!(odometer.boxminus(&odometer).amax() < 1e-12);
+    }
+
+    #[test]
+    fn converges_on_linear_measurement() {
+        let mut odometer = odometer();
+        let target = Vector3::new(1.5, 1.0, 3.2);
+        let converged =
+            odometer.iterated_update(&Config::default(), translation_measurements(target));
+        assert!(converged);
+        // the measurements are far more certain than the prior
+        assert!((odometer.isometry.translation.vector - target).norm() < 1e-3);
+        let variance = odometer.covariance.view_translation()[(0, 0)];
+        assert!(variance < 1e-5, "{variance}");
+        // the states not measured are kept
+        assert_eq!(odometer.rotation(), self::odometer().rotation());
+    }
+
+    #[test]
+    fn iteration_diagnostics() {
+        let odometer = odometer();
+        let config = Config::default();
+        let target = Vector3::new(1.5, 1.0, 3.2);
+        let iterations: Vec<_> = odometer
+            .iterate(&config, translation_measurements(target))
+            .collect();
+        // the first step solves the linear measurement, the second one is below the thresholds
+        assert_eq!(iterations.len(), 2);
+        let [first, second] = &iterations[..] else {
+            unreachable!()
+        };
+        assert!(!first.converged && second.converged);
+        assert_eq!(first.measurements_count, 3);
+        assert!(
+            (first.residual_norm - (target - Vector3::new(1.0, 2.0, 3.0)).norm()).abs() < 1e-12
+        );
+        assert!(second.residual_norm < 1e-3 && second.step_norm < first.step_norm);
+
+        let config = Config {
+            max_iterations: 1,
+            ..config
+        };
+        let iterations: Vec<_> = odometer
+            .iterate(&config, translation_measurements(target))
+            .collect();
+        assert_eq!(iterations.len(), 1);
+        assert!(!iterations[0].converged);
+
+        // no measurement, no iteration
+        assert_eq!(odometer.iterate(&config, |_| Vec::new()).count(), 0);
+    }
+}
@@ -0,0 +1,460 @@
+//! The evaluation of an estimated trajectory against its ground truth, like evo and
+//! rpg_trajectory_evaluation.
+//!
+//! The poses are associated by their timestamps, the estimated trajectory is aligned to the
+//! ground truth by the method of Umeyama, then the absolute trajectory error (ATE) of the positions
+//! and the relative pose error (RPE) over segments of traveled distance are computed.
+use std::{fmt, path::Path};
+
+use nalgebra::{IsometryMatrix3, Matrix3, Rotation3, SimilarityMatrix3, Translation3, Vector3};
+
+use crate::{
+    dataset::DatasetError,
+    trajectory::{Format, Trajectory},
+};
+
+/// The transform estimated to align the trajectories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    /// a rotation and a translation
+    Se3,
+    /// a rotation, a translation and a scale, like for a monocular odometry
+    Sim3,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub alignment: Alignment,
+    /// the maximum time difference of the associated poses, in s
+    pub max_time_difference: f64,
+    /// the traveled distances of the relative errors, in m
+    pub segment_lengths: Vec<f64>,
+}
+
+impl Default for Config {
+    /// The segments of the KITTI odometry benchmark.
+    fn default() -> Self {
+        Self {
+            alignment: Alignment::Se3,
+            max_time_difference: 0.01,
+            segment_lengths: vec![100.0, 200.0, 300.0, 400.0, 500.0, 600.0, 700.0, 800.0],
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EvalError {
+    #[error("failed to load a trajectory: {0}")]
+    Load(#[from] DatasetError),
+    #[error("only {found} poses are associated, at least 3 are needed for the alignment")]
+    TooFewPairs { found: usize },
+    #[error("the associated estimated positions are all the same, they can not be aligned")]
+    Degenerate,
+}
+
+/// The statistics of the errors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statistics {
+    pub count: usize,
+    pub rmse: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// the standard deviation
+    pub std: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Statistics {
+    /// The statistics of the errors, `None` if there are none.
+    pub fn new(errors: &[f64]) -> Option<Self> {
+        if errors.is_empty() {
+            return None;
+        }
+        let count = errors.len();
+        let mean = errors.iter().sum::<f64>() / count as f64;
+        let squared = errors.iter().map(|error| error * error).sum::<f64>() / count as f64;
+        let mut sorted = errors.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let median = match count % 2 {
+            0 => (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0,
+            _ => sorted[count / 2],
+        };
+        Some(Self {
+            count,
+            rmse: squared.sqrt(),
+            mean,
+            median,
+            std: (squared - mean * mean).max(0.0).sqrt(),
+            min: sorted[0],
+            max: sorted[count - 1],
+        })
+    }
+}
+
+/// The relative errors over the segments of a length.
+#[derive(Debug, Clone)]
+pub struct SegmentErrors {
+    /// the traveled distance of the segments, in m
+    pub length: f64,
+    /// the translation errors, in m
+    pub translation: Statistics,
+    /// the translation errors over the length, in %, like the KITTI benchmark
+    pub translation_percent: Statistics,
+    /// the rotation errors, in degrees
+    pub rotation: Statistics,
+}
+
+#[derive(Debug, Clone)]
+pub struct Evaluation {
+    /// the transform from the estimated trajectory to the ground truth
+    pub alignment: SimilarityMatrix3<f64>,
+    /// the number of associated poses
+    pub pairs: usize,
+    /// the position errors of the aligned poses, in m
+    pub ate: Statistics,
+    /// the relative errors of the segment lengths, the lengths longer than the ground truth are
+    /// skipped
+    pub rpe: Vec<SegmentErrors>,
+}
+
+/// Load the trajectories of the files and evaluate them.
+pub fn evaluate_paths(
+    estimated: impl AsRef<Path>,
+    estimated_format: Format,
+    ground_truth: impl AsRef<Path>,
+    ground_truth_format: Format,
+    config: &Config,
+) -> Result<Evaluation, EvalError> {
+    let estimated = Trajectory::from_path(estimated, estimated_format)?;
+    let ground_truth = Trajectory::from_path(ground_truth, ground_truth_format)?;
+    evaluate(&estimated, &ground_truth, config)
+}
+
+pub fn evaluate(
+    estimated: &Trajectory,
+    ground_truth: &Trajectory,
+    config: &Config,
+) -> Result<Evaluation, EvalError> {
+    let pairs = associate(estimated, ground_truth, config.max_time_difference);
+    if pairs.len() < 3 {
+        return Err(EvalError::TooFewPairs { found: pairs.len() });
+    }
+    let (aligned, reference): (Vec<_>, Vec<_>) = pairs
+        .iter()
+        .map(|&(estimated_index, ground_truth_index)| {
+            (
+                estimated.poses[estimated_index].pose,
+                ground_truth.poses[ground_truth_index].pose,
+            )
+        })
+        .unzip();
+    let positions = |poses: &[IsometryMatrix3<f64>]| -> Vec<_> {
+        poses.iter().map(|pose| pose.translation.vector).collect()
+    };
+    let alignment = umeyama(
+        &positions(&aligned),
+        &positions(&reference),
+        config.alignment == Alignment::Sim3,
+    )
+    .ok_or(EvalError::Degenerate)?;
+    let aligned: Vec<_> = aligned
+        .iter()
+        .map(|pose| {
+            let rotation = alignment.isometry.rotation * pose.rotation;
+            let translation = alignment.transform_point(&pose.translation.vector.into());
+            IsometryMatrix3::from_parts(Translation3::from(translation.coords), rotation)
+        })
+        .collect();
+
+    let errors: Vec<_> = aligned
+        .iter()
+        .zip(&reference)
+        .map(|(aligned, reference)| {
+            (aligned.translation.vector - reference.translation.vector).norm()
+        })
+        .collect();
+    let ate = Statistics::new(&errors).expect("there are at least 3 pairs");
+    let rpe = config
+        .segment_lengths
+        .iter()
+        .filter_map(|&length| segment_errors(&aligned, &reference, length))
+        .collect();
+    Ok(Evaluation {
+        alignment,
+        pairs: pairs.len(),
+        ate,
+        rpe,
+    })
+}
+
+/// The indices of the estimated poses and of the nearest ground truth poses within
+/// `max_time_difference`, each ground truth pose is associated once.
+pub fn associate(
+    estimated: &Trajectory,
+    ground_truth: &Trajectory,
+    max_time_difference: f64,
+) -> Vec<(usize, usize)> {
+    let times: Vec<_> = ground_truth
+        .poses
+        .iter()
+        .map(|pose| pose.timestamp)
+        .collect();
+    let mut previous = None;
+    estimated
+        .poses
+        .iter()
+        .enumerate()
+        .filter_map(|(index, pose)| {
+            let after = times.partition_point(|&time| time < pose.timestamp);
+            let nearest = [after.checked_sub(1), Some(after)]
+                .into_iter()
+                .flatten()
+                .filter(|&candidate| candidate < times.len())
+                .min_by(|&a, &b| {
+                    let difference = |candidate: usize| (times[candidate] - pose.timestamp).abs();
+                    difference(a).total_cmp(&difference(b))
+                })?;
+            let associated = (times[nearest] - pose.timestamp).abs() <= max_time_difference
+                && previous != Some(nearest);
+            associated.then(|| {
+                previous = Some(nearest);
+                (index, nearest)
+            })
+        })
+        .collect()
+}
+
+/// The similarity transform minimizing the squared distances from the transformed `source` to
+/// `target`, the scale is 1 without `with_scale`.
+///
+/// See Umeyama, "Least-squares estimation of transformation parameters between two point
+/// patterns", 1991. `None` if the point counts differ or the source points are all the same.
+pub fn umeyama(
+    source: &[Vector3<f64>],
+    target: &[Vector3<f64>],
+    with_scale: bool,
+) -> Option<SimilarityMatrix3<f64>> {
+    if source.len() != target.len() {
+        return None;
+    }
+    let count = source.len() as f64;
+    let mean = |points: &[Vector3<f64>]| points.iter().sum::<Vector3<f64>>() / count;
+    let (source_mean, target_mean) = (mean(source), mean(target));
+    let source_variance = source
+        .iter()
+        .map(|point| (point - source_mean).norm_squared())
+        .sum::<f64>()
+        / count;
+    if source_variance < f64::EPSILON {
+        return None;
+    }
+    let covariance = source
+        .iter()
+        .zip(target)
+        .map(|(source, target)| (target - target_mean) * (source - source_mean).transpose())
+        .sum::<Matrix3<f64>>()
+        / count;
+    let svd = covariance.svd(true, true);
+    let (u, v_t) = (svd.u?, svd.v_t?);
+    // a reflection is turned into a rotation by flipping the axis of the smallest singular value
+    let mut signs = Vector3::repeat(1.0);
+    if u.determinant() * v_t.determinant() < 0.0 {
+        signs.z = -1.0;
+    }
+    let rotation = Rotation3::from_matrix_unchecked(u * Matrix3::from_diagonal(&signs) * v_t);
+    let scale = if with_scale {
+        svd.singular_values.dot(&signs) / source_variance
+    } else {
+        1.0
+    };
+    let translation = target_mean - scale * (rotation * source_mean);
+    Some(SimilarityMatrix3::from_parts(
+        translation.into(),
+        rotation,
+        scale,
+    ))
+}
+
+/// The relative errors of the segments starting at every pose, `None` if the ground truth is
+/// shorter than the length.
+fn segment_errors(
+    aligned: &[IsometryMatrix3<f64>],
+    reference: &[IsometryMatrix3<f64>],
+    length: f64,
+) -> Option<SegmentErrors> {
+    let distances: Vec<_> = reference
+        .iter()
+        .scan(
+            (0.0, None),
+            |(distance, previous), pose: &IsometryMatrix3<f64>| {
+                if let Some(previous) = previous.replace(pose.translation.vector) {
+                    *distance += (pose.translation.vector - previous).norm();
+                }
+                Some(*distance)
+            },
+        )
+        .collect();
+    let (translation, rotation): (Vec<_>, Vec<_>) = (0..reference.len())
+        .filter_map(|start| {
+            let end = distances.partition_point(|&distance| distance < distances[start] + length);
+            let expected = reference[start].inverse() * reference.get(end)?;
+            let estimated = aligned[start].inverse() * aligned[end];
+            let error = expected.inverse() * estimated;
+            Some((
+                error.translation.vector.norm(),
+                angle(&error.rotation).to_degrees(),
+            ))
+        })
+        .unzip();
+    let percent: Vec<_> = translation
+        .iter()
+        .map(|error| error / length * 100.0)
+        .collect();
+    Some(SegmentErrors {
+        length,
+        translation: Statistics::new(&translation)?,
+        translation_percent: Statistics::new(&percent)?,
+        rotation: Statistics::new(&rotation)?,
+    })
+}
+
+/// The angle of the rotation, precise for the small rotations unlike the arccosine of the trace.
+fn angle(rotation: &Rotation3<f64>) -> f64 {
+    let matrix = rotation.matrix();
+    let sine = Vector3::new(
+        matrix[(2, 1)] - matrix[(1, 2)],
+        matrix[(0, 2)] - matrix[(2, 0)],
+        matrix[(1, 0)] - matrix[(0, 1)],
+    )
+    .norm()
+        / 2.0;
+    let cosine = (matrix.trace() - 1.0) / 2.0;
+    sine.atan2(cosine)
+}
+
+/// A summary for the logs, the ATE and the RMSE of the relative errors of each length.
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ate = &self.ate;
+        writeln!(
+            f,
+            "ATE of {} poses: rmse {:.4} m, mean {:.4} m, median {:.4} m, max {:.4} m, scale {:.4}",
+            self.pairs,
+            ate.rmse,
+            ate.mean,
+            ate.median,
+            ate.max,
+            self.alignment.scaling()
+        )?;
+        self.rpe.iter().try_for_each(|segment| {
+            writeln!(
+                f,
+                "RPE over {} m: translation {:.4} m ({:.3} %), rotation {:.4} deg, {} segments",
+                segment.length,
+                segment.translation.rmse,
+                segment.translation_percent.rmse,
+                segment.rotation.rmse,
+                segment.translation.count
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Matrix4;
+
+    use super::*;
+    use crate::trajectory::StampedPose;
+
+    /// A circle of radius 10m, a pose every 0.1s and 0.1m.
+    fn ground_truth() -> Trajectory {
+        (0..628)
+            .map(|index| {
+                let angle = index as f64 * 0.01;
+                let position = Vector3::new(angle.cos(), angle.sin(), 0.0) * 10.0;
+                let pose = IsometryMatrix3::new(position, Vector3::z() * angle);
+                StampedPose::new(index as f64 * 0.1, pose)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aligns_transformed_trajectory() {
+        let ground_truth = ground_truth();
+        let transform = SimilarityMatrix3::new(
+            Vector3::new(1.0, -2.0, 3.0),
+            Vector3::new(0.1, 0.2, -0.3),
+            0.5,
+        );
+        let estimated: Trajectory = ground_truth
+            .poses
+            .iter()
+            .map(|pose| {
+                let rotation = transform.isometry.rotation * pose.pose.rotation;
+                let translation = transform.transform_point(&pose.pose.translation.vector.into());
+                let aligned = IsometryMatrix3::from_parts(translation.coords.into(), rotation);
+                // the estimated timestamps are a bit off
+                StampedPose::new(pose.timestamp + 0.004, aligned)
+            })
+            .collect();
+
+        let config = Config {
+            alignment: Alignment::Sim3,
+            segment_lengths: vec![10.0, 100.0],
+            ..Config::default()
+        };
+        let evaluation = evaluate(&estimated, &ground_truth, &config).unwrap();
+        assert_eq!(evaluation.pairs, 628);
+        assert!(evaluation.ate.max < 1e-9, "{evaluation}");
+        assert!((evaluation.alignment.scaling() - 2.0).abs() < 1e-9);
+        // the circle is 62.8m long
+        assert_eq!(evaluation.rpe.len(), 1);
+        assert!(evaluation.rpe[0].translation.max < 1e-9);
+        assert!(evaluation.rpe[0].rotation.max < 1e-6);
+
+        // the scale is not estimated
+        let config = Config {
+            alignment: Alignment::Se3,
+            ..config
+        };
+        let evaluation = evaluate(&estimated, &ground_truth, &config).unwrap();
+        assert!(evaluation.ate.rmse > 1.0);
+        // the estimated segments are half as long, and their chords are shorter than their length
+        let percent = evaluation.rpe[0].translation_percent.mean;
+        assert!((45.0..50.0).contains(&percent), "{percent}");
+    }
+
+    #[test]
+    fn associations() {
+        let poses = |times: &[f64]| -> Trajectory {
+            times
+                .iter()
+                .map(|&time| StampedPose::new(time, IsometryMatrix3::identity()))
+                .collect()
+        };
+        let pairs = associate(
+            &poses(&[0.0, 0.095, 0.104, 0.3, 0.52]),
+            &poses(&[0.0, 0.1, 0.2, 0.5]),
+            0.01,
+        );
+        assert_eq!(pairs, [(0, 0), (1, 1)]);
+
+        let statistics = Statistics::new(&[3.0, 1.0, 4.0, 2.0]).unwrap();
+        assert_eq!(statistics.median, 2.5);
+        assert_eq!(statistics.rmse, 7.5f64.sqrt());
+        assert_eq!(statistics.std, 1.25f64.sqrt());
+        assert!(Statistics::new(&[]).is_none());
+    }
+
+    #[test]
+    fn umeyama_degenerate() {
+        let points = [Vector3::zeros(), Vector3::x(), Vector3::y()];
+        let alignment = umeyama(&points, &points, true).unwrap();
+        assert!((alignment.to_homogeneous() - Matrix4::identity()).norm() < 1e-12);
+
+        assert!(umeyama(&points, &points[..2], true).is_none());
+        assert!(umeyama(&[Vector3::x(); 3], &points, false).is_none());
+    }
+}
@@ -1,9 +1,9 @@
 use std::{
     marker::PhantomData,
-    ops::{Add, Deref, DerefMut, Sub},
+    ops::{Deref, DerefMut, Mul},
 };
 
-use nalgebra::{IsometryMatrix3, Point3, Scalar, SimdRealField, Vector3};
+use nalgebra::{IsometryMatrix3, Matrix3, Point3, RealField, Scalar, Unit, Vector3};
 use num_traits::Zero;
 
 #[derive(Debug)]
@@ -16,11 +16,21 @@ pub struct Imu {}
 #[derive(Debug)]
 pub struct Body {}
 
+/// the frame of the camera, with z-axis forward, x-axis right and y-axis down
+#[derive(Debug)]
+pub struct Camera {}
+
 pub type WorldPoint<T> = FramedPoint<T, World>;
 pub type ImuPoint<T> = FramedPoint<T, Imu>;
 pub type BodyPoint<T> = FramedPoint<T, Body>;
+pub type CameraPoint<T> = FramedPoint<T, Camera>;
 
 pub type FramedPoint<T, F> = Framed<Point3<T>, F>;
+pub type FramedVector<T, F> = Framed<Vector3<T>, F>;
+pub type FramedNormal<T, F> = Framed<Unit<Vector3<T>>, F>;
+pub type FramedCovariance<T, F> = Framed<Matrix3<T>, F>;
+/// A rigid transform from frame `From` to frame `To`.
+pub type FramedIsometry<T, From, To> = Framed<IsometryMatrix3<T>, fn(From) -> To>;
 
 #[derive(Debug, Copy)]
 pub struct Framed<T, F> {
@@ -118,34 +128,100 @@ where
     }
 }
 
-
 impl<T, F> FramedPoint<T, F>
 where
-    T: SimdRealField,
-    T::Element: SimdRealField,
+    T: RealField,
 {
-    pub fn transform_with_isometry<To>(
-        &self,
-        tf: &Framed<IsometryMatrix3<T>, fn(F) -> To>,
-    ) -> FramedPoint<T, To> {
-        (tf.deref() * self.deref()).into()
+    pub fn transform_with_isometry<To>(&self, tf: &FramedIsometry<T, F, To>) -> FramedPoint<T, To> {
+        tf.transform_point(self)
     }
 }
 
-impl BodyPoint<f64> {
-    pub fn to_imu_point(
+impl<T, From, To> FramedIsometry<T, From, To>
+where
+    T: RealField,
+{
+    /// The inverse transform, from frame `To` back to frame `From`.
+    pub fn inverse(&self) -> FramedIsometry<T, To, From> {
+        Framed::new(self.inner.inverse())
+    }
+
+    pub fn transform_point(&self, point: &FramedPoint<T, From>) -> FramedPoint<T, To> {
+        Framed::new(&self.inner * &point.inner)
+    }
+
+    pub fn inverse_transform_point(&self, point: &FramedPoint<T, To>) -> FramedPoint<T, From> {
+        Framed::new(self.inner.inverse_transform_point(&point.inner))
+    }
+
+    /// Transform a vector, which is only rotated.
+    pub fn transform_vector(&self, vector: &FramedVector<T, From>) -> FramedVector<T, To> {
+        Framed::new(&self.inner.rotation * &vector.inner)
+    }
+
+    /// Transform a normal, which is only rotated since the transform is rigid.
+    pub fn transform_normal(&self, normal: &FramedNormal<T, From>) -> FramedNormal<T, To> {
+        Framed::new(&self.inner.rotation * &normal.inner)
+    }
+
+    /// Transform the covariance of a point or a vector, `R * covariance * R^T`.
+    pub fn transform_covariance(
         &self,
-        body_to_imu: &Framed<IsometryMatrix3<f64>, fn(Body) -> Imu>,
-    ) -> ImuPoint<f64> {
-        (body_to_imu.deref() * self.deref()).into()
+        covariance: &FramedCovariance<T, From>,
+    ) -> FramedCovariance<T, To> {
+        let rotation = self.inner.rotation.matrix();
+        Framed::new(rotation * &covariance.inner * rotation.transpose())
     }
 }
 
-impl ImuPoint<f64> {
-    pub fn to_world_point(
-        &self,
-        imu_to_world: &Framed<IsometryMatrix3<f64>, fn(Imu) -> World>,
-    ) -> WorldPoint<f64> {
-        (imu_to_world.deref() * self.deref()).into()
+/// Chain the transforms, `a_to_b * b_to_c` is `a_to_c`.
+impl<T, A, B, C> Mul<FramedIsometry<T, B, C>> for FramedIsometry<T, A, B>
+where
+    T: RealField,
+{
+    type Output = FramedIsometry<T, A, C>;
+
+    fn mul(self, rhs: FramedIsometry<T, B, C>) -> Self::Output {
+        Framed::new(rhs.inner * self.inner)
+    }
+}
+
+impl<T, A, B, C> Mul<&FramedIsometry<T, B, C>> for &FramedIsometry<T, A, B>
+where
+    T: RealField,
+{
+    type Output = FramedIsometry<T, A, C>;
+
+    fn mul(self, rhs: &FramedIsometry<T, B, C>) -> Self::Output {
+        Framed::new(&rhs.inner * &self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    #[test]
+    fn chained_transforms() {
+        let body_to_imu: FramedIsometry<f64, Body, Imu> =
+            IsometryMatrix3::new(Vector3::new(0.1, -0.2, 0.3), Vector3::new(0.0, 0.0, 0.5)).into();
+        let imu_to_world: FramedIsometry<f64, Imu, World> =
+            IsometryMatrix3::new(Vector3::new(1.0, 2.0, 3.0), Vector3::new(0.2, -0.1, 0.0)).into();
+        let body_point: BodyPoint<f64> = Vector3::new(4.0, 5.0, 6.0).into();
+
+        let body_to_world = body_to_imu * imu_to_world;
+        let world_point = body_to_world.transform_point(&body_point);
+        let expected = imu_to_world.transform_point(&body_to_imu.transform_point(&body_point));
+        assert!((world_point.coords - expected.coords).norm() < 1e-12);
+
+        let back = body_to_world.inverse().transform_point(&world_point);
+        assert!((back.coords - body_point.coords).norm() < 1e-12);
+
+        let covariance: FramedCovariance<f64, Body> =
+            Framed::new(Matrix3::from_diagonal(&Vector3::new(1.0, 2.0, 3.0)));
+        let rotated = body_to_world.transform_covariance(&covariance);
+        assert!((rotated.trace() - 6.0).abs() < 1e-12);
     }
 }
@@ -1,42 +1,725 @@
-use nalgebra::{IsometryMatrix3, Vector3};
+use nalgebra::{IsometryMatrix3, Matrix3, OMatrix, Rotation3, Translation3, Vector3};
+use rust_livo2_macros::uncertainties;
+use serde::Deserialize;
 
-use crate::esikf;
+use crate::{
+    config::{self, ConfigError, ensure},
+    esikf::{self, OdometerUncertainties, StateMatrix, StateVector},
+    frame::{Body, BodyPoint, Framed, FramedIsometry, Imu, World},
+    uncertain::{UncertainForward, Uncertainty, Uncertainty3},
+    utils::{VectorSquareSum, so3_right_jacobian},
+};
 
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
+    /// the lidar-imu extrinsic, or its initial guess if [`Config::extrinsic_estimation`] is enabled
+    #[serde(deserialize_with = "config::deserialize_isometry")]
     pub body_to_imu: IsometryMatrix3<f64>,
+    /// refine the lidar-imu extrinsic online in the error state
+    pub extrinsic_estimation: bool,
+    /// prior variance of the extrinsic rotation, in rad^2
+    pub extrinsic_rotation_variance: f64,
+    /// prior variance of the extrinsic translation, in m^2
+    pub extrinsic_translation_variance: f64,
+    /// gyroscope measurement noise, in (rad/s)^2
+    pub gyro_noise: f64,
+    /// accelerator measurement noise, in (m/s^2)^2
+    pub acc_noise: f64,
+    /// gyroscope bias random walk noise
+    pub bias_gyro_noise: f64,
+    /// accelerator bias random walk noise
+    pub bias_acc_noise: f64,
+    /// the number of stationary imu samples used in [`gravity_alignment`]
+    pub init_samples: usize,
+    /// the maximum accelerator standard deviation in m/s^2 to be considered stationary
+    pub init_max_acc_std: f64,
+    /// the maximum gyroscope standard deviation in rad/s to be considered stationary
+    pub init_max_gyro_std: f64,
+    /// initial variance of the rotation after [`gravity_alignment`], in rad^2
+    pub init_rotation_variance: f64,
+    /// initial variance of the translation, in m^2
+    pub init_translation_variance: f64,
+    /// initial variance of the velocity, in (m/s)^2
+    pub init_velocity_variance: f64,
+    /// initial variance of the gyroscope bias, in (rad/s)^2
+    pub init_bias_gyro_variance: f64,
+    /// initial variance of the accelerator bias, in (m/s^2)^2
+    pub init_bias_acc_variance: f64,
+    /// initial variance of the gravity, in (m/s^2)^2
+    pub init_gravity_variance: f64,
 }
 
+impl Config {
+    pub(crate) fn validate(&self, section: &str) -> Result<(), ConfigError> {
+        let positive = "should be positive";
+        let non_negative = "should not be negative";
+        [
+            (
+                "extrinsic_rotation_variance",
+                self.extrinsic_rotation_variance,
+            ),
+            (
+                "extrinsic_translation_variance",
+                self.extrinsic_translation_variance,
+            ),
+            ("gyro_noise", self.gyro_noise),
+            ("acc_noise", self.acc_noise),
+            ("bias_gyro_noise", self.bias_gyro_noise),
+            ("bias_acc_noise", self.bias_acc_noise),
+            ("init_rotation_variance", self.init_rotation_variance),
+            ("init_translation_variance", self.init_translation_variance),
+            ("init_velocity_variance", self.init_velocity_variance),
+            ("init_bias_gyro_variance", self.init_bias_gyro_variance),
+            ("init_bias_acc_variance", self.init_bias_acc_variance),
+            ("init_gravity_variance", self.init_gravity_variance),
+        ]
+        .into_iter()
+        .try_for_each(|(field, value)| ensure(value >= 0.0, section, field, non_negative))?;
+        ensure(
+            self.init_samples >= 2,
+            section,
+            "init_samples",
+            "should be at least 2",
+        )?;
+        ensure(
+            self.init_max_acc_std > 0.0,
+            section,
+            "init_max_acc_std",
+            positive,
+        )?;
+        ensure(
+            self.init_max_gyro_std > 0.0,
+            section,
+            "init_max_gyro_std",
+            positive,
+        )
+    }
+}
+
+impl Default for Config {
+    /// The imu parameters of FAST-LIVO2, with an identity extrinsic.
+    fn default() -> Self {
+        Self {
+            body_to_imu: IsometryMatrix3::identity(),
+            extrinsic_estimation: false,
+            extrinsic_rotation_variance: 1e-4,
+            extrinsic_translation_variance: 1e-4,
+            gyro_noise: 0.3,
+            acc_noise: 0.5,
+            bias_gyro_noise: 1e-4,
+            bias_acc_noise: 1e-4,
+            init_samples: 20,
+            init_max_acc_std: 0.2,
+            init_max_gyro_std: 0.02,
+            init_rotation_variance: 1e-4,
+            init_translation_variance: 1e-4,
+            init_velocity_variance: 1e-4,
+            init_bias_gyro_variance: 1e-4,
+            init_bias_acc_variance: 1e-3,
+            init_gravity_variance: 1e-5,
+        }
+    }
+}
+
+/// Standard gravity, in m/s^2
+pub const GRAVITY_NORM: f64 = 9.81;
+
+#[derive(Debug, Clone)]
 pub struct State {
     /// estimated velocity, from imu frame to world frame
-    velocity: Vector3<f64>,
+    pub velocity: Vector3<f64>,
     /// gyroscope bias
-    bias_gyro: Vector3<f64>,
+    pub bias_gyro: Vector3<f64>,
     /// accelerator bias
-    bias_acc: Vector3<f64>,
+    pub bias_acc: Vector3<f64>,
     /// the estimated gravity acceleration
-    gravity: Vector3<f64>,
+    pub gravity: Vector3<f64>,
+    /// accelerator scale estimated at initialization, 1.0 if the accelerator is measured in m/s^2
+    pub acc_scale: f64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            velocity: Vector3::zeros(),
+            bias_gyro: Vector3::zeros(),
+            bias_acc: Vector3::zeros(),
+            gravity: Vector3::new(0.0, 0.0, -GRAVITY_NORM),
+            acc_scale: 1.0,
+        }
+    }
+}
+
+/// A timestamped imu measurement, both vectors are in the imu frame.
+#[derive(Debug, Clone)]
+pub struct ImuSample {
+    /// timestamp in seconds
+    pub timestamp: f64,
+    pub angular_velocity: Vector3<f64>,
+    pub linear_acceleration: Vector3<f64>,
+}
+
+/// The propagated imu pose at a sample time.
+#[derive(Debug, Clone)]
+pub struct ImuPose {
+    /// time offset from the start of the propagation
+    pub offset_time: f64,
+    /// bias compensated angular velocity, in imu frame
+    pub angular_velocity: Vector3<f64>,
+    /// gravity compensated acceleration, in world frame
+    pub acceleration: Vector3<f64>,
+    pub velocity: Vector3<f64>,
+    pub isometry: Framed<IsometryMatrix3<f64>, fn(Imu) -> World>,
+}
+
+#[uncertainties]
+#[derive(Debug)]
+struct NoiseUncertainties {
+    pub gyro: Uncertainty3<f64>,
+    pub acc: Uncertainty3<f64>,
+    pub bias_gyro: Uncertainty3<f64>,
+    pub bias_acc: Uncertainty3<f64>,
+}
+
+impl NoiseUncertainties {
+    fn from_config(config: &Config) -> Self {
+        let mut noise = Self::from(NoiseUncertaintiesMatrix::zeros());
+        [
+            (Self::GYRO_INDEX, config.gyro_noise),
+            (Self::ACC_INDEX, config.acc_noise),
+            (Self::BIAS_GYRO_INDEX, config.bias_gyro_noise),
+            (Self::BIAS_ACC_INDEX, config.bias_acc_noise),
+        ]
+        .into_iter()
+        .for_each(|(index, variance)| {
+            noise
+                .fixed_view_mut::<3, 3>(index, index)
+                .fill_diagonal(variance)
+        });
+        noise
+    }
+}
+
+impl ImuPose {
+    fn new(offset_time: f64, odometer: &esikf::UncertainOdometer) -> Self {
+        Self {
+            offset_time,
+            angular_velocity: Vector3::zeros(),
+            acceleration: Vector3::zeros(),
+            velocity: odometer.imu.velocity,
+            isometry: odometer.isometry,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum GravityAlignmentError {
+    #[error("not enough imu samples to initialize, expected {expected}, found {found}")]
+    NotEnoughSamples { expected: usize, found: usize },
+    #[error("no gravity found in imu samples, the platform may be free falling")]
+    NoGravity,
+    #[error(
+        "the platform was moving during initialization, acc std: {acc_std}, gyro std: {gyro_std}"
+    )]
+    Moving { acc_std: f64, gyro_std: f64 },
+}
+
+/// Initialize the odometer with stationary imu samples.
+///
+/// Estimates the gravity and the gyroscope bias, rotates the odometer so that the world z-axis is up,
+/// resets the extrinsic to [`Config::body_to_imu`] and resets the odometer covariance
+/// to the initial variances of the config.
+/// The extrinsic variances are zero if the extrinsic estimation is disabled, which locks it.
+/// So are the variances of the visual states, until they are set by
+/// [`Vio::initialize`](crate::vio::Vio::initialize) from the visual config.
+///
+/// The accelerator bias is only observable along the gravity direction, and if the accelerator
+/// is not measured in m/s^2 (e.g. in g), it is absorbed into [`State::acc_scale`] instead.
+pub fn gravity_alignment(
+    state: &mut esikf::UncertainOdometer,
+    samples: &[ImuSample],
+    config: &Config,
+) -> Result<(), GravityAlignmentError> {
+    let expected = config.init_samples.max(2);
+    if samples.len() < expected {
+        return Err(GravityAlignmentError::NotEnoughSamples {
+            expected,
+            found: samples.len(),
+        });
+    }
+
+    let (mean_acc, acc_covariance) = samples
+        .iter()
+        .map(|sample| &sample.linear_acceleration)
+        .sum::<VectorSquareSum>()
+        .mean();
+    let (mean_gyro, gyro_covariance) = samples
+        .iter()
+        .map(|sample| &sample.angular_velocity)
+        .sum::<VectorSquareSum>()
+        .mean();
+
+    let acc_norm = mean_acc.norm();
+    if acc_norm < f64::EPSILON {
+        return Err(GravityAlignmentError::NoGravity);
+    }
+    let acc_scale = if (acc_norm - GRAVITY_NORM).abs() > GRAVITY_NORM / 2.0 {
+        GRAVITY_NORM / acc_norm
+    } else {
+        1.0
+    };
+
+    let acc_std = acc_covariance.trace().sqrt() * acc_scale;
+    let gyro_std = gyro_covariance.trace().sqrt();
+    if acc_std > config.init_max_acc_std || gyro_std > config.init_max_gyro_std {
+        return Err(GravityAlignmentError::Moving { acc_std, gyro_std });
+    }
+
+    let mean_acc = mean_acc * acc_scale;
+    let acc_direction = mean_acc / mean_acc.norm();
+    let rotation = Rotation3::rotation_between(&acc_direction, &Vector3::z())
+        .unwrap_or_else(|| Rotation3::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI));
+
+    state.isometry = IsometryMatrix3::from_parts(Translation3::identity(), rotation).into();
+    state.imu = State {
+        velocity: Vector3::zeros(),
+        bias_gyro: mean_gyro,
+        bias_acc: mean_acc - acc_direction * GRAVITY_NORM,
+        gravity: Vector3::new(0.0, 0.0, -GRAVITY_NORM),
+        acc_scale,
+    };
+    state.body_to_imu = config.body_to_imu.into();
+    let (extrinsic_rotation_variance, extrinsic_translation_variance) =
+        if config.extrinsic_estimation {
+            (
+                config.extrinsic_rotation_variance,
+                config.extrinsic_translation_variance,
+            )
+        } else {
+            (0.0, 0.0)
+        };
+
+    type Odometer = OdometerUncertainties;
+    let mut variances = StateVector::zeros();
+    [
+        (Odometer::ROTATION_INDEX, 3, config.init_rotation_variance),
+        (
+            Odometer::TRANSLATION_INDEX,
+            3,
+            config.init_translation_variance,
+        ),
+        (Odometer::VELOCITY_INDEX, 3, config.init_velocity_variance),
+        (Odometer::BIAS_GYRO_INDEX, 3, config.init_bias_gyro_variance),
+        (Odometer::BIAS_ACC_INDEX, 3, config.init_bias_acc_variance),
+        (Odometer::GRAVITY_INDEX, 3, config.init_gravity_variance),
+        (
+            Odometer::BODY_TO_IMU_ROTATION_INDEX,
+            3,
+            extrinsic_rotation_variance,
+        ),
+        (
+            Odometer::BODY_TO_IMU_TRANSLATION_INDEX,
+            3,
+            extrinsic_translation_variance,
+        ),
+    ]
+    .into_iter()
+    .for_each(|(index, dim, variance)| variances.rows_mut(index, dim).fill(variance));
+
+    state.covariance = StateMatrix::from_diagonal(&variances).into();
+    Ok(())
 }
 
-fn gravity_alignment(state: &mut esikf::UncertainOdometer) {
-    
+/// Undistort the points of a sweep, re-express every point in the body frame at the end of the sweep.
+///
+/// `points` are paired with their time offsets from the start of the sweep,
+/// `poses` are propagated by [`forward_propagation`] over the imu samples covering the sweep,
+/// the last pose should be at the end of the sweep.
+pub fn undistort_pcl(
+    points: impl IntoIterator<Item = (BodyPoint<f64>, f64)>,
+    poses: &[ImuPose],
+    body_to_imu: &FramedIsometry<f64, Body, Imu>,
+) -> Vec<BodyPoint<f64>> {
+    let Some(end_pose) = poses.last() else {
+        return points.into_iter().map(|(point, _)| point).collect();
+    };
+
+    points
+        .into_iter()
+        .map(|(point, offset_time)| {
+            let imu_to_world =
+                backward_propagation(poses, offset_time).expect("the poses are not empty");
+            let world_point = (body_to_imu * &imu_to_world).transform_point(&point);
+
+            let imu_point = end_pose.isometry.inverse_transform_point(&world_point);
+            body_to_imu.inverse_transform_point(&imu_point)
+        })
+        .collect()
+}
+
+/// Propagate the odometer from `start_time` to `end_time` with the mid-point integration,
+/// returns the propagated poses at every imu sample, started with the pose at `start_time`.
+///
+/// `samples` should be sorted by timestamp, and the first sample should be at or before `start_time`.
+pub fn forward_propagation(
+    odometer: &mut esikf::UncertainOdometer,
+    samples: &[ImuSample],
+    start_time: f64,
+    end_time: f64,
+    config: &Config,
+) -> Vec<ImuPose> {
+    let noise = NoiseUncertainties::from_config(config);
+    let mut poses = vec![ImuPose::new(0.0, odometer)];
+
+    let mut propagate = |odometer: &mut esikf::UncertainOdometer,
+                         angular_velocity: Vector3<f64>,
+                         linear_acceleration: Vector3<f64>,
+                         from: f64,
+                         to: f64| {
+        let dt = to - from;
+        if dt <= 0.0 {
+            return;
+        }
+        let angular_velocity = angular_velocity - odometer.imu.bias_gyro;
+        let linear_acceleration =
+            linear_acceleration * odometer.imu.acc_scale - odometer.imu.bias_acc;
+
+        covariance_propagation(
+            odometer,
+            &angular_velocity,
+            &linear_acceleration,
+            &noise,
+            dt,
+        );
+        let acceleration =
+            imu_attitude_propagation(odometer, &angular_velocity, &linear_acceleration, dt);
+
+        poses.push(ImuPose {
+            offset_time: to - start_time,
+            angular_velocity,
+            acceleration,
+            velocity: odometer.imu.velocity,
+            isometry: odometer.isometry,
+        });
+    };
+
+    samples.windows(2).for_each(|pair| {
+        let [head, tail] = pair else { unreachable!() };
+        if tail.timestamp <= start_time || head.timestamp >= end_time {
+            return;
+        }
+        let angular_velocity = (head.angular_velocity + tail.angular_velocity) / 2.0;
+        let linear_acceleration = (head.linear_acceleration + tail.linear_acceleration) / 2.0;
+
+        propagate(
+            odometer,
+            angular_velocity,
+            linear_acceleration,
+            head.timestamp.max(start_time),
+            tail.timestamp.min(end_time),
+        );
+    });
+
+    if let Some(last) = samples.last().filter(|last| last.timestamp < end_time) {
+        propagate(
+            odometer,
+            last.angular_velocity,
+            last.linear_acceleration,
+            last.timestamp.max(start_time),
+            end_time,
+        );
+    }
+
+    poses
 }
 
-fn undistort_pcl() {
+/// Interpolate the imu pose at `offset_time` from the poses propagated by [`forward_propagation`],
+/// `None` if there is no pose.
+pub fn backward_propagation(
+    poses: &[ImuPose],
+    offset_time: f64,
+) -> Option<FramedIsometry<f64, Imu, World>> {
+    let last_index = poses.len().checked_sub(1)?;
+    let tail_index = poses
+        .partition_point(|pose| pose.offset_time < offset_time)
+        .min(last_index);
+    let Some(head_index) = tail_index.checked_sub(1) else {
+        return Some(poses[0].isometry);
+    };
+    let head = &poses[head_index];
+    let tail = &poses[tail_index];
 
+    let dt = offset_time - head.offset_time;
+    let rotation = head.isometry.rotation * Rotation3::from_scaled_axis(tail.angular_velocity * dt);
+    let translation =
+        head.isometry.translation.vector + head.velocity * dt + tail.acceleration * dt * dt / 2.0;
+
+    Some(IsometryMatrix3::from_parts(translation.into(), rotation).into())
 }
 
-fn forward_propagation() {
+/// Propagate the error state covariance with `P = F * P * F^T + G * Q * G^T`.
+///
+/// `angular_velocity` and `linear_acceleration` should be bias compensated.
+fn covariance_propagation(
+    state: &mut esikf::UncertainOdometer,
+    angular_velocity: &Vector3<f64>,
+    linear_acceleration: &Vector3<f64>,
+    noise: &NoiseUncertainties,
+    dt: f64,
+) {
+    type State = OdometerUncertainties;
+    type Noise = NoiseUncertainties;
+
+    let rotation = state.rotation().into_inner();
+    let rotation_vector = angular_velocity * dt;
+    let right_jacobian = so3_right_jacobian(&rotation_vector);
+    let identity_dt = Matrix3::from_diagonal_element(dt);
+
+    let mut transition = StateMatrix::identity();
+    let mut set_block = |row: usize, col: usize, block: Matrix3<f64>| {
+        transition
+            .fixed_view_mut::<3, 3>(row, col)
+            .copy_from(&block);
+    };
+    set_block(
+        State::ROTATION_INDEX,
+        State::ROTATION_INDEX,
+        Rotation3::from_scaled_axis(-rotation_vector).into_inner(),
+    );
+    set_block(
+        State::ROTATION_INDEX,
+        State::BIAS_GYRO_INDEX,
+        -right_jacobian * dt,
+    );
+    set_block(State::TRANSLATION_INDEX, State::VELOCITY_INDEX, identity_dt);
+    set_block(
+        State::VELOCITY_INDEX,
+        State::ROTATION_INDEX,
+        -rotation * linear_acceleration.cross_matrix() * dt,
+    );
+    set_block(State::VELOCITY_INDEX, State::BIAS_ACC_INDEX, -rotation * dt);
+    set_block(State::VELOCITY_INDEX, State::GRAVITY_INDEX, identity_dt);
 
+    let mut noise_jacobian = OMatrix::<f64, esikf::StateDim, <Noise as Uncertainty>::Dim>::zeros();
+    let mut set_block = |row: usize, col: usize, block: Matrix3<f64>| {
+        noise_jacobian
+            .fixed_view_mut::<3, 3>(row, col)
+            .copy_from(&block);
+    };
+    set_block(
+        State::ROTATION_INDEX,
+        Noise::GYRO_INDEX,
+        -right_jacobian * dt,
+    );
+    set_block(State::VELOCITY_INDEX, Noise::ACC_INDEX, -rotation * dt);
+    set_block(State::BIAS_GYRO_INDEX, Noise::BIAS_GYRO_INDEX, identity_dt);
+    set_block(State::BIAS_ACC_INDEX, Noise::BIAS_ACC_INDEX, identity_dt);
+
+    let covariance = state.covariance.forward(transition) + noise.forward(noise_jacobian);
+    state.covariance = covariance.into();
 }
 
-fn backward_propagation() {
+/// Propagate the nominal state, returns the gravity compensated acceleration in world frame.
+///
+/// `angular_velocity` and `linear_acceleration` should be bias compensated.
+fn imu_attitude_propagation(
+    state: &mut esikf::UncertainOdometer,
+    angular_velocity: &Vector3<f64>,
+    linear_acceleration: &Vector3<f64>,
+    dt: f64,
+) -> Vector3<f64> {
+    let imu = &mut state.imu;
+    let isometry = &mut state.isometry;
+
+    let acceleration = isometry.rotation * linear_acceleration + imu.gravity;
 
+    isometry.rotation *= Rotation3::from_scaled_axis(angular_velocity * dt);
+    isometry.translation = Translation3::from(
+        isometry.translation.vector + imu.velocity * dt + acceleration * dt * dt / 2.0,
+    );
+    imu.velocity += acceleration * dt;
+
+    acceleration
 }
 
-fn covariance_propagation(state: &mut esikf::UncertainOdometer) {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vio;
+
+    fn odometer() -> esikf::UncertainOdometer {
+        esikf::UncertainOdometer::new(
+            IsometryMatrix3::identity().into(),
+            State::default(),
+            vio::State::default(),
+            IsometryMatrix3::identity().into(),
+            StateMatrix::from_diagonal_element(1e-4).into(),
+        )
+    }
+
+    /// The samples of 0.1s at 100Hz, with the imu level.
+    fn samples(
+        angular_velocity: Vector3<f64>,
+        linear_acceleration: Vector3<f64>,
+    ) -> Vec<ImuSample> {
+        (0..=10)
+            .map(|index| ImuSample {
+                timestamp: index as f64 * 0.01,
+                angular_velocity,
+                linear_acceleration,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stationary() {
+        let mut odometer = odometer();
+        let samples = samples(Vector3::zeros(), Vector3::new(0.0, 0.0, GRAVITY_NORM));
+        let poses = forward_propagation(&mut odometer, &samples, 0.0, 0.1, &Config::default());
+        assert_eq!(poses.len(), 11);
+        assert_eq!(poses.last().unwrap().offset_time, 0.1);
+        assert!(odometer.isometry.translation.vector.norm() < 1e-12);
+        assert!(odometer.rotation().angle() < 1e-12);
+        assert!(odometer.imu.velocity.norm() < 1e-12);
+    }
+
+    #[test]
+    fn constant_acceleration() {
+        let mut odometer = odometer();
+        let samples = samples(Vector3::zeros(), Vector3::new(1.0, 0.0, GRAVITY_NORM));
+        // the last sample is held until the end time
+        let poses = forward_propagation(&mut odometer, &samples, 0.0, 0.2, &Config::default());
+        assert_eq!(poses.len(), 12);
+        assert!((odometer.imu.velocity - Vector3::new(0.2, 0.0, 0.0)).norm() < 1e-12);
+        let translation = odometer.isometry.translation.vector;
+        assert!((translation - Vector3::new(0.02, 0.0, 0.0)).norm() < 1e-12);
+        assert!((poses[5].acceleration - Vector3::x()).norm() < 1e-12);
+    }
 
-} 
+    #[test]
+    fn covariance_grows() {
+        let mut odometer = odometer();
+        let prior = *odometer.covariance;
+        let samples = samples(
+            Vector3::new(0.1, -0.2, 0.3),
+            Vector3::new(0.5, 0.2, GRAVITY_NORM),
+        );
+        forward_propagation(&mut odometer, &samples, 0.0, 0.1, &Config::default());
+        let covariance = *odometer.covariance;
+        assert!((covariance - covariance.transpose()).amax() < 1e-15);
+        type Odometer = OdometerUncertainties;
+        [
+            Odometer::ROTATION_INDEX,
+            Odometer::TRANSLATION_INDEX,
+            Odometer::VELOCITY_INDEX,
+            Odometer::BIAS_GYRO_INDEX,
+            Odometer::BIAS_ACC_INDEX,
+        ]
+        .into_iter()
+        .for_each(|index| {
+            let block = |matrix: &StateMatrix| matrix.fixed_view::<3, 3>(index, index).trace();
+            assert!(block(&covariance) > block(&prior), "index {index}");
+        });
+        // the translation is correlated with the velocity
+        assert!(covariance[(Odometer::TRANSLATION_INDEX, Odometer::VELOCITY_INDEX)] > 0.0);
+    }
 
-fn imu_attitude_propagation(state: &mut esikf::UncertainOdometer) {
+    #[test]
+    fn undistortion() {
+        assert!(backward_propagation(&[], 0.0).is_none());
 
-} 
+        // turning at 1 rad/s while moving at 1 m/s along the world x-axis
+        let mut odometer = odometer();
+        odometer.imu.velocity = Vector3::x();
+        let samples = samples(Vector3::z(), Vector3::new(0.0, 0.0, GRAVITY_NORM));
+        let poses = forward_propagation(&mut odometer, &samples, 0.0, 0.1, &Config::default());
+        let imu_to_world = |time: f64| {
+            IsometryMatrix3::new(Vector3::new(time, 0.0, 0.0), Vector3::new(0.0, 0.0, time))
+        };
+        let body_to_imu = IsometryMatrix3::translation(0.1, 0.2, 0.3);
+
+        // a world point seen at different times of the sweep
+        let world_point = nalgebra::Point3::new(5.0, 1.0, 0.5);
+        let observe = |time: f64| {
+            let body_point =
+                (imu_to_world(time) * body_to_imu).inverse_transform_point(&world_point);
+            (BodyPoint::from(body_point), time)
+        };
+        let undistorted = undistort_pcl(
+            [0.0, 0.033, 0.07, 0.1].map(observe),
+            &poses,
+            &body_to_imu.into(),
+        );
+        let (expected, _) = observe(0.1);
+        undistorted.iter().for_each(|point| {
+            assert!((point.coords - expected.coords).norm() < 1e-9, "{point:?}");
+        });
+    }
+
+    #[test]
+    fn alignment() {
+        let config = Config {
+            init_samples: 10,
+            init_rotation_variance: 1e-3,
+            ..Config::default()
+        };
+        // tilted about the x-axis, the accelerator measures in g
+        let acc = Vector3::new(0.0, 0.6, 0.8);
+        let mut samples = samples(Vector3::new(0.01, 0.0, -0.02), acc);
+        samples.iter_mut().enumerate().for_each(|(index, sample)| {
+            let sign = if index % 2 == 0 { 1.0 } else { -1.0 };
+            sample.linear_acceleration += Vector3::x() * 1e-3 * sign;
+        });
+        let mut odometer = odometer();
+        gravity_alignment(&mut odometer, &samples, &config).unwrap();
+
+        assert!((odometer.imu.acc_scale - GRAVITY_NORM).abs() < 1e-3);
+        let up = odometer.rotation() * acc.normalize();
+        assert!((up - Vector3::z()).norm() < 1e-3, "{up}");
+        assert!((odometer.imu.bias_gyro - Vector3::new(0.01, 0.0, -0.02)).norm() < 1e-12);
+        let covariance = &odometer.covariance;
+        assert_eq!(covariance.view_rotation()[(0, 0)], 1e-3);
+        assert_eq!(
+            covariance.view_bias_acc()[(2, 2)],
+            config.init_bias_acc_variance
+        );
+        // the extrinsic and the inverse exposure time are locked
+        assert_eq!(covariance.view_body_to_imu_rotation().trace(), 0.0);
+        assert_eq!(covariance.view_inverse_exposure_time()[(0, 0)], 0.0);
+    }
+
+    #[test]
+    fn alignment_errors() {
+        let config = Config::default();
+        let level = samples(Vector3::zeros(), Vector3::new(0.0, 0.0, GRAVITY_NORM));
+        assert!(matches!(
+            gravity_alignment(&mut odometer(), &level, &config),
+            Err(GravityAlignmentError::NotEnoughSamples {
+                expected: 20,
+                found: 11
+            })
+        ));
+
+        let config = Config {
+            init_samples: 10,
+            ..config
+        };
+        let mut shaking = level.clone();
+        shaking.iter_mut().enumerate().for_each(|(index, sample)| {
+            sample.angular_velocity.z = if index % 2 == 0 { 0.1 } else { -0.1 };
+        });
+        let Err(GravityAlignmentError::Moving { gyro_std, .. }) =
+            gravity_alignment(&mut odometer(), &shaking, &config)
+        else {
+            panic!("the gyroscope is above its limit");
+        };
+        assert!(gyro_std > config.init_max_gyro_std);
+
+        let falling = samples(Vector3::zeros(), Vector3::zeros());
+        assert!(matches!(
+            gravity_alignment(&mut odometer(), &falling, &config),
+            Err(GravityAlignmentError::NoGravity)
+        ));
+    }
+}
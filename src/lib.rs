@@ -1,9 +1,17 @@
-mod esikf;
-mod imu;
-mod vio;
-mod voxel_map;
+pub mod esikf;
+pub mod imu;
+pub mod vio;
+pub mod voxel_map;
 mod utils;
-mod config;
+pub mod config;
 
 pub mod uncertain;
 pub mod frame;
+pub mod camera;
+pub mod cloud;
+pub mod dataset;
+pub mod eval;
+pub mod measurement;
+pub mod odometry;
+pub mod preprocess;
+pub mod trajectory;
@@ -0,0 +1,301 @@
+//! Timestamped sensor inputs and their synchronization into [`MeasureGroup`]s.
+use std::collections::VecDeque;
+
+use crate::{frame::BodyPoint, vio::GrayImage};
+
+pub use crate::imu::ImuSample;
+
+/// A point of a lidar scan.
+#[derive(Debug, Clone)]
+pub struct LidarPoint {
+    pub point: BodyPoint<f64>,
+    /// time offset from the start of the scan, in seconds
+    pub offset_time: f64,
+    pub intensity: f32,
+    /// the laser ring of the point, 0 if unknown
+    pub ring: u16,
+}
+
+/// A lidar scan, the points are in the body frame.
+#[derive(Debug, Clone)]
+pub struct LidarScan {
+    /// timestamp of the scan start, in seconds
+    pub timestamp: f64,
+    pub points: Vec<LidarPoint>,
+}
+
+impl LidarScan {
+    /// The timestamp of the latest point.
+    pub fn end_time(&self) -> f64 {
+        self.timestamp
+            + self
+                .points
+                .iter()
+                .map(|point| point.offset_time)
+                .fold(0.0, f64::max)
+    }
+}
+
+/// A grayscale camera image.
+#[derive(Clone)]
+pub struct CameraImage {
+    /// timestamp in seconds, without the estimated time offset
+    pub timestamp: f64,
+    pub image: GrayImage,
+}
+
+/// The measurements to process a lidar scan.
+#[derive(Clone)]
+pub struct MeasureGroup {
+    pub scan: LidarScan,
+    /// the imu samples covering the scan,
+    /// from the last sample at or before the scan start to the first sample at or after the scan end
+    pub imu_samples: Vec<ImuSample>,
+    /// the image captured closest to the scan end, if any
+    pub image: Option<CameraImage>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SynchronizerError {
+    #[error("{sensor} message at {timestamp} is older than the previous one at {previous}")]
+    OutOfOrder {
+        sensor: &'static str,
+        previous: f64,
+        timestamp: f64,
+    },
+}
+
+/// Buffers the sensor inputs and emits a [`MeasureGroup`] once every input of a scan has arrived.
+pub struct MeasurementSynchronizer {
+    /// the maximum time difference between the capture time of an image and the scan end, in seconds
+    image_tolerance: f64,
+    /// added to the image timestamps to get their capture time, in seconds
+    image_time_offset: f64,
+    imu_samples: VecDeque<ImuSample>,
+    scans: VecDeque<LidarScan>,
+    images: VecDeque<CameraImage>,
+}
+
+impl MeasurementSynchronizer {
+    pub fn new(image_tolerance: f64) -> Self {
+        Self {
+            image_tolerance,
+            image_time_offset: 0.0,
+            imu_samples: VecDeque::new(),
+            scans: VecDeque::new(),
+            images: VecDeque::new(),
+        }
+    }
+
+    /// Set the time offset of the images, e.g. to the estimated [`crate::vio::State::time_offset`].
+    pub fn set_image_time_offset(&mut self, time_offset: f64) {
+        self.image_time_offset = time_offset;
+    }
+
+    pub fn push_imu(&mut self, sample: ImuSample) -> Result<(), SynchronizerError> {
+        let previous = self.imu_samples.back().map(|sample| sample.timestamp);
+        check_order("imu", previous, sample.timestamp)?;
+        self.imu_samples.push_back(sample);
+        Ok(())
+    }
+
+    pub fn push_scan(&mut self, scan: LidarScan) -> Result<(), SynchronizerError> {
+        let previous = self.scans.back().map(|scan| scan.timestamp);
+        check_order("lidar", previous, scan.timestamp)?;
+        self.scans.push_back(scan);
+        Ok(())
+    }
+
+    pub fn push_image(&mut self, image: CameraImage) -> Result<(), SynchronizerError> {
+        let previous = self.images.back().map(|image| image.timestamp);
+        check_order("camera", previous, image.timestamp)?;
+        self.images.push_back(image);
+        Ok(())
+    }
+
+    /// Emit the group of the oldest scan, `None` if its imu samples or image have not arrived yet.
+    ///
+    /// The image is selected once an image after the scan end has arrived,
+    /// or the imu samples have passed the scan end by the image tolerance.
+    pub fn next_group(&mut self) -> Option<MeasureGroup> {
+        let scan = self.scans.front()?;
+        let start_time = scan.timestamp;
+        let end_time = scan.end_time();
+
+        let latest_imu_time = self.imu_samples.back()?.timestamp;
+        if latest_imu_time < end_time {
+            return None;
+        }
+        let image_arrived = self
+            .images
+            .back()
+            .is_some_and(|image| self.capture_time(image) >= end_time);
+        if !image_arrived && latest_imu_time < end_time + self.image_tolerance {
+            return None;
+        }
+
+        let scan = self.scans.pop_front()?;
+        let image = self.take_image(end_time);
+        let imu_samples = self.take_imu_samples(start_time, end_time);
+        Some(MeasureGroup {
+            scan,
+            imu_samples,
+            image,
+        })
+    }
+
+    fn capture_time(&self, image: &CameraImage) -> f64 {
+        image.timestamp + self.image_time_offset
+    }
+
+    /// Take the image closest to `end_time` within the tolerance, the images before it are dropped.
+    fn take_image(&mut self, end_time: f64) -> Option<CameraImage> {
+        let (index, difference) = self
+            .images
+            .iter()
+            .map(|image| (self.capture_time(image) - end_time).abs())
+            .enumerate()
+            .min_by(|(_, left), (_, right)| left.total_cmp(right))?;
+        if difference > self.image_tolerance {
+            // the images too old to be matched by any later scan
+            let stale = self
+                .images
+                .iter()
+                .take_while(|image| self.capture_time(image) < end_time)
+                .count();
+            self.images.drain(..stale);
+            return None;
+        }
+        self.images.drain(..index);
+        self.images.pop_front()
+    }
+
+    /// Take the imu samples covering `[start_time, end_time]`,
+    /// the last sample at or before `end_time` is kept for the next scan.
+    fn take_imu_samples(&mut self, start_time: f64, end_time: f64) -> Vec<ImuSample> {
+        let first = self
+            .imu_samples
+            .iter()
+            .rposition(|sample| sample.timestamp <= start_time)
+            .unwrap_or(0);
+        self.imu_samples.drain(..first);
+
+        let last = self
+            .imu_samples
+            .iter()
+            .position(|sample| sample.timestamp >= end_time)
+            .unwrap_or(self.imu_samples.len() - 1);
+        let samples = self.imu_samples.range(..=last).cloned().collect();
+
+        let kept = self
+            .imu_samples
+            .iter()
+            .rposition(|sample| sample.timestamp <= end_time)
+            .unwrap_or(0);
+        self.imu_samples.drain(..kept);
+        samples
+    }
+}
+
+fn check_order(
+    sensor: &'static str,
+    previous: Option<f64>,
+    timestamp: f64,
+) -> Result<(), SynchronizerError> {
+    match previous {
+        Some(previous) if timestamp < previous => Err(SynchronizerError::OutOfOrder {
+            sensor,
+            previous,
+            timestamp,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kornia::image::{ImageSize, allocator::CpuAllocator};
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    fn imu(timestamp: f64) -> ImuSample {
+        ImuSample {
+            timestamp,
+            angular_velocity: Vector3::zeros(),
+            linear_acceleration: Vector3::z(),
+        }
+    }
+
+    fn scan(timestamp: f64, duration: f64) -> LidarScan {
+        let point = |offset_time| LidarPoint {
+            point: Vector3::x().into(),
+            offset_time,
+            intensity: 0.0,
+            ring: 0,
+        };
+        LidarScan {
+            timestamp,
+            points: vec![point(0.0), point(duration)],
+        }
+    }
+
+    fn image(timestamp: f64) -> CameraImage {
+        let size = ImageSize {
+            width: 1,
+            height: 1,
+        };
+        CameraImage {
+            timestamp,
+            image: GrayImage::new(size, vec![0], CpuAllocator).unwrap(),
+        }
+    }
+
+    #[test]
+    fn group_covers_scan_with_imu_samples() {
+        let mut synchronizer = MeasurementSynchronizer::new(0.01);
+        (95..=111).for_each(|i| synchronizer.push_imu(imu(i as f64 / 100.0)).unwrap());
+        synchronizer.push_scan(scan(1.0, 0.1)).unwrap();
+        synchronizer.push_scan(scan(1.1, 0.1)).unwrap();
+
+        let group = synchronizer.next_group().unwrap();
+        let timestamps: Vec<_> = group
+            .imu_samples
+            .iter()
+            .map(|sample| (sample.timestamp * 100.0).round() as i32)
+            .collect();
+        assert_eq!(timestamps, (100..=110).collect::<Vec<_>>());
+        assert!(group.image.is_none());
+
+        // the imu samples of the second scan have not arrived yet
+        assert!(synchronizer.next_group().is_none());
+        (112..=125).for_each(|i| synchronizer.push_imu(imu(i as f64 / 100.0)).unwrap());
+        let group = synchronizer.next_group().unwrap();
+        assert_eq!(group.imu_samples.first().unwrap().timestamp, 1.1);
+    }
+
+    #[test]
+    fn closest_image_to_scan_end() {
+        let mut synchronizer = MeasurementSynchronizer::new(0.02);
+        synchronizer.push_scan(scan(1.0, 0.1)).unwrap();
+        [0.99, 1.06, 1.095].into_iter().for_each(|time| {
+            synchronizer.push_image(image(time)).unwrap();
+        });
+        (100..=110).for_each(|i| synchronizer.push_imu(imu(i as f64 / 100.0)).unwrap());
+
+        // waiting for an image after the scan end
+        assert!(synchronizer.next_group().is_none());
+        synchronizer.push_image(image(1.13)).unwrap();
+
+        let group = synchronizer.next_group().unwrap();
+        assert_eq!(group.image.unwrap().timestamp, 1.095);
+        assert_eq!(synchronizer.images.len(), 1);
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut synchronizer = MeasurementSynchronizer::new(0.01);
+        synchronizer.push_imu(imu(1.0)).unwrap();
+        assert!(synchronizer.push_imu(imu(0.5)).is_err());
+    }
+}
@@ -0,0 +1,533 @@
+//! The lidar-inertial-visual odometry driven by asynchronous sensor streams.
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_lite::{Stream, StreamExt, stream};
+use nalgebra::{IsometryMatrix3, Vector3};
+
+use crate::{
+    config::Config,
+    esikf::{self, StateMatrix, UncertainOdometer},
+    frame::{Body, WorldPoint},
+    imu::{self, ImuPose, ImuSample},
+    measurement::{CameraImage, LidarScan, MeasureGroup, MeasurementSynchronizer},
+    preprocess::Preprocessor,
+    vio::{self, Vio},
+    voxel_map::{VoxelMap, point::UncertainPoint},
+};
+
+/// A message of one of the sensors.
+pub enum SensorMessage {
+    Imu(ImuSample),
+    Lidar(LidarScan),
+    Camera(CameraImage),
+}
+
+/// The estimate after processing a [`MeasureGroup`].
+#[derive(Debug, Clone)]
+pub struct OdometryFrame {
+    /// the time of the odometer, the scan end, or the capture time of the image if it is later
+    pub timestamp: f64,
+    pub odometer: UncertainOdometer,
+    /// the undistorted points of the scan, registered in world frame
+    pub points: Vec<WorldPoint<f64>>,
+}
+
+/// The odometry pipeline, from the sensor messages to the registered scans.
+pub struct Odometry {
+    esikf: esikf::Config,
+    imu: imu::Config,
+    map: VoxelMap,
+    vio: Option<Vio>,
+    preprocessor: Preprocessor,
+    synchronizer: MeasurementSynchronizer,
+    /// the imu samples collected for the initialization
+    init_samples: Vec<ImuSample>,
+    /// the odometer and its time, `None` before the initialization
+    state: Option<(UncertainOdometer, f64)>,
+    /// the latest registered lidar points with the normals of their planes, to create visual points
+    lidar_points: Vec<(WorldPoint<f64>, Vector3<f64>)>,
+}
+
+impl Odometry {
+    pub fn new(config: Config) -> Self {
+        Self {
+            esikf: config.esikf,
+            imu: config.imu,
+            map: VoxelMap::new(config.voxel_map),
+            vio: config.vio.map(Vio::new),
+            preprocessor: Preprocessor::new(config.preprocess),
+            synchronizer: MeasurementSynchronizer::new(config.image_tolerance),
+            init_samples: Vec::new(),
+            state: None,
+            lidar_points: Vec::new(),
+        }
+    }
+
+    /// The current odometer, `None` before the initialization.
+    pub fn odometer(&self) -> Option<&UncertainOdometer> {
+        self.state.as_ref().map(|(odometer, _)| odometer)
+    }
+
+    pub fn map(&self) -> &VoxelMap {
+        &self.map
+    }
+
+    pub fn vio(&self) -> Option<&Vio> {
+        self.vio.as_ref()
+    }
+
+    /// Buffer a sensor message, the messages out of order are dropped,
+    /// and so are the images if the visual update is disabled.
+    ///
+    /// The points of the scans are filtered by [`Preprocessor::filter`], the scans should be
+    /// decoded but not filtered yet.
+    pub fn push(&mut self, message: SensorMessage) {
+        let result = match message {
+            SensorMessage::Imu(sample) => self.synchronizer.push_imu(sample),
+            SensorMessage::Lidar(scan) => {
+                self.synchronizer.push_scan(self.preprocessor.filter(scan))
+            }
+            SensorMessage::Camera(image) if self.vio.is_some() => {
+                self.synchronizer.push_image(image)
+            }
+            SensorMessage::Camera(_) => Ok(()),
+        };
+        if let Err(error) = result {
+            log::warn!("dropped a sensor message: {error}");
+        }
+    }
+
+    /// Process the buffered messages until a scan is registered.
+    ///
+    /// Returns `None` if the messages of the next scan have not arrived yet.
+    pub fn next_frame(&mut self) -> Option<OdometryFrame> {
+        loop {
+            let group = self.synchronizer.next_group()?;
+            if let Some(frame) = self.process(group) {
+                return Some(frame);
+            }
+        }
+    }
+
+    /// Run the odometry over the sensor streams, yields a frame for every registered scan.
+    ///
+    /// The streams are merged in timestamp order, so a stream that stays pending stalls the others,
+    /// pass [`stream::empty`] for a missing sensor. The scans still waiting for their imu samples
+    /// when the streams end are dropped, and the estimated extrinsics are logged.
+    pub fn run<I, L, C>(self, imu: I, lidar: L, camera: C) -> impl Stream<Item = OdometryFrame>
+    where
+        I: Stream<Item = ImuSample>,
+        L: Stream<Item = LidarScan>,
+        C: Stream<Item = CameraImage>,
+    {
+        let messages = TimeOrdered {
+            imu: Source::new(imu),
+            lidar: Source::new(lidar),
+            camera: Source::new(camera),
+        };
+        stream::unfold(
+            (self, messages),
+            |(mut odometry, mut messages)| async move {
+                loop {
+                    if let Some(frame) = odometry.next_frame() {
+                        return Some((frame, (odometry, messages)));
+                    }
+                    match messages.next().await {
+                        Some(message) => odometry.push(message),
+                        None => {
+                            odometry.report();
+                            return None;
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    fn process(&mut self, group: MeasureGroup) -> Option<OdometryFrame> {
+        let Some((mut odometer, mut time)) = self.state.take() else {
+            return self.initialize(group);
+        };
+        let scan_time = group.scan.timestamp;
+        let end_time = group.scan.end_time();
+        let mut image = group.image;
+        let capture_time = |odometer: &UncertainOdometer, image: &CameraImage| {
+            image.timestamp + odometer.vio.time_offset
+        };
+
+        // the measurements are applied in time order, an image captured before the scan end goes first
+        let mut poses = Vec::new();
+        if let Some(early_image) = image.take_if(|image| capture_time(&odometer, image) < end_time)
+        {
+            let capture_time = capture_time(&odometer, &early_image);
+            poses = self.propagate(
+                &mut odometer,
+                &group.imu_samples,
+                time,
+                capture_time,
+                scan_time,
+            );
+            self.visual_update(early_image, &mut odometer, &poses);
+            time = capture_time;
+        }
+        poses.extend(self.propagate(&mut odometer, &group.imu_samples, time, end_time, scan_time));
+        let points = self.register_scan(&group.scan, &mut odometer, &poses);
+        time = end_time;
+
+        if let Some(image) = image {
+            let capture_time = capture_time(&odometer, &image);
+            let poses = self.propagate(&mut odometer, &group.imu_samples, time, capture_time, time);
+            self.visual_update(image, &mut odometer, &poses);
+            time = capture_time;
+        }
+
+        Some(self.finish(odometer, time, points))
+    }
+
+    /// Collect the imu samples until the gravity alignment succeeds, then register the first scan.
+    fn initialize(&mut self, group: MeasureGroup) -> Option<OdometryFrame> {
+        let last_time = self.init_samples.last().map(|sample| sample.timestamp);
+        self.init_samples.extend(
+            group
+                .imu_samples
+                .into_iter()
+                .filter(|sample| last_time.is_none_or(|last_time| sample.timestamp > last_time)),
+        );
+        let required = self.imu.init_samples.max(2);
+        let start = self.init_samples.len().checked_sub(required)?;
+
+        let mut odometer = UncertainOdometer::new(
+            IsometryMatrix3::identity().into(),
+            imu::State::default(),
+            vio::State::default(),
+            self.imu.body_to_imu.into(),
+            StateMatrix::zeros().into(),
+        );
+        let result = imu::gravity_alignment(&mut odometer, &self.init_samples[start..], &self.imu);
+        self.init_samples.clear();
+        if let Err(error) = result {
+            log::warn!("imu initialization failed: {error}");
+            return None;
+        }
+        if let Some(vio) = &self.vio {
+            vio.initialize(&mut odometer);
+        }
+
+        // the platform is stationary during the initialization, so the scan is not undistorted
+        let points = self.register_scan(&group.scan, &mut odometer, &[]);
+        Some(self.finish(odometer, group.scan.end_time(), points))
+    }
+
+    fn finish(
+        &mut self,
+        odometer: UncertainOdometer,
+        time: f64,
+        points: Vec<WorldPoint<f64>>,
+    ) -> OdometryFrame {
+        self.synchronizer
+            .set_image_time_offset(odometer.vio.time_offset);
+        self.state = Some((odometer.clone(), time));
+        OdometryFrame {
+            timestamp: time,
+            odometer,
+            points,
+        }
+    }
+
+    /// Propagate the odometer from `from` to `to`, the time offsets of the poses are from `origin`.
+    fn propagate(
+        &self,
+        odometer: &mut UncertainOdometer,
+        samples: &[ImuSample],
+        from: f64,
+        to: f64,
+        origin: f64,
+    ) -> Vec<ImuPose> {
+        let mut poses = imu::forward_propagation(odometer, samples, from, to, &self.imu);
+        poses
+            .iter_mut()
+            .for_each(|pose| pose.offset_time += from - origin);
+        poses
+    }
+
+    /// Undistort the scan with the propagated `poses`, update the odometer with the point-to-plane
+    /// residuals and insert the registered points into the map.
+    fn register_scan(
+        &mut self,
+        scan: &LidarScan,
+        odometer: &mut UncertainOdometer,
+        poses: &[ImuPose],
+    ) -> Vec<WorldPoint<f64>> {
+        let body_points: Vec<UncertainPoint<Body>> = imu::undistort_pcl(
+            scan.points
+                .iter()
+                .map(|point| (point.point.clone(), point.offset_time)),
+            poses,
+            &odometer.body_to_imu,
+        )
+        .into_iter()
+        .map(|point| UncertainPoint::new_body_point(point, self.map.config()))
+        .collect();
+
+        // the point-to-plane matches of the last iteration, the lidar points of the vio update
+        let mut residuals = Vec::new();
+        if !self.map.is_empty() {
+            let converged = odometer.iterated_update(&self.esikf, |odometer| {
+                residuals = self.map.build_residual(&body_points, odometer);
+                residuals
+                    .iter()
+                    .map(|residual| residual.measurement(odometer))
+                    .collect::<Vec<_>>()
+            });
+            if !converged {
+                log::debug!("the lidar update at {} is not converged", scan.timestamp);
+            }
+        }
+
+        if self.vio.is_some() {
+            let body_to_world = odometer.body_to_imu * odometer.isometry;
+            self.lidar_points = residuals
+                .into_iter()
+                .map(|residual| {
+                    (
+                        body_to_world.transform_point(&residual.body_point),
+                        residual.normal,
+                    )
+                })
+                .collect();
+        }
+
+        let world_points: Vec<_> = body_points
+            .into_iter()
+            .map(|point| UncertainPoint::from_body_point(point, odometer, &odometer.body_to_imu))
+            .collect();
+        let points = world_points.iter().map(|point| (**point).clone()).collect();
+        self.map.extend(world_points);
+        points
+    }
+
+    /// Update the odometer propagated to the capture time with the image,
+    /// the visual points are created from the latest registered lidar points.
+    fn visual_update(
+        &mut self,
+        image: CameraImage,
+        odometer: &mut UncertainOdometer,
+        poses: &[ImuPose],
+    ) {
+        let Some(vio) = &mut self.vio else {
+            return;
+        };
+        let angular_velocity = poses
+            .last()
+            .map_or_else(Vector3::zeros, |pose| pose.angular_velocity);
+        vio.process(
+            image.image,
+            odometer,
+            &angular_velocity,
+            &self.esikf,
+            self.lidar_points.drain(..),
+        );
+    }
+
+    /// Log the estimated extrinsics at the end of a run.
+    fn report(&self) {
+        let Some((odometer, _)) = &self.state else {
+            log::info!("the odometry ended before the initialization");
+            return;
+        };
+        log::info!(
+            "estimated lidar-imu extrinsic: {}",
+            odometer.body_to_imu.inner
+        );
+        if self.vio.is_some() {
+            log::info!(
+                "estimated camera-imu extrinsic: {}, time offset: {} s",
+                odometer.vio.camera_to_imu.inner,
+                odometer.vio.time_offset
+            );
+        }
+    }
+}
+
+/// A sensor stream with its next message.
+struct Source<S: Stream> {
+    stream: Pin<Box<S>>,
+    head: Option<S::Item>,
+    finished: bool,
+}
+
+impl<S: Stream> Source<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: Box::pin(stream),
+            head: None,
+            finished: false,
+        }
+    }
+
+    /// Poll the next message into the head, returns `false` if the stream is pending.
+    fn poll_head(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.head.is_none() && !self.finished {
+            match self.stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => self.head = Some(item),
+                Poll::Ready(None) => self.finished = true,
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Merges the sensor streams in timestamp order, waits until every unfinished stream has a message.
+struct TimeOrdered<I: Stream, L: Stream, C: Stream> {
+    imu: Source<I>,
+    lidar: Source<L>,
+    camera: Source<C>,
+}
+
+impl<I, L, C> Stream for TimeOrdered<I, L, C>
+where
+    I: Stream<Item = ImuSample>,
+    L: Stream<Item = LidarScan>,
+    C: Stream<Item = CameraImage>,
+{
+    type Item = SensorMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let ready = [
+            this.imu.poll_head(cx),
+            this.lidar.poll_head(cx),
+            this.camera.poll_head(cx),
+        ];
+        if ready.contains(&false) {
+            return Poll::Pending;
+        }
+
+        let timestamps = [
+            this.imu.head.as_ref().map(|sample| sample.timestamp),
+            this.lidar.head.as_ref().map(|scan| scan.timestamp),
+            this.camera.head.as_ref().map(|image| image.timestamp),
+        ];
+        let earliest = timestamps
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, timestamp)| Some((index, timestamp?)))
+            .min_by(|(_, left), (_, right)| left.total_cmp(right))
+            .map(|(index, _)| index);
+        Poll::Ready(match earliest {
+            Some(0) => this.imu.head.take().map(SensorMessage::Imu),
+            Some(1) => this.lidar.head.take().map(SensorMessage::Lidar),
+            Some(_) => this.camera.head.take().map(SensorMessage::Camera),
+            None => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use super::*;
+    use crate::{frame::BodyPoint, measurement::LidarPoint, preprocess, voxel_map};
+
+    fn config() -> Config {
+        Config {
+            esikf: esikf::Config::default(),
+            imu: imu::Config {
+                body_to_imu: IsometryMatrix3::identity(),
+                extrinsic_estimation: false,
+                extrinsic_rotation_variance: 0.0,
+                extrinsic_translation_variance: 0.0,
+                gyro_noise: 0.01,
+                acc_noise: 0.01,
+                bias_gyro_noise: 1e-4,
+                bias_acc_noise: 1e-4,
+                init_samples: 20,
+                init_max_acc_std: 0.1,
+                init_max_gyro_std: 0.01,
+                ..imu::Config::default()
+            },
+            voxel_map: voxel_map::Config::default(),
+            preprocess: preprocess::Config::default(),
+            vio: None,
+            image_tolerance: 0.01,
+        }
+    }
+
+    /// A scan of a 4m x 4m x 3m room from `position`, the points are spread over 0.1s.
+    ///
+    /// The points are sampled away from the edges of the room, so that every voxel contains
+    /// a single plane, and jittered in the plane, so that no leaf contains only collinear points.
+    fn room_scan(timestamp: f64, position: Vector3<f64>) -> LidarScan {
+        let face = |axis: usize, value: f64| {
+            (0..14).flat_map(move |i| {
+                (0..14).map(move |j| {
+                    let mut point = Vector3::zeros();
+                    point[axis] = value;
+                    let jitter = |seed: usize| (seed % 7) as f64 * 0.01;
+                    point[(axis + 1) % 3] = 0.5 + i as f64 * 0.15 + jitter(3 * i + 5 * j);
+                    point[(axis + 2) % 3] = 0.5 + j as f64 * 0.15 + jitter(2 * i + 3 * j);
+                    point
+                })
+            })
+        };
+        let points: Vec<_> = [
+            (0, 0.05),
+            (0, 3.95),
+            (1, 0.05),
+            (1, 3.95),
+            (2, 0.05),
+            (2, 2.95),
+        ]
+        .into_iter()
+        .flat_map(|(axis, value)| face(axis, value))
+        .collect();
+        let count = points.len() as f64;
+        LidarScan {
+            timestamp,
+            points: points
+                .into_iter()
+                .enumerate()
+                .map(|(index, point)| LidarPoint {
+                    point: BodyPoint::from(point - position),
+                    offset_time: index as f64 / count * 0.1,
+                    intensity: 0.0,
+                    ring: 0,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn stationary_in_room() {
+        let position = Vector3::new(2.0, 1.8, 1.4);
+        let imu_samples = (0..=240).map(|i| ImuSample {
+            timestamp: i as f64 * 0.005,
+            angular_velocity: Vector3::zeros(),
+            linear_acceleration: Vector3::new(0.0, 0.0, imu::GRAVITY_NORM),
+        });
+        let scans = (0..10).map(|i| room_scan(i as f64 * 0.1, position));
+
+        let odometry = Odometry::new(config());
+        let frames: Vec<_> = future::block_on(
+            odometry
+                .run(
+                    stream::iter(imu_samples),
+                    stream::iter(scans),
+                    stream::empty(),
+                )
+                .collect(),
+        );
+
+        assert_eq!(frames.len(), 10);
+        let last = frames.last().unwrap();
+        assert!((last.timestamp - 1.0).abs() < 1e-3);
+        assert!(last.odometer.isometry.translation.vector.norm() < 1e-2);
+        assert!(last.odometer.isometry.rotation.angle() < 1e-3);
+        assert_eq!(last.points.len(), 6 * 14 * 14);
+    }
+}
@@ -0,0 +1,339 @@
+//! The preprocessing of the lidar points, like the `preprocess` of FAST-LIVO2.
+//!
+//! The points of a driver are decoded by [`decode`] into a [`LidarScan`] with the point times
+//! relative to the scan start, the dataset readers decode their scans with the configured [`Lidar`].
+//! Every scan is then filtered once by [`Preprocessor::filter`] when it is pushed to the odometry,
+//! the points are skipped, the ones within the blind distance or out of the field of view are
+//! dropped and the remaining ones are downsampled by a [`VoxelGrid`].
+pub mod voxel_grid;
+
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use crate::{
+    config::{ConfigError, ensure},
+    frame::BodyPoint,
+    measurement::{LidarPoint, LidarScan},
+};
+use voxel_grid::VoxelGrid;
+
+/// The lidars whose points need a specific handling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Lidar {
+    /// Livox Avia, the points have a line, a tag and a time offset in ns
+    Avia,
+    /// Livox Mid-360, like the Avia with 4 lines
+    Mid360,
+    /// the time `t` of the points is in ns from the scan start
+    Ouster,
+    /// the `time` of the points is in s from the stamp, negative if the stamp is the scan end
+    Velodyne,
+    /// the `timestamp` of the points is their absolute time in s, like the Pandar XT32
+    Hesai,
+}
+
+impl Lidar {
+    pub fn is_livox(self) -> bool {
+        matches!(self, Self::Avia | Self::Mid360)
+    }
+
+    /// The time of a point from the stamp of its scan in s, from the time field of the driver.
+    pub fn offset_time(self, time: f64, stamp: f64) -> f64 {
+        match self {
+            Self::Avia | Self::Mid360 | Self::Ouster => time / 1e9,
+            Self::Velodyne => time,
+            Self::Hesai => time - stamp,
+        }
+    }
+}
+
+/// Whether a Livox point is of the first return, by the bits 4 and 5 of its tag,
+/// the points of the other returns are dropped like FAST-LIVO2.
+pub fn is_first_return(tag: u8) -> bool {
+    matches!(tag & 0x30, 0x00 | 0x10)
+}
+
+/// A point of a lidar driver.
+#[derive(Debug, Clone)]
+pub struct RawPoint {
+    pub point: BodyPoint<f64>,
+    /// the time field of the driver, its unit depends on the [`Lidar`]
+    pub time: f64,
+    pub intensity: f32,
+    /// the laser ring, or the line of a Livox
+    pub ring: u16,
+    /// the tag of a Livox, 0 for the other lidars
+    pub tag: u8,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// the lidar of the scans, `None` if their points need no specific handling
+    pub lidar: Option<Lidar>,
+    /// the number of lines of a Livox, the points of the other lines are dropped
+    pub scan_lines: u16,
+    /// keep one point out of this many, 0 keeps all the points like 1
+    pub point_filter_num: usize,
+    /// the points closer than this are dropped, like the points on the vehicle, in m
+    pub blind: f64,
+    /// the horizontal field of view around the x axis, in degrees
+    pub fov_degree: f64,
+    /// the leaf size of the voxel grid downsampling in m, 0 to keep every point
+    pub downsample_size: f64,
+    /// the point kept in each voxel of the downsampling
+    pub downsample_mode: voxel_grid::Mode,
+}
+
+impl Config {
+    pub(crate) fn validate(&self, section: &str) -> Result<(), ConfigError> {
+        let positive = "should be positive";
+        let non_negative = "should not be negative";
+        ensure(self.scan_lines > 0, section, "scan_lines", positive)?;
+        ensure(
+            self.point_filter_num > 0,
+            section,
+            "point_filter_num",
+            positive,
+        )?;
+        ensure(self.blind >= 0.0, section, "blind", non_negative)?;
+        ensure(
+            self.fov_degree > 0.0 && self.fov_degree <= 360.0,
+            section,
+            "fov_degree",
+            "should be in (0, 360]",
+        )?;
+        ensure(
+            self.downsample_size >= 0.0,
+            section,
+            "downsample_size",
+            non_negative,
+        )
+    }
+}
+
+impl Default for Config {
+    /// The preprocess parameters of FAST-LIVO2, without downsampling.
+    fn default() -> Self {
+        Self {
+            lidar: None,
+            scan_lines: 6,
+            point_filter_num: 1,
+            blind: 0.01,
+            fov_degree: 360.0,
+            downsample_size: 0.0,
+            downsample_mode: voxel_grid::Mode::Centroid,
+        }
+    }
+}
+
+/// Turns the points of a driver into the scans of the odometry.
+pub struct Preprocessor {
+    config: Config,
+    /// `None` if the points are not downsampled
+    voxel_grid: Option<VoxelGrid>,
+}
+
+impl Preprocessor {
+    pub fn new(config: Config) -> Self {
+        let voxel_grid = (config.downsample_size > 0.0)
+            .then(|| VoxelGrid::new(config.downsample_size, config.downsample_mode));
+        Self { config, voxel_grid }
+    }
+
+    /// Decode the points of a driver with the configured [`Lidar`], see [`decode`].
+    ///
+    /// The scan is not filtered, the odometry filters the scans pushed to it.
+    pub fn decode(&self, timestamp: f64, points: &[RawPoint]) -> LidarScan {
+        decode(self.config.lidar, timestamp, points)
+    }
+
+    /// Filter the points of a decoded scan, their times are kept.
+    pub fn filter(&self, mut scan: LidarScan) -> LidarScan {
+        let config = &self.config;
+        let livox = config.lidar.is_some_and(Lidar::is_livox);
+        let blind_squared = config.blind * config.blind;
+        let half_fov = config.fov_degree.to_radians() / 2.0;
+        let mut previous = None;
+        let points: Vec<_> = scan
+            .points
+            .into_iter()
+            // the config of the odometry may not be validated
+            .step_by(config.point_filter_num.max(1))
+            .filter(|point| !livox || point.ring < config.scan_lines)
+            .filter(|point| {
+                // a Livox may repeat a point
+                let coordinates = point.point.coords;
+                let repeated = livox
+                    && previous.is_some_and(|previous: Vector3<f64>| {
+                        (previous - coordinates).amax() < 1e-7
+                    });
+                previous = Some(coordinates);
+                !repeated
+            })
+            .filter(|point| point.point.coords.norm_squared() > blind_squared)
+            .filter(|point| {
+                config.fov_degree >= 360.0 || point.point.y.atan2(point.point.x).abs() <= half_fov
+            })
+            .collect();
+        scan.points = match &self.voxel_grid {
+            Some(voxel_grid) => voxel_grid.filter(&points),
+            None => points,
+        };
+        scan
+    }
+}
+
+/// Decode the points of a driver stamped at `timestamp`, the second returns of a Livox are dropped.
+///
+/// The scan starts at its stamp, or at its earliest point if it is before the stamp.
+/// Without a [`Lidar`], the point times are offsets in s already.
+pub fn decode(lidar: Option<Lidar>, timestamp: f64, points: &[RawPoint]) -> LidarScan {
+    let livox = lidar.is_some_and(Lidar::is_livox);
+    let points = points
+        .iter()
+        .filter(|point| !livox || is_first_return(point.tag))
+        .map(|point| LidarPoint {
+            point: point.point.clone(),
+            offset_time: lidar.map_or(point.time, |lidar| lidar.offset_time(point.time, timestamp)),
+            intensity: point.intensity,
+            ring: point.ring,
+        })
+        .collect();
+    normalize_times(LidarScan { timestamp, points })
+}
+
+/// Move the scan start to its earliest point, as some drivers stamp the scans at their end.
+fn normalize_times(mut scan: LidarScan) -> LidarScan {
+    let earliest = scan
+        .points
+        .iter()
+        .map(|point| point.offset_time)
+        .fold(0.0, f64::min);
+    scan.timestamp += earliest;
+    scan.points
+        .iter_mut()
+        .for_each(|point| point.offset_time -= earliest);
+    scan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_point(x: f64, y: f64, time: f64, ring: u16, tag: u8) -> RawPoint {
+        RawPoint {
+            point: Vector3::new(x, y, 0.0).into(),
+            time,
+            intensity: 1.0,
+            ring,
+            tag,
+        }
+    }
+
+    #[test]
+    fn livox() {
+        let preprocessor = Preprocessor::new(Config {
+            lidar: Some(Lidar::Mid360),
+            scan_lines: 4,
+            blind: 0.5,
+            ..Config::default()
+        });
+        let points = [
+            raw_point(1.0, 0.0, 0.0, 0, 0x10),
+            // repeated
+            raw_point(1.0, 0.0, 1e6, 1, 0x10),
+            // second return
+            raw_point(2.0, 0.0, 2e6, 1, 0x20),
+            // out of the lines
+            raw_point(3.0, 0.0, 3e6, 4, 0x00),
+            // within the blind distance
+            raw_point(0.1, 0.2, 4e6, 2, 0x00),
+            raw_point(-5.0, 1.0, 5e6, 3, 0x00),
+        ];
+        let scan = preprocessor.decode(10.0, &points);
+        // only the second return is dropped by the decoding
+        assert_eq!(scan.points.len(), 5);
+        let scan = preprocessor.filter(scan);
+        assert_eq!(scan.timestamp, 10.0);
+        let kept: Vec<_> = scan
+            .points
+            .iter()
+            .map(|point| (point.point.x, point.offset_time))
+            .collect();
+        assert_eq!(kept, [(1.0, 0.0), (-5.0, 0.005)]);
+    }
+
+    #[test]
+    fn point_times() {
+        let decode = |lidar, timestamp, times: [f64; 2]| {
+            let preprocessor = Preprocessor::new(Config {
+                lidar: Some(lidar),
+                ..Config::default()
+            });
+            let points = times.map(|time| raw_point(1.0, 0.0, time, 0, 0));
+            let scan = preprocessor.decode(timestamp, &points);
+            let times: Vec<_> = scan.points.iter().map(|point| point.offset_time).collect();
+            (scan.timestamp, times)
+        };
+        assert_eq!(
+            decode(Lidar::Ouster, 1.0, [0.0, 5e7]),
+            (1.0, vec![0.0, 0.05])
+        );
+        // stamped at the scan end
+        assert_eq!(
+            decode(Lidar::Velodyne, 1.0, [-0.1, 0.0]),
+            (0.9, vec![0.0, 0.1])
+        );
+        let (timestamp, times) = decode(Lidar::Hesai, 100.0, [100.0, 100.05]);
+        assert!(timestamp == 100.0 && (times[1] - 0.05).abs() < 1e-9);
+    }
+
+    #[test]
+    fn filter() {
+        let preprocessor = Preprocessor::new(Config {
+            fov_degree: 90.0,
+            downsample_size: 1.0,
+            downsample_mode: voxel_grid::Mode::NearestToCentroid,
+            ..Config::default()
+        });
+        let points = [(2.1, 0.1), (2.9, 0.9), (3.5, 0.1), (0.0, 3.0), (3.0, -3.5)]
+            .map(|(x, y)| LidarPoint {
+                point: Vector3::new(x, y, 0.5).into(),
+                offset_time: x / 10.0,
+                intensity: y as f32,
+                ring: 0,
+            })
+            .to_vec();
+        let scan = preprocessor.filter(LidarScan {
+            timestamp: 0.0,
+            points,
+        });
+        let kept: Vec<_> = scan.points.iter().map(|point| point.point.x).collect();
+        assert_eq!(kept, [2.1, 3.5]);
+        assert_eq!(scan.points[1].offset_time, 0.35);
+
+        let kept = |point_filter_num| {
+            let preprocessor = Preprocessor::new(Config {
+                point_filter_num,
+                ..Config::default()
+            });
+            let points = (0..5)
+                .map(|index| LidarPoint {
+                    point: Vector3::new(index as f64 + 1.0, 0.0, 0.0).into(),
+                    offset_time: 0.0,
+                    intensity: 0.0,
+                    ring: 0,
+                })
+                .collect();
+            let scan = preprocessor.filter(LidarScan {
+                timestamp: 0.0,
+                points,
+            });
+            scan.points.len()
+        };
+        assert_eq!(kept(2), 3);
+        assert_eq!(kept(0), 5);
+    }
+}
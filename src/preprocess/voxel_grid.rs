@@ -0,0 +1,144 @@
+//! The voxel grid downsampling of the scans, one point is kept in each voxel of the grid.
+use nalgebra::Vector3;
+use nohash_hasher::IntMap;
+use serde::Deserialize;
+
+use crate::{frame::Body, measurement::LidarPoint, voxel_map::VoxelIndex};
+
+/// The point kept in each voxel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// the centroid of the points, with their mean time and intensity, like the voxel grid of PCL
+    #[default]
+    Centroid,
+    /// the point nearest to the centroid, with its own time and intensity
+    NearestToCentroid,
+}
+
+/// Downsamples the points in a grid of cubic voxels.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    /// the side length of the voxels, in m
+    pub leaf_size: f64,
+    pub mode: Mode,
+}
+
+/// The sums of the points of a voxel.
+struct Voxel {
+    coordinates: Vector3<f64>,
+    time: f64,
+    intensity: f64,
+    count: usize,
+    /// the first point of the voxel, for its ring
+    first: usize,
+}
+
+impl VoxelGrid {
+    pub fn new(leaf_size: f64, mode: Mode) -> Self {
+        Self { leaf_size, mode }
+    }
+
+    /// The downsampled points in the order of their voxels' first point.
+    pub fn filter(&self, points: &[LidarPoint]) -> Vec<LidarPoint> {
+        let mut indices = IntMap::<VoxelIndex<Body>, usize>::default();
+        let mut voxels = Vec::<Voxel>::new();
+        let point_voxels: Vec<_> = points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| {
+                let voxel = *indices
+                    .entry(VoxelIndex::from_point(&point.point, self.leaf_size))
+                    .or_insert_with(|| {
+                        voxels.push(Voxel {
+                            coordinates: Vector3::zeros(),
+                            time: 0.0,
+                            intensity: 0.0,
+                            count: 0,
+                            first: index,
+                        });
+                        voxels.len() - 1
+                    });
+                let sums = &mut voxels[voxel];
+                sums.coordinates += point.point.coords;
+                sums.time += point.offset_time;
+                sums.intensity += point.intensity as f64;
+                sums.count += 1;
+                voxel
+            })
+            .collect();
+        let centroid = |voxel: &Voxel| voxel.coordinates / voxel.count as f64;
+
+        match self.mode {
+            Mode::Centroid => voxels
+                .iter()
+                .map(|voxel| {
+                    let count = voxel.count as f64;
+                    LidarPoint {
+                        point: centroid(voxel).into(),
+                        offset_time: voxel.time / count,
+                        intensity: (voxel.intensity / count) as f32,
+                        ring: points[voxel.first].ring,
+                    }
+                })
+                .collect(),
+            Mode::NearestToCentroid => {
+                let centroids: Vec<_> = voxels.iter().map(centroid).collect();
+                let mut nearest = vec![(f64::INFINITY, 0); voxels.len()];
+                points
+                    .iter()
+                    .zip(point_voxels)
+                    .enumerate()
+                    .for_each(|(index, (point, voxel))| {
+                        let distance = (point.point.coords - centroids[voxel]).norm_squared();
+                        if distance < nearest[voxel].0 {
+                            nearest[voxel] = (distance, index);
+                        }
+                    });
+                nearest
+                    .into_iter()
+                    .map(|(_, index)| points[index].clone())
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64, offset_time: f64, intensity: f32) -> LidarPoint {
+        LidarPoint {
+            point: Vector3::new(x, y, 0.5).into(),
+            offset_time,
+            intensity,
+            ring: (x * 10.0) as u16,
+        }
+    }
+
+    #[test]
+    fn modes() {
+        let points = [
+            point(0.1, 0.2, 0.01, 10.0),
+            point(-0.5, 0.5, 0.02, 1.0),
+            point(0.9, 0.3, 0.03, 20.0),
+            point(0.5, 0.4, 0.05, 30.0),
+        ];
+        let centroids = VoxelGrid::new(1.0, Mode::Centroid).filter(&points);
+        assert_eq!(centroids.len(), 2);
+        assert!((centroids[0].point.coords - Vector3::new(0.5, 0.3, 0.5)).norm() < 1e-12);
+        assert!((centroids[0].offset_time - 0.03).abs() < 1e-12);
+        assert_eq!(centroids[0].intensity, 20.0);
+        assert_eq!(centroids[0].ring, 1);
+        // the negative coordinates are in their own voxel
+        assert_eq!(centroids[1].point.x, -0.5);
+
+        let nearest = VoxelGrid::new(1.0, Mode::NearestToCentroid).filter(&points);
+        assert_eq!(nearest.len(), 2);
+        assert_eq!(nearest[0].point.x, 0.5);
+        assert_eq!(nearest[0].offset_time, 0.05);
+        assert_eq!(nearest[0].intensity, 30.0);
+        assert_eq!(nearest[1].offset_time, 0.02);
+    }
+}
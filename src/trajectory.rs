@@ -0,0 +1,475 @@
+//! Timestamped poses, like the estimated odometry or the ground truth of a dataset.
+//!
+//! The trajectories are read and written in the formats of the evaluation tools like evo,
+//! see [`Format`].
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use nalgebra::{IsometryMatrix3, Quaternion, Translation3, UnitQuaternion, Vector6};
+
+use crate::{
+    config,
+    dataset::{DatasetError, csv::Csv},
+    odometry::OdometryFrame,
+};
+
+/// A pose at a timestamp.
+#[derive(Debug, Clone)]
+pub struct StampedPose {
+    /// timestamp in seconds
+    pub timestamp: f64,
+    pub pose: IsometryMatrix3<f64>,
+    /// the variances of the rotation and the translation errors,
+    /// the diagonal of the pose block of the odometer covariance
+    pub variances: Option<Vector6<f64>>,
+}
+
+impl StampedPose {
+    /// A pose without variances.
+    pub fn new(timestamp: f64, pose: IsometryMatrix3<f64>) -> Self {
+        Self {
+            timestamp,
+            pose,
+            variances: None,
+        }
+    }
+}
+
+/// The imu pose of the frame, with its variances.
+impl From<&OdometryFrame> for StampedPose {
+    fn from(frame: &OdometryFrame) -> Self {
+        let covariance = &frame.odometer.covariance;
+        let rotation = covariance.view_rotation().diagonal();
+        let translation = covariance.view_translation().diagonal();
+        Self {
+            timestamp: frame.timestamp,
+            pose: frame.odometer.isometry.inner,
+            variances: Some(Vector6::new(
+                rotation.x,
+                rotation.y,
+                rotation.z,
+                translation.x,
+                translation.y,
+                translation.z,
+            )),
+        }
+    }
+}
+
+/// The poses of a trajectory, in time order.
+#[derive(Debug, Clone, Default)]
+pub struct Trajectory {
+    pub poses: Vec<StampedPose>,
+}
+
+impl Trajectory {
+    pub fn new(poses: Vec<StampedPose>) -> Self {
+        Self { poses }
+    }
+
+    pub fn len(&self) -> usize {
+        self.poses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.poses.is_empty()
+    }
+
+    /// Read the poses of the format, without their variances.
+    ///
+    /// The KITTI poses have no timestamps, they are stamped by their index.
+    pub fn read(reader: impl BufRead, format: Format) -> Result<Self, DatasetError> {
+        if format == Format::Euroc {
+            return Csv::read(reader, "euroc").and_then(|csv| csv.trajectory());
+        }
+        let invalid = |number: usize, message: &str| DatasetError::Format {
+            format: format.name(),
+            message: format!("line {number}: {message}"),
+        };
+        let mut index = 0;
+        reader
+            .lines()
+            .enumerate()
+            .filter_map(|(number, line)| {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(error) => return Some(Err(error.into())),
+                };
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let number = number + 1;
+                let values = match line
+                    .split_whitespace()
+                    .map(str::parse)
+                    .collect::<Result<Vec<f64>, _>>()
+                {
+                    Ok(values) => values,
+                    Err(error) => return Some(Err(invalid(number, &error.to_string()))),
+                };
+                let pose = match format {
+                    Format::Tum => match values[..] {
+                        [timestamp, x, y, z, qx, qy, qz, qw, ..] => Ok(StampedPose::new(
+                            timestamp,
+                            IsometryMatrix3::from_parts(
+                                Translation3::new(x, y, z),
+                                UnitQuaternion::from_quaternion(Quaternion::new(qw, qx, qy, qz))
+                                    .to_rotation_matrix(),
+                            ),
+                        )),
+                        _ => Err(invalid(number, "expected 8 numbers")),
+                    },
+                    _ => match values[..] {
+                        [r00, r01, r02, tx, r10, r11, r12, ty, r20, r21, r22, tz, ..] => {
+                            config::isometry(
+                                &[r00, r01, r02, r10, r11, r12, r20, r21, r22],
+                                &[tx, ty, tz],
+                            )
+                            .map(|pose| StampedPose::new(index as f64, pose))
+                            .map_err(|message| invalid(number, message))
+                        }
+                        _ => Err(invalid(number, "expected 12 numbers")),
+                    },
+                };
+                index += 1;
+                Some(pose)
+            })
+            .collect::<Result<_, _>>()
+            .map(Self::new)
+    }
+
+    /// Like [`Trajectory::read`], but from a file.
+    pub fn from_path(path: impl AsRef<Path>, format: Format) -> Result<Self, DatasetError> {
+        Self::read(BufReader::new(File::open(path)?), format)
+    }
+
+    /// Write the poses, with their variances as given by `variances`.
+    pub fn write<W: Write>(
+        &self,
+        writer: W,
+        format: Format,
+        variances: Variances<W>,
+    ) -> io::Result<()> {
+        let mut writer = TrajectoryWriter::new(writer, format, variances)?;
+        self.poses.iter().try_for_each(|pose| writer.write(pose))?;
+        writer.flush()
+    }
+
+    /// Like [`Trajectory::write`], but to a file. The variances are appended to the EuRoC rows,
+    /// and written to the [`variances_path`] of the file for TUM and KITTI.
+    pub fn write_path(
+        &self,
+        path: impl AsRef<Path>,
+        format: Format,
+        variances: bool,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        let variances = match (variances, format) {
+            (false, _) => Variances::None,
+            (true, Format::Euroc) => Variances::Columns,
+            (true, Format::Tum | Format::Kitti) => {
+                Variances::File(BufWriter::new(File::create(variances_path(path))?))
+            }
+        };
+        self.write(BufWriter::new(File::create(path)?), format, variances)
+    }
+}
+
+impl FromIterator<StampedPose> for Trajectory {
+    fn from_iter<I: IntoIterator<Item = StampedPose>>(poses: I) -> Self {
+        Self::new(poses.into_iter().collect())
+    }
+}
+
+/// The trajectory formats.
+///
+/// The TUM and KITTI readers of evo expect exactly 8 and 12 values per row, so their variances
+/// are written to a separate file, see [`Variances`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `timestamp tx ty tz qx qy qz qw`, separated by spaces, the timestamp in s
+    Tum,
+    /// the 12 values of the row-major 3x4 pose matrix, separated by spaces, without timestamp
+    Kitti,
+    /// `timestamp,tx,ty,tz,qw,qx,qy,qz` with a header like the EuRoC ground truth,
+    /// the timestamp in ns
+    Euroc,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Tum => "tum",
+            Self::Kitti => "kitti",
+            Self::Euroc => "euroc",
+        }
+    }
+}
+
+/// Where the variances of the poses are written.
+pub enum Variances<W> {
+    None,
+    /// appended to the rows, only for EuRoC whose readers ignore the extra columns
+    Columns,
+    /// to a separate file of `timestamp var_r_x var_r_y var_r_z var_p_x var_p_y var_p_z` rows,
+    /// one per pose, the timestamp in s
+    File(W),
+}
+
+/// The file of the variances of the trajectory file at `path`, `<path>.var`.
+pub fn variances_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut path = path.as_ref().as_os_str().to_owned();
+    path.push(".var");
+    path.into()
+}
+
+/// Writes the poses one by one, like the frames of a running odometry.
+pub struct TrajectoryWriter<W> {
+    writer: W,
+    format: Format,
+    variances: Variances<W>,
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    /// The writer of the format, the headers are written right away.
+    /// [`Variances::Columns`] is only supported by EuRoC, an `InvalidInput` error otherwise.
+    pub fn new(mut writer: W, format: Format, mut variances: Variances<W>) -> io::Result<Self> {
+        let columns = matches!(variances, Variances::Columns);
+        if columns && format != Format::Euroc {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the variances can not be appended to the {} rows",
+                    format.name()
+                ),
+            ));
+        }
+        if format == Format::Euroc {
+            write!(
+                writer,
+                "#timestamp [ns],p_RS_R_x [m],p_RS_R_y [m],p_RS_R_z [m],\
+                 q_RS_w [],q_RS_x [],q_RS_y [],q_RS_z []"
+            )?;
+            if columns {
+                write!(
+                    writer,
+                    ",var_r_x [rad^2],var_r_y [rad^2],var_r_z [rad^2],\
+                     var_p_x [m^2],var_p_y [m^2],var_p_z [m^2]"
+                )?;
+            }
+            writeln!(writer)?;
+        }
+        if let Variances::File(variances) = &mut variances {
+            writeln!(
+                variances,
+                "# timestamp var_r_x var_r_y var_r_z var_p_x var_p_y var_p_z"
+            )?;
+        }
+        Ok(Self {
+            writer,
+            format,
+            variances,
+        })
+    }
+
+    /// Write a pose, its variances are `nan` if they are written but unknown.
+    pub fn write(&mut self, pose: &StampedPose) -> io::Result<()> {
+        let translation = pose.pose.translation.vector;
+        let rotation = UnitQuaternion::from_rotation_matrix(&pose.pose.rotation);
+        let [x, y, z] = [translation.x, translation.y, translation.z];
+        let [qx, qy, qz, qw] = [rotation.i, rotation.j, rotation.k, rotation.w];
+        let mut values: Vec<_> = match self.format {
+            Format::Tum => std::iter::once(format!("{:.9}", pose.timestamp))
+                .chain([x, y, z, qx, qy, qz, qw].map(|value| value.to_string()))
+                .collect(),
+            Format::Kitti => {
+                let matrix = pose.pose.to_homogeneous();
+                (0..3)
+                    .flat_map(|row| (0..4).map(move |column| matrix[(row, column)].to_string()))
+                    .collect()
+            }
+            Format::Euroc => std::iter::once(nanoseconds(pose.timestamp))
+                .chain([x, y, z, qw, qx, qy, qz].map(|value| value.to_string()))
+                .collect(),
+        };
+        let variances: Vec<_> = match pose.variances {
+            Some(variances) => variances.iter().map(f64::to_string).collect(),
+            None => vec!["nan".to_owned(); 6],
+        };
+        match &mut self.variances {
+            Variances::None => {}
+            Variances::Columns => values.extend(variances),
+            Variances::File(writer) => {
+                writeln!(writer, "{:.9} {}", pose.timestamp, variances.join(" "))?
+            }
+        }
+        let separator = match self.format {
+            Format::Tum | Format::Kitti => " ",
+            Format::Euroc => ",",
+        };
+        writeln!(self.writer, "{}", values.join(separator))
+    }
+
+    /// Flush the trajectory and the variances.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Variances::File(variances) = &mut self.variances {
+            variances.flush()?;
+        }
+        self.writer.flush()
+    }
+
+    /// The writers of the trajectory and of the variances, if they are written to a file.
+    pub fn into_inner(self) -> (W, Option<W>) {
+        let variances = match self.variances {
+            Variances::File(variances) => Some(variances),
+            _ => None,
+        };
+        (self.writer, variances)
+    }
+}
+
+/// The timestamp in integer ns, the seconds and the fraction of the epoch times are split
+/// to keep their precision.
+fn nanoseconds(timestamp: f64) -> String {
+    if timestamp.abs() < 1e6 {
+        return ((timestamp * 1e9).round() as i64).to_string();
+    }
+    let seconds = timestamp.floor();
+    let fraction = ((timestamp - seconds) * 1e9).round() as u64;
+    let (seconds, fraction) = match fraction {
+        1_000_000_000 => (seconds as i64 + 1, 0),
+        fraction => (seconds as i64, fraction),
+    };
+    format!("{seconds}{fraction:09}")
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    fn trajectory() -> Trajectory {
+        let mut pose = StampedPose::new(
+            1403636579.75,
+            IsometryMatrix3::new(
+                Vector3::new(1.0, -2.0, 0.5),
+                Vector3::z() * std::f64::consts::FRAC_PI_2,
+            ),
+        );
+        pose.variances = Some(Vector6::new(1e-4, 1e-4, 2e-4, 0.01, 0.01, 0.02));
+        [StampedPose::new(0.25, IsometryMatrix3::identity()), pose]
+            .into_iter()
+            .collect()
+    }
+
+    fn lines(data: &[u8]) -> Vec<String> {
+        String::from_utf8(data.to_vec())
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// The lines of the trajectory, and of the variances if they are written to a file.
+    fn written(format: Format, variances: bool) -> (Vec<String>, Vec<String>) {
+        let (mut data, mut variance_data) = (Vec::new(), Vec::new());
+        let variances = match (variances, format) {
+            (false, _) => Variances::None,
+            (true, Format::Euroc) => Variances::Columns,
+            (true, _) => Variances::File(&mut variance_data),
+        };
+        trajectory().write(&mut data, format, variances).unwrap();
+        (lines(&data), lines(&variance_data))
+    }
+
+    fn numbers(line: &str, separator: char) -> Vec<f64> {
+        line.split(separator)
+            .map(|value| value.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn formats() {
+        let (tum, _) = written(Format::Tum, false);
+        assert_eq!(tum[0], "0.250000000 0 0 0 0 0 0 1");
+        let values = numbers(&tum[1], ' ');
+        assert_eq!(values.len(), 8);
+        assert!(tum[1].starts_with("1403636579.750000000 1 -2 0.5 "));
+        assert!((values[6] - std::f64::consts::FRAC_1_SQRT_2).abs() < 1e-12);
+
+        let (kitti, _) = written(Format::Kitti, false);
+        let values = numbers(&kitti[1], ' ');
+        assert_eq!(values.len(), 12);
+        // the x axis is rotated to the y axis
+        assert!((values[4] - 1.0).abs() < 1e-12);
+        assert_eq!([values[3], values[7], values[11]], [1.0, -2.0, 0.5]);
+
+        let (euroc, _) = written(Format::Euroc, true);
+        assert!(euroc[0].starts_with("#timestamp [ns],p_RS_R_x [m]"));
+        assert_eq!(euroc[0].split(',').count(), 14);
+        assert_eq!(euroc[1], "250000000,0,0,0,1,0,0,0,nan,nan,nan,nan,nan,nan");
+        let values: Vec<_> = euroc[2].split(',').collect();
+        assert_eq!(values[0], "1403636579750000000");
+        assert_eq!(
+            &values[8..],
+            &["0.0001", "0.0001", "0.0002", "0.01", "0.01", "0.02"]
+        );
+
+        // the TUM and KITTI rows keep their sizes, the variances are in a separate file
+        let (tum, variances) = written(Format::Tum, true);
+        assert_eq!(tum[1].split(' ').count(), 8);
+        assert_eq!(
+            variances,
+            [
+                "# timestamp var_r_x var_r_y var_r_z var_p_x var_p_y var_p_z",
+                "0.250000000 nan nan nan nan nan nan",
+                "1403636579.750000000 0.0001 0.0001 0.0002 0.01 0.01 0.02",
+            ]
+        );
+        let (kitti, variances) = written(Format::Kitti, true);
+        assert_eq!(kitti[1].split(' ').count(), 12);
+        assert_eq!(variances.len(), 3);
+
+        assert!(TrajectoryWriter::new(Vec::new(), Format::Kitti, Variances::Columns).is_err());
+    }
+
+    #[test]
+    fn variances_file() {
+        let directory = std::env::temp_dir().join(format!("trajectory-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("trajectory.txt");
+        trajectory().write_path(&path, Format::Tum, true).unwrap();
+        let variances = std::fs::read_to_string(directory.join("trajectory.txt.var")).unwrap();
+        assert_eq!(variances.lines().count(), 3);
+        assert_eq!(Trajectory::from_path(&path, Format::Tum).unwrap().len(), 2);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn read_written() {
+        [Format::Tum, Format::Kitti, Format::Euroc]
+            .into_iter()
+            .for_each(|format| {
+                let (lines, _) = written(format, true);
+                let read = Trajectory::read(lines.join("\n").as_bytes(), format).unwrap();
+                assert_eq!(read.len(), 2);
+                let pose = &read.poses[1];
+                assert_eq!(pose.pose.translation.vector, Vector3::new(1.0, -2.0, 0.5));
+                assert!((pose.pose.rotation.angle() - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+                let timestamp = match format {
+                    Format::Kitti => 1.0,
+                    _ => 1403636579.75,
+                };
+                assert_eq!(pose.timestamp, timestamp);
+            });
+        assert!(matches!(
+            Trajectory::read("# comment\n0 1 2 3\n".as_bytes(), Format::Tum),
+            Err(DatasetError::Format { .. })
+        ));
+    }
+}
@@ -140,3 +140,34 @@ impl<T: Scalar> Uncertainty for UncertaintyZero<T> {
 pub type Uncertainty1<T> = SMatrix<T, 1, 1>;
 pub type Uncertainty2<T> = SMatrix<T, 2, 2>;
 pub type Uncertainty3<T> = SMatrix<T, 3, 3>;
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Matrix6, Vector3, Vector6};
+    use rust_livo2_macros::uncertainties;
+
+    use super::*;
+
+    #[uncertainties]
+    struct PointUncertainty {
+        pub distance: Uncertainty1<f64>,
+        direction: Uncertainty2<f64>,
+        pub normal: Uncertainty3<f64>,
+    }
+
+    #[test]
+    fn uncertainties_layout() {
+        assert_eq!(PointUncertainty::DISTANCE_INDEX, 0);
+        assert_eq!(PointUncertainty::NORMAL_INDEX, 3);
+
+        let uncertainty = PointUncertainty::from(Matrix6::from_diagonal(&Vector6::new(
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0,
+        )));
+        assert_eq!(uncertainty.view_distance()[(0, 0)], 1.0);
+        assert_eq!(
+            uncertainty.view_normal().diagonal(),
+            Vector3::new(4.0, 5.0, 6.0)
+        );
+        assert_eq!(uncertainty.trace(), 21.0);
+    }
+}
@@ -33,3 +33,15 @@ impl<'a> Sum<&'a Vector3<f64>> for VectorSquareSum {
         })
     }
 }
+
+/// The right jacobian of SO3, see [`https://arxiv.org/pdf/1812.01537`] eq.(143)
+pub fn so3_right_jacobian(rotation_vector: &Vector3<f64>) -> Matrix3<f64> {
+    let angle = rotation_vector.norm();
+    let cross_matrix = rotation_vector.cross_matrix();
+    if angle < 1e-8 {
+        return Matrix3::identity() - cross_matrix / 2.0;
+    }
+    let angle_squared = angle * angle;
+    Matrix3::identity() - (1.0 - angle.cos()) / angle_squared * cross_matrix
+        + (angle - angle.sin()) / (angle_squared * angle) * cross_matrix * cross_matrix
+}
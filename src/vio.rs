@@ -1,4 +1,855 @@
+//! Direct visual-inertial odometry, more infomation see [`https://arxiv.org/pdf/2408.14035`]
+mod patch;
+pub mod point;
+
+use std::sync::Arc;
+
+use kornia::image::{Image, allocator::CpuAllocator};
+use nalgebra::{IsometryMatrix3, Matrix3, OMatrix, Rotation3, U3, Vector2, Vector3};
+use nohash_hasher::IntMap;
+use serde::Deserialize;
+
+use crate::{
+    camera::{self, CameraModel},
+    config::{self, ConfigError, ensure},
+    esikf::{
+        self, Measurement, MeasurementJacobian, OdometerUncertainties, StateDim, UncertainOdometer,
+    },
+    frame::{Camera, CameraPoint, FramedIsometry, Imu, World, WorldPoint},
+    voxel_map::VoxelIndex,
+};
+use point::{Observation, VisualPoint};
+
+/// A 8-bit grayscale image.
+pub type GrayImage = Image<u8, 1, CpuAllocator>;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(deserialize_with = "camera::deserialize_model")]
+    pub camera: Box<dyn CameraModel>,
+    /// the camera-imu extrinsic, or its initial guess if [`Config::extrinsic_estimation`] is enabled
+    #[serde(
+        default = "IsometryMatrix3::identity",
+        deserialize_with = "config::deserialize_isometry"
+    )]
+    pub camera_to_imu: IsometryMatrix3<f64>,
+    /// the time offset of the image timestamps, or its initial guess, in s
+    #[serde(default = "defaults::time_offset")]
+    pub time_offset: f64,
+    /// half of the side length of the square patch, in pixels
+    #[serde(default = "defaults::patch_half_size")]
+    pub patch_half_size: usize,
+    /// side length of the grid cells, in pixels, at most one visual point is selected in a cell
+    #[serde(default = "defaults::grid_size")]
+    pub grid_size: usize,
+    /// variance of the photometric error of a pixel
+    #[serde(default = "defaults::photometric_noise")]
+    pub photometric_noise: f64,
+    /// a visual point is not tracked if its mean squared photometric error exceeds this
+    #[serde(default = "defaults::outlier_threshold")]
+    pub outlier_threshold: f64,
+    /// the minimum gradient score of the patch to create a visual point
+    #[serde(default = "defaults::min_gradient_score")]
+    pub min_gradient_score: f64,
+    /// side length of the voxels of the visual map
+    #[serde(default = "defaults::voxel_size")]
+    pub voxel_size: f64,
+    /// a new observation is added if the camera moved further than this, in m
+    #[serde(default = "defaults::observation_min_distance")]
+    pub observation_min_distance: f64,
+    /// or rotated more than this, in rad
+    #[serde(default = "defaults::observation_min_angle")]
+    pub observation_min_angle: f64,
+    /// the maximum observations kept in a visual point
+    #[serde(default = "defaults::max_observations")]
+    pub max_observations: usize,
+    /// estimate the inverse exposure time of each image, disable it for cameras with fixed exposure
+    #[serde(default = "defaults::exposure_estimation")]
+    pub exposure_estimation: bool,
+    /// variance of the inverse exposure time prior, which is reset for each image
+    #[serde(default = "defaults::exposure_prior_variance")]
+    pub exposure_prior_variance: f64,
+    /// refine the camera-imu extrinsic online in the error state
+    #[serde(default = "defaults::extrinsic_estimation")]
+    pub extrinsic_estimation: bool,
+    /// prior variance of the extrinsic rotation, in rad^2
+    #[serde(default = "defaults::extrinsic_rotation_variance")]
+    pub extrinsic_rotation_variance: f64,
+    /// prior variance of the extrinsic translation, in m^2
+    #[serde(default = "defaults::extrinsic_translation_variance")]
+    pub extrinsic_translation_variance: f64,
+    /// refine the time offset online in the error state
+    #[serde(default = "defaults::time_offset_estimation")]
+    pub time_offset_estimation: bool,
+    /// prior variance of the time offset, in s^2
+    #[serde(default = "defaults::time_offset_variance")]
+    pub time_offset_variance: f64,
+}
+
+impl Config {
+    /// The configuration with the parameters of FAST-LIVO2.
+    pub fn new(camera: Box<dyn CameraModel>) -> Self {
+        Self {
+            camera,
+            camera_to_imu: IsometryMatrix3::identity(),
+            time_offset: defaults::time_offset(),
+            patch_half_size: defaults::patch_half_size(),
+            grid_size: defaults::grid_size(),
+            photometric_noise: defaults::photometric_noise(),
+            outlier_threshold: defaults::outlier_threshold(),
+            min_gradient_score: defaults::min_gradient_score(),
+            voxel_size: defaults::voxel_size(),
+            observation_min_distance: defaults::observation_min_distance(),
+            observation_min_angle: defaults::observation_min_angle(),
+            max_observations: defaults::max_observations(),
+            exposure_estimation: defaults::exposure_estimation(),
+            exposure_prior_variance: defaults::exposure_prior_variance(),
+            extrinsic_estimation: defaults::extrinsic_estimation(),
+            extrinsic_rotation_variance: defaults::extrinsic_rotation_variance(),
+            extrinsic_translation_variance: defaults::extrinsic_translation_variance(),
+            time_offset_estimation: defaults::time_offset_estimation(),
+            time_offset_variance: defaults::time_offset_variance(),
+        }
+    }
+
+    pub(crate) fn validate(&self, section: &str) -> Result<(), ConfigError> {
+        let positive = "should be positive";
+        let non_negative = "should not be negative";
+        [
+            ("patch_half_size", self.patch_half_size),
+            ("grid_size", self.grid_size),
+            ("max_observations", self.max_observations),
+        ]
+        .into_iter()
+        .try_for_each(|(field, value)| ensure(value > 0, section, field, positive))?;
+        [
+            ("photometric_noise", self.photometric_noise),
+            ("outlier_threshold", self.outlier_threshold),
+            ("voxel_size", self.voxel_size),
+        ]
+        .into_iter()
+        .try_for_each(|(field, value)| ensure(value > 0.0, section, field, positive))?;
+        [
+            ("min_gradient_score", self.min_gradient_score),
+            ("observation_min_distance", self.observation_min_distance),
+            ("observation_min_angle", self.observation_min_angle),
+            ("exposure_prior_variance", self.exposure_prior_variance),
+            (
+                "extrinsic_rotation_variance",
+                self.extrinsic_rotation_variance,
+            ),
+            (
+                "extrinsic_translation_variance",
+                self.extrinsic_translation_variance,
+            ),
+            ("time_offset_variance", self.time_offset_variance),
+        ]
+        .into_iter()
+        .try_for_each(|(field, value)| ensure(value >= 0.0, section, field, non_negative))?;
+        ensure(
+            self.time_offset.is_finite(),
+            section,
+            "time_offset",
+            "should be finite",
+        )
+    }
+}
+
+/// The default parameters of FAST-LIVO2.
+mod defaults {
+    pub fn time_offset() -> f64 {
+        0.0
+    }
+
+    pub fn patch_half_size() -> usize {
+        4
+    }
+
+    pub fn grid_size() -> usize {
+        5
+    }
+
+    pub fn photometric_noise() -> f64 {
+        100.0
+    }
+
+    pub fn outlier_threshold() -> f64 {
+        1000.0
+    }
+
+    pub fn min_gradient_score() -> f64 {
+        10.0
+    }
+
+    pub fn voxel_size() -> f64 {
+        0.5
+    }
+
+    pub fn observation_min_distance() -> f64 {
+        0.5
+    }
+
+    pub fn observation_min_angle() -> f64 {
+        10.0_f64.to_radians()
+    }
+
+    pub fn max_observations() -> usize {
+        30
+    }
+
+    pub fn exposure_estimation() -> bool {
+        true
+    }
+
+    pub fn exposure_prior_variance() -> f64 {
+        0.1
+    }
+
+    pub fn extrinsic_estimation() -> bool {
+        false
+    }
+
+    pub fn extrinsic_rotation_variance() -> f64 {
+        1e-4
+    }
+
+    pub fn extrinsic_translation_variance() -> f64 {
+        1e-4
+    }
+
+    pub fn time_offset_estimation() -> bool {
+        false
+    }
+
+    pub fn time_offset_variance() -> f64 {
+        1e-4
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct State {
-    /// estimated no scale inverse exposure time 
+    /// estimated no scale inverse exposure time
+    pub inverse_exposure_time: f64,
+    /// estimated extrinsic, from camera frame to imu frame
+    pub camera_to_imu: FramedIsometry<f64, Camera, Imu>,
+    /// estimated time offset, an image is captured at its timestamp plus this offset
+    pub time_offset: f64,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            inverse_exposure_time: 1.0,
+            camera_to_imu: IsometryMatrix3::identity().into(),
+            time_offset: 0.0,
+        }
+    }
+}
+
+/// A visual point tracked in the current image.
+struct TrackedPoint {
+    voxel: VoxelIndex,
+    index: usize,
+    position: WorldPoint<f64>,
+    /// the reference patch warped into the current image
+    patch: Vec<f64>,
+    /// the inverse exposure time of the reference image
     inverse_exposure_time: f64,
 }
+
+/// The motion of the imu between the propagated time and the estimated capture time of the image.
+struct Capture<'a> {
+    angular_velocity: &'a Vector3<f64>,
+    /// the time offset when the odometer was propagated
+    propagated_time_offset: f64,
+}
+
+impl Capture<'_> {
+    /// The imu pose at the capture time, extrapolated with the angular velocity and the velocity.
+    fn imu_to_world(&self, odometer: &UncertainOdometer) -> FramedIsometry<f64, Imu, World> {
+        let time_shift = odometer.vio.time_offset - self.propagated_time_offset;
+        let mut imu_to_world = odometer.isometry;
+        imu_to_world.rotation *= Rotation3::from_scaled_axis(self.angular_velocity * time_shift);
+        imu_to_world.translation.vector += odometer.imu.velocity * time_shift;
+        imu_to_world
+    }
+
+    fn camera_to_world(&self, odometer: &UncertainOdometer) -> FramedIsometry<f64, Camera, World> {
+        odometer.vio.camera_to_imu * self.imu_to_world(odometer)
+    }
+}
+
+/// The visual map and the photometric update.
+pub struct Vio {
+    config: Config,
+    map: IntMap<VoxelIndex, Vec<VisualPoint>>,
+}
+
+impl Vio {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            map: IntMap::default(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// All the visual points in the map.
+    pub fn points(&self) -> impl Iterator<Item = &VisualPoint> {
+        self.map.values().flatten()
+    }
+
+    /// Reset the camera-imu extrinsic and the time offset of the odometer to the configured values,
+    /// with the prior variances, or zero variances if the estimation is disabled, which locks them.
+    /// The inverse exposure time is given its prior variance likewise.
+    ///
+    /// Should be called after the odometer is initialized, e.g. by [`crate::imu::gravity_alignment`].
+    pub fn initialize(&self, odometer: &mut UncertainOdometer) {
+        type State = OdometerUncertainties;
+        let config = &self.config;
+
+        odometer.vio.camera_to_imu = config.camera_to_imu.into();
+        odometer.vio.time_offset = config.time_offset;
+
+        let enabled = |estimation: bool, variance: f64| if estimation { variance } else { 0.0 };
+        [
+            (
+                State::INVERSE_EXPOSURE_TIME_INDEX,
+                1,
+                enabled(config.exposure_estimation, config.exposure_prior_variance),
+            ),
+            (
+                State::CAMERA_TO_IMU_ROTATION_INDEX,
+                3,
+                enabled(
+                    config.extrinsic_estimation,
+                    config.extrinsic_rotation_variance,
+                ),
+            ),
+            (
+                State::CAMERA_TO_IMU_TRANSLATION_INDEX,
+                3,
+                enabled(
+                    config.extrinsic_estimation,
+                    config.extrinsic_translation_variance,
+                ),
+            ),
+            (
+                State::TIME_OFFSET_INDEX,
+                1,
+                enabled(config.time_offset_estimation, config.time_offset_variance),
+            ),
+        ]
+        .into_iter()
+        .for_each(|(index, dim, variance)| {
+            odometer.covariance.rows_mut(index, dim).fill(0.0);
+            odometer.covariance.columns_mut(index, dim).fill(0.0);
+            odometer
+                .covariance
+                .view_mut((index, index), (dim, dim))
+                .fill_diagonal(variance);
+        });
+    }
+
+    /// Update the odometer with the photometric errors of the image,
+    /// then update the visual map with the image and the new lidar points.
+    ///
+    /// The odometer should be propagated to the capture time of the image,
+    /// which is its timestamp plus the estimated [`State::time_offset`].
+    /// `angular_velocity` is the bias compensated angular velocity in imu frame at that time,
+    /// see [`crate::imu::ImuPose`].
+    ///
+    /// `lidar_points` are the lidar points of the current scan in world frame,
+    /// paired with the normals of the planes where they lie.
+    pub fn process(
+        &mut self,
+        image: GrayImage,
+        odometer: &mut UncertainOdometer,
+        angular_velocity: &Vector3<f64>,
+        esikf_config: &esikf::Config,
+        lidar_points: impl IntoIterator<Item = (WorldPoint<f64>, Vector3<f64>)>,
+    ) {
+        let image = Arc::new(image);
+        self.reset_exposure_prior(odometer);
+        let capture = Capture {
+            angular_velocity,
+            propagated_time_offset: odometer.vio.time_offset,
+        };
+        let tracked_points = self.track(&image, &capture.camera_to_world(odometer), odometer);
+
+        if !tracked_points.is_empty() {
+            odometer.iterated_update(esikf_config, |odometer| {
+                tracked_points
+                    .iter()
+                    .flat_map(|tracked_point| {
+                        self.measure(&image, odometer, &capture, tracked_point)
+                    })
+                    .collect::<Vec<_>>()
+            });
+        }
+
+        let camera_to_world = capture.camera_to_world(odometer);
+        let inverse_exposure_time = odometer.vio.inverse_exposure_time;
+        let occupied_cells = self.add_observations(
+            &image,
+            &tracked_points,
+            &camera_to_world,
+            inverse_exposure_time,
+        );
+        self.generate_points(
+            &image,
+            lidar_points,
+            &camera_to_world,
+            inverse_exposure_time,
+            &occupied_cells,
+        );
+    }
+
+    /// The exposure time may change arbitrarily between images with auto exposure,
+    /// so the inverse exposure time is decorrelated from the other states with a fresh prior,
+    /// or locked with zero variance if the exposure estimation is disabled.
+    fn reset_exposure_prior(&self, odometer: &mut UncertainOdometer) {
+        let index = OdometerUncertainties::INVERSE_EXPOSURE_TIME_INDEX;
+        let variance = if self.config.exposure_estimation {
+            self.config.exposure_prior_variance
+        } else {
+            0.0
+        };
+        odometer.covariance.row_mut(index).fill(0.0);
+        odometer.covariance.column_mut(index).fill(0.0);
+        odometer.covariance[(index, index)] = variance;
+    }
+
+    fn grid_cell(&self, pixel: &Vector2<f64>) -> usize {
+        let grid_size = self.config.grid_size as f64;
+        let columns = self.config.camera.width().div_ceil(self.config.grid_size);
+        (pixel.y / grid_size) as usize * columns + (pixel.x / grid_size) as usize
+    }
+
+    /// Select the nearest visual point in each grid cell,
+    /// and warp their reference patches into the current image.
+    fn track(
+        &self,
+        image: &GrayImage,
+        camera_to_world: &FramedIsometry<f64, Camera, World>,
+        odometer: &UncertainOdometer,
+    ) -> Vec<TrackedPoint> {
+        let config = &self.config;
+        let camera_center = camera_to_world.translation.vector;
+        let border = (config.patch_half_size + 1) as f64;
+
+        let mut cells = IntMap::<usize, (f64, &VoxelIndex, usize, Vector2<f64>)>::default();
+        self.map.iter().for_each(|(voxel, points)| {
+            points.iter().enumerate().for_each(|(index, point)| {
+                let camera_point: CameraPoint<f64> =
+                    camera_to_world.inverse_transform_point(&point.position);
+                let Some(pixel) = config
+                    .camera
+                    .project_to_image(&camera_point)
+                    .filter(|pixel| config.camera.contains(pixel, border))
+                else {
+                    return;
+                };
+                let distance = camera_point.coords.norm();
+                cells
+                    .entry(self.grid_cell(&pixel))
+                    .and_modify(|nearest| {
+                        if distance < nearest.0 {
+                            *nearest = (distance, voxel, index, pixel);
+                        }
+                    })
+                    .or_insert((distance, voxel, index, pixel));
+            });
+        });
+
+        cells
+            .into_values()
+            .filter_map(|(_, voxel, index, pixel)| {
+                let point = &self.map[voxel][index];
+                let reference = point.best_observation(&camera_center)?;
+                let warp = patch::affine_warp(
+                    config.camera.as_ref(),
+                    reference,
+                    &point.position,
+                    &point.normal,
+                    camera_to_world,
+                    config.patch_half_size,
+                )?;
+                let reference_patch = patch::warp_patch(reference, &warp, config.patch_half_size)?;
+
+                let inverse_exposure_time = odometer.vio.inverse_exposure_time;
+                let squared_error = patch::offsets(config.patch_half_size)
+                    .zip(&reference_patch)
+                    .map(|(offset, reference_value)| {
+                        let value = patch::interpolate(image, &(pixel + offset))?;
+                        Some(
+                            (inverse_exposure_time * value
+                                - reference.inverse_exposure_time * reference_value)
+                                .powi(2),
+                        )
+                    })
+                    .sum::<Option<f64>>()?;
+                if squared_error / reference_patch.len() as f64 > config.outlier_threshold {
+                    return None;
+                }
+
+                Some(TrackedPoint {
+                    voxel: voxel.clone(),
+                    index,
+                    position: point.position.clone(),
+                    patch: reference_patch,
+                    inverse_exposure_time: reference.inverse_exposure_time,
+                })
+            })
+            .collect()
+    }
+
+    /// The photometric measurements of the pixels in the patch of the tracked point.
+    fn measure(
+        &self,
+        image: &GrayImage,
+        odometer: &UncertainOdometer,
+        capture: &Capture,
+        tracked_point: &TrackedPoint,
+    ) -> Vec<Measurement> {
+        type State = OdometerUncertainties;
+        let config = &self.config;
+
+        let imu_to_world = capture.imu_to_world(odometer);
+        let imu_rotation_inverse = imu_to_world.rotation.inverse();
+        let camera_to_imu = &odometer.vio.camera_to_imu;
+        let imu_point = imu_to_world.inverse_transform_point(&tracked_point.position);
+        let camera_point = camera_to_imu.inverse_transform_point(&imu_point);
+        let Some(pixel) = config.camera.project_to_image(&camera_point) else {
+            return Vec::new();
+        };
+
+        // the jacobian of the camera point w.r.t. the error state
+        let camera_rotation_inverse = camera_to_imu.rotation.inverse();
+        let mut point_jacobian = OMatrix::<f64, U3, StateDim>::zeros();
+        let mut set_block = |index, block: Matrix3<f64>| {
+            point_jacobian
+                .fixed_columns_mut::<3>(index)
+                .copy_from(&block);
+        };
+        set_block(
+            State::ROTATION_INDEX,
+            camera_rotation_inverse * imu_point.coords.cross_matrix(),
+        );
+        set_block(
+            State::TRANSLATION_INDEX,
+            -(camera_rotation_inverse * imu_rotation_inverse).into_inner(),
+        );
+        set_block(
+            State::CAMERA_TO_IMU_ROTATION_INDEX,
+            camera_point.coords.cross_matrix(),
+        );
+        set_block(
+            State::CAMERA_TO_IMU_TRANSLATION_INDEX,
+            -camera_rotation_inverse.into_inner(),
+        );
+        // the imu point moves with the odometer during the time offset
+        let imu_point_velocity = imu_point.coords.cross(capture.angular_velocity)
+            - imu_rotation_inverse * odometer.imu.velocity;
+        point_jacobian
+            .column_mut(State::TIME_OFFSET_INDEX)
+            .copy_from(&(camera_rotation_inverse * imu_point_velocity));
+
+        let pixel_jacobian = config.camera.project_jacobian(&camera_point) * point_jacobian;
+
+        let inverse_exposure_time = odometer.vio.inverse_exposure_time;
+        patch::offsets(config.patch_half_size)
+            .zip(&tracked_point.patch)
+            .filter_map(|(offset, reference_value)| {
+                let pixel = pixel + offset;
+                let value = patch::interpolate(image, &pixel)?;
+                let gradient = patch::gradient(image, &pixel)?;
+
+                let mut jacobian: MeasurementJacobian =
+                    inverse_exposure_time * gradient * pixel_jacobian;
+                if config.exposure_estimation {
+                    jacobian[State::INVERSE_EXPOSURE_TIME_INDEX] = value;
+                }
+
+                Some(Measurement {
+                    residual: tracked_point.inverse_exposure_time * reference_value
+                        - inverse_exposure_time * value,
+                    jacobian,
+                    variance: config.photometric_noise,
+                })
+            })
+            .collect()
+    }
+
+    /// Add the current image as a new observation to the tracked points,
+    /// returns the grid cells occupied by the tracked points.
+    fn add_observations(
+        &mut self,
+        image: &Arc<GrayImage>,
+        tracked_points: &[TrackedPoint],
+        camera_to_world: &FramedIsometry<f64, Camera, World>,
+        inverse_exposure_time: f64,
+    ) -> IntMap<usize, ()> {
+        let mut occupied_cells = IntMap::default();
+
+        tracked_points.iter().for_each(|tracked_point| {
+            let camera_point: CameraPoint<f64> =
+                camera_to_world.inverse_transform_point(&tracked_point.position);
+            let Some(pixel) = self.config.camera.project_to_image(&camera_point) else {
+                return;
+            };
+            occupied_cells.insert(self.grid_cell(&pixel), ());
+
+            let config = &self.config;
+            let Some(point) = self
+                .map
+                .get_mut(&tracked_point.voxel)
+                .and_then(|points| points.get_mut(tracked_point.index))
+            else {
+                return;
+            };
+            point.add_observation(
+                Observation {
+                    image: image.clone(),
+                    pixel,
+                    camera_to_world: *camera_to_world,
+                    inverse_exposure_time,
+                },
+                config.observation_min_distance,
+                config.observation_min_angle,
+                config.max_observations,
+            );
+        });
+        occupied_cells
+    }
+
+    /// Create visual points from the lidar points with the highest gradient score
+    /// in the grid cells without tracked points.
+    fn generate_points(
+        &mut self,
+        image: &Arc<GrayImage>,
+        lidar_points: impl IntoIterator<Item = (WorldPoint<f64>, Vector3<f64>)>,
+        camera_to_world: &FramedIsometry<f64, Camera, World>,
+        inverse_exposure_time: f64,
+        occupied_cells: &IntMap<usize, ()>,
+    ) {
+        let config = &self.config;
+        let border = (config.patch_half_size + 1) as f64;
+
+        let mut cells =
+            IntMap::<usize, (f64, WorldPoint<f64>, Vector3<f64>, Vector2<f64>)>::default();
+        lidar_points.into_iter().for_each(|(position, normal)| {
+            let camera_point: CameraPoint<f64> = camera_to_world.inverse_transform_point(&position);
+            let Some(pixel) = config
+                .camera
+                .project(&camera_point)
+                .filter(|pixel| config.camera.contains(pixel, border))
+            else {
+                return;
+            };
+            let cell = self.grid_cell(&pixel);
+            if occupied_cells.contains_key(&cell) {
+                return;
+            }
+            let Some(score) = patch::gradient_score(image, &pixel, config.patch_half_size)
+                .filter(|&score| score > config.min_gradient_score)
+            else {
+                return;
+            };
+            if cells.get(&cell).is_none_or(|best| score > best.0) {
+                cells.insert(cell, (score, position, normal, pixel));
+            }
+        });
+
+        cells
+            .into_values()
+            .for_each(|(_, position, normal, pixel)| {
+                let voxel = VoxelIndex::from_point(&position, self.config.voxel_size);
+                let observation = Observation {
+                    image: image.clone(),
+                    pixel,
+                    camera_to_world: *camera_to_world,
+                    inverse_exposure_time,
+                };
+                self.map.entry(voxel).or_default().push(VisualPoint::new(
+                    position,
+                    normal,
+                    observation,
+                ));
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kornia::image::ImageSize;
+
+    use super::*;
+    use crate::{camera::PinholeCamera, esikf::StateMatrix, esikf::StateVector, imu};
+
+    /// An image whose intensity is linear in the pixel, so that its interpolation is exact.
+    fn image() -> GrayImage {
+        let (width, height) = (100, 60);
+        let pixels = (0..height)
+            .flat_map(|row| (0..width).map(move |column| (column + 2 * row) as u8))
+            .collect();
+        GrayImage::new(ImageSize { width, height }, pixels, CpuAllocator).unwrap()
+    }
+
+    fn vio(exposure_estimation: bool) -> Vio {
+        let camera = PinholeCamera {
+            width: 100,
+            height: 60,
+            fx: 50.0,
+            fy: 50.0,
+            cx: 50.0,
+            cy: 30.0,
+            distortion: Default::default(),
+        };
+        Vio::new(Config {
+            camera_to_imu: IsometryMatrix3::new(
+                Vector3::new(0.05, -0.02, 0.1),
+                Vector3::new(0.02, -0.01, 0.03),
+            ),
+            patch_half_size: 2,
+            exposure_estimation,
+            ..Config::new(Box::new(camera))
+        })
+    }
+
+    fn odometer(vio: &Vio, variance: f64) -> UncertainOdometer {
+        let mut odometer = UncertainOdometer::new(
+            IsometryMatrix3::new(
+                Vector3::new(0.2, 0.1, -0.1),
+                Vector3::new(0.05, 0.02, -0.04),
+            )
+            .into(),
+            imu::State {
+                velocity: Vector3::new(0.3, -0.1, 0.2),
+                ..Default::default()
+            },
+            State {
+                inverse_exposure_time: 1.1,
+                ..Default::default()
+            },
+            IsometryMatrix3::identity().into(),
+            StateMatrix::from_diagonal_element(variance).into(),
+        );
+        vio.initialize(&mut odometer);
+        odometer
+    }
+
+    /// A point in front of the camera, its reference patch is `brightness` times the image.
+    fn tracked_point(vio: &Vio, odometer: &UncertainOdometer, brightness: f64) -> TrackedPoint {
+        let capture = Capture {
+            angular_velocity: &Vector3::zeros(),
+            propagated_time_offset: odometer.vio.time_offset,
+        };
+        let camera_to_world = capture.camera_to_world(odometer);
+        let position: WorldPoint<f64> =
+            camera_to_world.transform_point(&Vector3::new(0.1, -0.05, 2.0).into());
+        let pixel = vio
+            .config
+            .camera
+            .project(&camera_to_world.inverse_transform_point(&position))
+            .unwrap();
+        let patch = patch::offsets(vio.config.patch_half_size)
+            .map(|offset| brightness * patch::interpolate(&image(), &(pixel + offset)).unwrap())
+            .collect();
+        TrackedPoint {
+            voxel: VoxelIndex::from_point(&position, vio.config.voxel_size),
+            index: 0,
+            position,
+            patch,
+            inverse_exposure_time: 1.0,
+        }
+    }
+
+    #[test]
+    fn photometric_jacobian_matches_numeric() {
+        let vio = vio(true);
+        let image = image();
+        let mut odometer = odometer(&vio, 1e-2);
+        odometer.vio.time_offset = 0.01;
+        let angular_velocity = Vector3::new(0.1, 0.2, -0.1);
+        // the jacobian neglects the extrapolated rotation, exact at the propagated time offset
+        let capture = Capture {
+            angular_velocity: &angular_velocity,
+            propagated_time_offset: odometer.vio.time_offset,
+        };
+        let tracked_point = tracked_point(&vio, &odometer, 1.0);
+        let residuals = |odometer: &UncertainOdometer| {
+            vio.measure(&image, odometer, &capture, &tracked_point)
+                .iter()
+                .map(|measurement| measurement.residual)
+                .collect::<Vec<_>>()
+        };
+        let measurements = vio.measure(&image, &odometer, &capture, &tracked_point);
+        assert_eq!(measurements.len(), 16);
+
+        type Odometer = OdometerUncertainties;
+        let step = 1e-6;
+        [
+            Odometer::ROTATION_INDEX..Odometer::ROTATION_INDEX + 3,
+            Odometer::TRANSLATION_INDEX..Odometer::TRANSLATION_INDEX + 3,
+            Odometer::INVERSE_EXPOSURE_TIME_INDEX..Odometer::INVERSE_EXPOSURE_TIME_INDEX + 1,
+            Odometer::CAMERA_TO_IMU_ROTATION_INDEX..Odometer::CAMERA_TO_IMU_ROTATION_INDEX + 3,
+            Odometer::CAMERA_TO_IMU_TRANSLATION_INDEX
+                ..Odometer::CAMERA_TO_IMU_TRANSLATION_INDEX + 3,
+            Odometer::TIME_OFFSET_INDEX..Odometer::TIME_OFFSET_INDEX + 1,
+        ]
+        .into_iter()
+        .flatten()
+        .for_each(|index| {
+            let perturbed = |sign: f64| {
+                let mut odometer = odometer.clone();
+                odometer.boxplus(&StateVector::ith(index, sign * step));
+                residuals(&odometer)
+            };
+            let (forward, backward) = (perturbed(1.0), perturbed(-1.0));
+            measurements
+                .iter()
+                .enumerate()
+                .for_each(|(pixel, measurement)| {
+                    // the residual is the observed value minus the predicted one
+                    let numeric = -(forward[pixel] - backward[pixel]) / (2.0 * step);
+                    let analytic = measurement.jacobian[index];
+                    assert!(
+                        (analytic - numeric).abs() < 1e-4 * analytic.abs().max(1.0),
+                        "column {index}, pixel {pixel}: {analytic} != {numeric}"
+                    );
+                });
+        });
+    }
+
+    #[test]
+    fn exposure_lock() {
+        let image = image();
+        let capture_update = |vio: &Vio| {
+            // the pose is certain, the brighter reference is explained by the exposure only
+            let mut odometer = odometer(vio, 1e-12);
+            vio.reset_exposure_prior(&mut odometer);
+            let tracked_point = tracked_point(vio, &odometer, 1.2);
+            let capture = Capture {
+                angular_velocity: &Vector3::zeros(),
+                propagated_time_offset: odometer.vio.time_offset,
+            };
+            odometer.iterated_update(&esikf::Config::default(), |odometer| {
+                vio.measure(&image, odometer, &capture, &tracked_point)
+            });
+            odometer
+        };
+
+        let locked = capture_update(&vio(false));
+        assert_eq!(locked.vio.inverse_exposure_time, 1.1);
+        let index = OdometerUncertainties::INVERSE_EXPOSURE_TIME_INDEX;
+        assert_eq!(locked.covariance[(index, index)], 0.0);
+
+        let estimated = capture_update(&vio(true)).vio.inverse_exposure_time;
+        assert!((estimated - 1.2).abs() < 1e-3, "{estimated}");
+    }
+}
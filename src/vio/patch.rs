@@ -0,0 +1,99 @@
+use nalgebra::{IsometryMatrix3, Matrix2, RowVector2, Vector2, Vector3};
+
+use super::{GrayImage, point::Observation};
+use crate::{
+    camera::CameraModel,
+    frame::{Camera, Framed, World, WorldPoint},
+};
+
+/// Bilinear interpolation of the image intensity at `pixel`.
+pub fn interpolate(image: &GrayImage, pixel: &Vector2<f64>) -> Option<f64> {
+    let (x, y) = (pixel.x.floor(), pixel.y.floor());
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    let (column, row) = (x as usize, y as usize);
+    let width = image.width();
+    if column + 1 >= width || row + 1 >= image.height() {
+        return None;
+    }
+    let (dx, dy) = (pixel.x - x, pixel.y - y);
+    let data = image.as_slice();
+    let at = |row: usize, column: usize| data[row * width + column] as f64;
+
+    Some(
+        (1.0 - dy) * ((1.0 - dx) * at(row, column) + dx * at(row, column + 1))
+            + dy * ((1.0 - dx) * at(row + 1, column) + dx * at(row + 1, column + 1)),
+    )
+}
+
+/// The image gradient at `pixel` with central difference.
+pub fn gradient(image: &GrayImage, pixel: &Vector2<f64>) -> Option<RowVector2<f64>> {
+    let difference = |offset: Vector2<f64>| {
+        Some(
+            (interpolate(image, &(pixel + offset))? - interpolate(image, &(pixel - offset))?) / 2.0,
+        )
+    };
+    Some(RowVector2::new(
+        difference(Vector2::x())?,
+        difference(Vector2::y())?,
+    ))
+}
+
+/// The pixel offsets of a square patch, in row major order.
+pub fn offsets(half_size: usize) -> impl Iterator<Item = Vector2<f64>> + Clone {
+    let half_size = half_size as isize;
+    (-half_size..half_size)
+        .flat_map(move |y| (-half_size..half_size).map(move |x| Vector2::new(x as f64, y as f64)))
+}
+
+/// The sum of squared gradients in the patch, which measures how well the patch can be aligned.
+pub fn gradient_score(image: &GrayImage, pixel: &Vector2<f64>, half_size: usize) -> Option<f64> {
+    offsets(half_size)
+        .map(|offset| gradient(image, &(pixel + offset)).map(|gradient| gradient.norm_squared()))
+        .sum()
+}
+
+/// The affine warp from the pixel offsets in the current image to the pixel offsets in the reference image,
+/// approximated with the homography induced by the plane where the point lies.
+pub fn affine_warp(
+    camera: &dyn CameraModel,
+    reference: &Observation,
+    position: &WorldPoint<f64>,
+    normal: &Vector3<f64>,
+    camera_to_world: &Framed<IsometryMatrix3<f64>, fn(Camera) -> World>,
+    half_size: usize,
+) -> Option<Matrix2<f64>> {
+    let reference_to_current = reference.camera_to_world * camera_to_world.inverse();
+    let normal = reference.camera_to_world.rotation.inverse() * normal;
+    let plane_point = reference.camera_to_world.inverse_transform_point(position);
+    let plane_distance = normal.dot(&plane_point.coords);
+
+    let project_from_reference = |pixel: Vector2<f64>| {
+        let bearing = camera.unproject(&pixel)?;
+        let scale = plane_distance / normal.dot(&bearing.coords);
+        if !scale.is_finite() || scale <= 0.0 {
+            return None;
+        }
+        let point = reference_to_current.transform_point(&(bearing.coords * scale).into());
+        camera.project_to_image(&point)
+    };
+
+    let step = half_size.max(1) as f64;
+    let center = project_from_reference(reference.pixel)?;
+    let x_axis = (project_from_reference(reference.pixel + Vector2::x() * step)? - center) / step;
+    let y_axis = (project_from_reference(reference.pixel + Vector2::y() * step)? - center) / step;
+
+    Matrix2::from_columns(&[x_axis, y_axis]).try_inverse()
+}
+
+/// Warp the reference patch into the current image with the affine `warp`.
+pub fn warp_patch(
+    reference: &Observation,
+    warp: &Matrix2<f64>,
+    half_size: usize,
+) -> Option<Vec<f64>> {
+    offsets(half_size)
+        .map(|offset| interpolate(&reference.image, &(reference.pixel + warp * offset)))
+        .collect()
+}
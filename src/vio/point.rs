@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use nalgebra::{IsometryMatrix3, Vector2, Vector3};
+
+use crate::frame::{Camera, Framed, World, WorldPoint};
+
+use super::GrayImage;
+
+/// A point of the visual map, which is a lidar point lying on a plane and observed by the camera.
+pub struct VisualPoint {
+    pub position: WorldPoint<f64>,
+    /// the normal of the plane where the point lies
+    pub normal: Vector3<f64>,
+    observations: Vec<Observation>,
+}
+
+/// An observation of a visual point in a camera frame.
+#[derive(Clone)]
+pub struct Observation {
+    pub image: Arc<GrayImage>,
+    pub pixel: Vector2<f64>,
+    pub camera_to_world: Framed<IsometryMatrix3<f64>, fn(Camera) -> World>,
+    /// the estimated inverse exposure time of the image
+    pub inverse_exposure_time: f64,
+}
+
+impl Observation {
+    pub fn camera_center(&self) -> Vector3<f64> {
+        self.camera_to_world.translation.vector
+    }
+}
+
+impl VisualPoint {
+    pub fn new(position: WorldPoint<f64>, normal: Vector3<f64>, observation: Observation) -> Self {
+        Self {
+            position,
+            normal,
+            observations: vec![observation],
+        }
+    }
+
+    pub fn observations(&self) -> &[Observation] {
+        &self.observations
+    }
+
+    /// The observation with the most similar view direction to the camera at `camera_center`,
+    /// which is the best reference to warp the patch from.
+    pub fn best_observation(&self, camera_center: &Vector3<f64>) -> Option<&Observation> {
+        let view_direction = (camera_center - self.position.coords).normalize();
+        self.observations.iter().max_by(|left, right| {
+            let cos = |observation: &Observation| {
+                (observation.camera_center() - self.position.coords)
+                    .normalize()
+                    .dot(&view_direction)
+            };
+            cos(left).total_cmp(&cos(right))
+        })
+    }
+
+    /// Add the observation if the camera moved far enough from the latest observation,
+    /// the oldest observation is dropped when there are more than `max_observations`.
+    pub fn add_observation(
+        &mut self,
+        observation: Observation,
+        min_distance: f64,
+        min_angle: f64,
+        max_observations: usize,
+    ) {
+        if let Some(latest) = self.observations.last() {
+            let distance = (observation.camera_center() - latest.camera_center()).norm();
+            let angle = latest
+                .camera_to_world
+                .rotation
+                .angle_to(&observation.camera_to_world.rotation);
+            if distance < min_distance && angle < min_angle {
+                return;
+            }
+        }
+        self.observations.push(observation);
+        if self.observations.len() > max_observations {
+            self.observations.remove(0);
+        }
+    }
+}
@@ -1,40 +1,109 @@
 //! more infomation see [`https://arxiv.org/pdf/2109.07082`] and ['https://arxiv.org/pdf/2103.01627']
-mod plane;
-mod point;
-mod point_to_plane;
+pub mod plane;
+pub mod point;
+pub mod point_to_plane;
 
 use std::{
+    borrow::Cow,
     hash::Hash,
     ops::{Deref, DerefMut, Index, IndexMut},
 };
 
 use nohash_hasher::IntMap;
+use serde::Deserialize;
 
-use nalgebra::{DVector, dvector};
-
-use crate::{frame::{World, WorldPoint}, voxel_map::point::UncertainPoint};
+use crate::{
+    config::{ConfigError, ensure},
+    esikf::UncertainOdometer,
+    frame::{Body, FramedPoint, World, WorldPoint},
+    voxel_map::{point::UncertainPoint, point_to_plane::UncertainPoint2Plane},
+};
 use plane::UncertainPlane;
 
+/// The number of new points to trigger a plane refit.
+const PLANE_UPDATE_THRESHOLD: usize = 5;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    beam_err: f64,
-    dept_err: f64,
-    sigma_num: f64,
-    planer_threshold: f64,
-    max_points_num: usize,
-    max_layer: usize,
-    layer_init_threshold: &'static [usize],
-    voxel_size: f64,
+    /// standard deviation of the lidar range, in m
+    pub beam_err: f64,
+    /// standard deviation of the lidar bearing, in degrees
+    pub dept_err: f64,
+    /// a point is matched to a plane within this many standard deviations
+    pub sigma_num: f64,
+    /// the maximum smallest eigenvalue of the point covariance to be considered a plane, in m^2
+    pub planer_threshold: f64,
+    /// the plane of a voxel is no longer updated after this many points
+    pub max_points_num: usize,
+    /// the minimum points to create a plane of each layer, its length is the max layer
+    pub layer_init_threshold: Cow<'static, [usize]>,
+    /// side length of the root voxels, in m
+    pub voxel_size: f64,
 }
 
 impl Config {
-    pub const fn max_layer(&self) -> usize {
+    pub fn max_layer(&self) -> usize {
         self.layer_init_threshold.len()
     }
+
+    pub(crate) fn validate(&self, section: &str) -> Result<(), ConfigError> {
+        let positive = "should be positive";
+        let non_negative = "should not be negative";
+        ensure(self.beam_err >= 0.0, section, "beam_err", non_negative)?;
+        ensure(self.dept_err >= 0.0, section, "dept_err", non_negative)?;
+        ensure(self.sigma_num > 0.0, section, "sigma_num", positive)?;
+        ensure(
+            self.planer_threshold > 0.0,
+            section,
+            "planer_threshold",
+            positive,
+        )?;
+        ensure(self.max_points_num > 0, section, "max_points_num", positive)?;
+        ensure(
+            !self.layer_init_threshold.is_empty(),
+            section,
+            "layer_init_threshold",
+            "should have at least one layer",
+        )?;
+        ensure(self.voxel_size > 0.0, section, "voxel_size", positive)
+    }
+}
+
+impl Default for Config {
+    /// The voxel map parameters of FAST-LIVO2.
+    fn default() -> Self {
+        Self {
+            beam_err: 0.02,
+            dept_err: 0.05,
+            sigma_num: 3.0,
+            planer_threshold: 0.0025,
+            max_points_num: 50,
+            layer_init_threshold: Cow::Borrowed(&[5, 5, 5]),
+            voxel_size: 0.5,
+        }
+    }
+}
+
+/// The index of a voxel of side length `voxel_size` containing a point of frame `F`.
+#[derive(Debug)]
+pub struct VoxelIndex<F = World>(FramedPoint<i64, F>);
+
+impl<F> Clone for VoxelIndex<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F> PartialEq for VoxelIndex<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
 }
 
-pub struct VoxelIndex(WorldPoint<i64>);
+impl<F> Eq for VoxelIndex<F> {}
 
-impl Hash for VoxelIndex {
+impl<F> Hash for VoxelIndex<F> {
     fn hash<H>(&self, hasher: &mut H)
     where
         H: std::hash::Hasher,
@@ -51,35 +120,40 @@ impl Hash for VoxelIndex {
 }
 
 /// Hasher methods is invoked exactly once
-impl nohash_hasher::IsEnabled for VoxelIndex {}
+impl<F> nohash_hasher::IsEnabled for VoxelIndex<F> {}
 
-impl Deref for VoxelIndex {
-    type Target = WorldPoint<i64>;
+impl<F> Deref for VoxelIndex<F> {
+    type Target = FramedPoint<i64, F>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
-impl DerefMut for VoxelIndex {
+impl<F> DerefMut for VoxelIndex<F> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
 
-impl VoxelIndex {
-    pub fn from_point(point: WorldPoint<f64>, voxel_size: f64) -> Self {
-        let point: WorldPoint<_> = point
+impl<F> VoxelIndex<F> {
+    pub fn from_point(point: &FramedPoint<f64, F>, voxel_size: f64) -> Self {
+        let point: FramedPoint<_, F> = point
             .map(|x| x / voxel_size)
             .map(f64::floor)
             .map(|x| x as i64)
             .into();
         point.into()
     }
+
+    /// The center of the voxel in its frame.
+    pub fn center(&self, voxel_size: f64) -> FramedPoint<f64, F> {
+        self.map(|x| (x as f64 + 0.5) * voxel_size).into()
+    }
 }
 
-impl From<WorldPoint<i64>> for VoxelIndex {
-    fn from(value: WorldPoint<i64>) -> Self {
+impl<F> From<FramedPoint<i64, F>> for VoxelIndex<F> {
+    fn from(value: FramedPoint<i64, F>) -> Self {
         Self(value)
     }
 }
@@ -95,6 +169,12 @@ impl Leafs {
             .iter()
             .flat_map(|z| z.iter().flat_map(|y| y.iter().flat_map(|x| x.iter())))
     }
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<Octree>> {
+        self.0.iter_mut().flat_map(|z| {
+            z.iter_mut()
+                .flat_map(|y| y.iter_mut().flat_map(|x| x.iter_mut()))
+        })
+    }
 }
 
 impl Index<&WorldPoint<bool>> for Leafs {
@@ -115,67 +195,480 @@ impl IndexMut<&WorldPoint<bool>> for Leafs {
 
 pub struct Octree {
     leafs: Leafs,
-    points: Vec<WorldPoint<f64>>,
+    points: Vec<UncertainPoint<World>>,
     center: WorldPoint<f64>,
+    /// a quarter of the side length of this node
     tree_size: f64,
     plane: Option<UncertainPlane>,
+    layer: usize,
+    /// whether this node has enough points to create a plane
+    initialized: bool,
+    /// the number of points inserted since the last plane fitting
+    new_points_count: usize,
+    /// the plane is fixed after the points reaches [`Config::max_points_num`]
+    update_enabled: bool,
 }
 
 impl Octree {
-    pub fn new() -> Self {
-        todo!()
+    pub fn new(center: WorldPoint<f64>, tree_size: f64, layer: usize) -> Self {
+        Self {
+            leafs: Leafs::empty(),
+            points: Vec::new(),
+            center,
+            tree_size,
+            plane: None,
+            layer,
+            initialized: false,
+            new_points_count: 0,
+            update_enabled: true,
+        }
+    }
+
+    pub fn plane(&self) -> Option<&UncertainPlane> {
+        self.plane.as_ref()
+    }
+
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    pub fn center(&self) -> &WorldPoint<f64> {
+        &self.center
+    }
+
+    /// The existing sub nodes of this node.
+    pub fn leafs(&self) -> impl Iterator<Item = &Octree> {
+        self.leafs.iter().map(Box::as_ref)
+    }
+
+    pub fn insert(&mut self, point: UncertainPoint<World>, config: &Config) {
+        if !self.initialized {
+            self.points.push(point);
+            if self.points.len() >= config.layer_init_threshold[self.layer] {
+                self.initialize(config);
+            }
+            return;
+        }
+        if self.plane.is_none() && self.layer + 1 < config.max_layer() {
+            self.leaf_mut(&point).insert(point, config);
+            return;
+        }
+        if !self.update_enabled {
+            return;
+        }
+        self.points.push(point);
+        self.new_points_count += 1;
+
+        if self.new_points_count >= PLANE_UPDATE_THRESHOLD {
+            self.new_points_count = 0;
+            self.create_plane(
+                config.layer_init_threshold[self.layer],
+                config.planer_threshold,
+            );
+            // the new points break the plane
+            if self.plane.is_none() && self.layer + 1 < config.max_layer() {
+                self.cut(config);
+                return;
+            }
+        }
+        if self.points.len() >= config.max_points_num {
+            self.update_enabled = false;
+            self.points = Vec::new();
+        }
+    }
+
+    /// Try to create a plane with the collected points, cut this node if it is not a plane.
+    fn initialize(&mut self, config: &Config) {
+        self.initialized = true;
+        self.create_plane(
+            config.layer_init_threshold[self.layer],
+            config.planer_threshold,
+        );
+        if self.plane.is_none() {
+            self.cut(config);
+        }
     }
 
     pub fn create_plane(&mut self, plane_min_points: usize, planer_threshold: f64) {
         if self.points.len() < plane_min_points {
             return;
         }
-        todo!("create uncertain points");
-        todo!("create plane from points")
-        // self.points.iter().map(UncertainPoint::new_body_point)
-        // UncertainPlane::new(&self.points, planer_threshold);
+        self.plane = UncertainPlane::new(&self.points, planer_threshold);
     }
-    pub fn cut(&mut self, max_layer: usize) {
-        self.points.iter().cloned().for_each(|point| {
-            let leaf_index = (point.deref() - self.center.deref())
-                .map(|x| x.is_sign_positive())
-                .into();
 
-            let leaf = &mut self.leafs[&leaf_index];
+    /// Distribute the points into the sub nodes, and recursively try to create planes in them,
+    /// until the max layer is reached.
+    pub fn cut(&mut self, config: &Config) {
+        if self.layer + 1 >= config.max_layer() {
+            return;
+        }
+        std::mem::take(&mut self.points)
+            .into_iter()
+            .for_each(|point| self.leaf_mut(&point).points.push(point));
 
-            if let Some(leaf) = leaf {
-                leaf.points.push(point);
-            } else {
-                *leaf = Some(Box::new(Octree {
-                    points: vec![point],
-                    center: leaf_index.framed_map(|point| {
-                        point.map(|x| if x { 1.0 } else { -1.0 }) * self.tree_size
-                            + self.center.coords
-                    }),
-                    tree_size: self.tree_size / 2.0,
-                    leafs: Leafs::empty(),
-                    plane: None,
-                }));
+        self.leafs.iter_mut().for_each(|leaf| {
+            if leaf.points.len() >= config.layer_init_threshold[leaf.layer] {
+                leaf.initialize(config);
             }
         });
-        todo!("try to create plane")
+    }
+
+    /// Find the most probable plane of the point in this node and its sub nodes.
+    fn match_plane(
+        &self,
+        point: &UncertainPoint<World>,
+        sigma_num: f64,
+    ) -> Option<(f64, &UncertainPlane)> {
+        if let Some(plane) = &self.plane {
+            return plane
+                .probability_of(point, sigma_num)
+                .map(|probability| (probability, plane));
+        }
+        self.leafs()
+            .filter_map(|leaf| leaf.match_plane(point, sigma_num))
+            .max_by(|(left, _), (right, _)| left.total_cmp(right))
+    }
+
+    /// Get the sub node containing the point, create it if not exists.
+    fn leaf_mut(&mut self, point: &UncertainPoint<World>) -> &mut Octree {
+        let leaf_index = (point.point() - self.center.deref())
+            .map(|x| x.is_sign_positive())
+            .into();
+
+        self.leafs[&leaf_index].get_or_insert_with(|| {
+            let center = leaf_index.framed_map(|point| {
+                point.map(|x| if x { 1.0 } else { -1.0 }) * self.tree_size + self.center.coords
+            });
+            Box::new(Octree::new(center, self.tree_size / 2.0, self.layer + 1))
+        })
     }
 }
 
 pub struct VoxelMap {
+    config: Config,
     trees: IntMap<VoxelIndex, Octree>,
 }
 
-impl Extend<WorldPoint<f64>> for VoxelMap {
+impl Extend<UncertainPoint<World>> for VoxelMap {
     fn extend<T>(&mut self, iter: T)
     where
-        T: IntoIterator<Item = WorldPoint<f64>>,
+        T: IntoIterator<Item = UncertainPoint<World>>,
     {
-        todo!()
+        let voxel_size = self.config.voxel_size;
+        iter.into_iter().for_each(|point| {
+            let index = VoxelIndex::from_point(&point, voxel_size);
+            self.trees
+                .entry(index)
+                .or_insert_with_key(|index| {
+                    Octree::new(index.center(voxel_size), voxel_size / 4.0, 0)
+                })
+                .insert(point, &self.config);
+        });
     }
 }
+
 impl VoxelMap {
-    pub fn build_residual(&self, config: &Config) {
-        todo!()
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            trees: IntMap::default(),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn get(&self, index: &VoxelIndex) -> Option<&Octree> {
+        self.trees.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// Match every point to the most probable plane in its voxel or the neighbour voxels,
+    /// the unmatched points are skipped.
+    ///
+    /// The points are transformed with the estimated extrinsic of the odometer.
+    pub fn build_residual(
+        &self,
+        body_points: &[UncertainPoint<Body>],
+        odometer: &UncertainOdometer,
+    ) -> Vec<UncertainPoint2Plane> {
+        let body_to_imu = &odometer.body_to_imu;
+        body_points
+            .iter()
+            .filter_map(|body_point| {
+                let world_point =
+                    UncertainPoint::from_body_point(body_point.clone(), odometer, body_to_imu);
+                let plane = self.match_plane(&world_point)?;
+
+                let world_point = UncertainPoint::from_body_point_without_pose_error(
+                    body_point.clone(),
+                    odometer,
+                    body_to_imu,
+                );
+                Some(UncertainPoint2Plane::new(
+                    (**body_point).clone(),
+                    body_to_imu,
+                    &world_point,
+                    plane,
+                ))
+            })
+            .collect()
+    }
+
+    fn match_plane(&self, point: &UncertainPoint<World>) -> Option<&UncertainPlane> {
+        let voxel_size = self.config.voxel_size;
+        let sigma_num = self.config.sigma_num;
+        let index = VoxelIndex::from_point(point, voxel_size);
+
+        let match_in = |index: &VoxelIndex| {
+            self.trees
+                .get(index)
+                .and_then(|tree| tree.match_plane(point, sigma_num))
+        };
+        if let Some((_, plane)) = match_in(&index) {
+            return Some(plane);
+        }
+
+        // the point may belong to a plane in the neighbour voxel if it is close to the boundary
+        let relative_position = (point.coords - index.center(voxel_size).coords) / voxel_size;
+        (0..3)
+            .filter(|&axis| relative_position[axis].abs() > 0.25)
+            .filter_map(|axis| {
+                let mut neighbour = index.clone();
+                neighbour[axis] += relative_position[axis].signum() as i64;
+                match_in(&neighbour)
+            })
+            .max_by(|(left, _), (right, _)| left.total_cmp(right))
+            .map(|(_, plane)| plane)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Matrix3, Vector3};
+
+    use super::*;
+
+    const CONFIG: Config = Config {
+        beam_err: 0.02,
+        dept_err: 0.05,
+        sigma_num: 3.0,
+        planer_threshold: 0.0025,
+        max_points_num: 1000,
+        layer_init_threshold: Cow::Borrowed(&[20, 10, 10]),
+        voxel_size: 1.0,
+    };
+
+    fn uncertain_point(x: f64, y: f64, z: f64) -> UncertainPoint<World> {
+        UncertainPoint::new_uncertained(
+            WorldPoint::from(Vector3::new(x, y, z)),
+            Matrix3::from_diagonal_element(1e-4),
+        )
+    }
+
+    /// Sample points on a grid of `step` over the `[start, end)` ranges.
+    fn grid(start: [f64; 2], end: [f64; 2], step: f64) -> impl Iterator<Item = (f64, f64)> + Clone {
+        let steps = move |axis: usize| ((end[axis] - start[axis]) / step).ceil() as usize;
+        (0..steps(0)).flat_map(move |i| {
+            (0..steps(1)).map(move |j| (start[0] + i as f64 * step, start[1] + j as f64 * step))
+        })
+    }
+
+    fn voxel_map(points: impl IntoIterator<Item = UncertainPoint<World>>) -> VoxelMap {
+        let mut map = VoxelMap::new(CONFIG);
+        map.extend(points);
+        map
+    }
+
+    fn octree(map: &VoxelMap, x: i64, y: i64, z: i64) -> &Octree {
+        map.get(&WorldPoint::from(Vector3::new(x, y, z)).into())
+            .expect("voxel should exist")
+    }
+
+    fn assert_normal(octree: &Octree, normal: Vector3<f64>) {
+        let plane = octree.plane().expect("should be a plane");
+        assert!(
+            plane.normal().dot(&normal).abs() > 0.99,
+            "normal {} is not {normal}",
+            plane.normal()
+        );
+    }
+
+    #[test]
+    fn single_plane_voxel() {
+        let map =
+            voxel_map(grid([0.0, 0.0], [1.0, 1.0], 0.05).map(|(x, y)| uncertain_point(x, y, 0.3)));
+        assert_eq!(map.len(), 1);
+
+        let root = octree(&map, 0, 0, 0);
+        assert_normal(root, Vector3::z());
+        assert_eq!(root.leafs().count(), 0);
+    }
+
+    #[test]
+    fn step_voxel_is_cut_into_planes() {
+        let floor = grid([0.0, 0.0], [1.0, 1.0], 0.05).map(|(x, y)| uncertain_point(x, y, 0.25));
+        let riser = grid([0.0, 0.5], [1.0, 1.0], 0.05).map(|(y, z)| uncertain_point(0.75, y, z));
+        let map = voxel_map(floor.chain(riser));
+
+        let root = octree(&map, 0, 0, 0);
+        assert!(root.plane().is_none());
+        assert_eq!(root.leafs().count(), 6);
+
+        root.leafs().for_each(|leaf| {
+            assert_eq!(leaf.layer(), 1);
+            if leaf.center().z < 0.5 {
+                assert_normal(leaf, Vector3::z());
+            } else {
+                assert!(leaf.center().x > 0.5);
+                assert_normal(leaf, Vector3::x());
+            }
+        });
+    }
+
+    #[test]
+    fn corridor() {
+        let floor = grid([0.0, 0.0], [3.0, 3.0], 0.1).map(|(x, y)| uncertain_point(x, y, 0.1));
+        let left = grid([0.0, 0.0], [3.0, 1.0], 0.1).map(|(x, z)| uncertain_point(x, 0.1, z));
+        let right = grid([0.0, 0.0], [3.0, 1.0], 0.1).map(|(x, z)| uncertain_point(x, 2.9, z));
+        let map = voxel_map(floor.chain(left).chain(right));
+        assert_eq!(map.len(), 9);
+
+        (0..3).for_each(|x| {
+            let middle = octree(&map, x, 1, 0);
+            assert_normal(middle, Vector3::z());
+
+            [0, 2].into_iter().for_each(|y| {
+                let side = octree(&map, x, y, 0);
+                assert!(side.plane().is_none());
+
+                let wall_y = if y == 0 { 0.1 } else { 2.9 };
+                side.leafs().for_each(|leaf| {
+                    let contains_wall = (leaf.center().y - wall_y).abs() < 0.25;
+                    let contains_floor = leaf.center().z < 0.5;
+                    match (contains_wall, contains_floor) {
+                        // the corner is never a plane, even at the max layer
+                        (true, true) => {
+                            assert!(leaf.plane().is_none());
+                            assert!(leaf.leafs().all(|leaf| leaf.layer() == 2));
+                        }
+                        (true, false) => assert_normal(leaf, Vector3::y()),
+                        (false, true) => assert_normal(leaf, Vector3::z()),
+                        (false, false) => unreachable!("empty leafs should not be created"),
+                    }
+                });
+            });
+        });
+    }
+
+    #[test]
+    fn box_corner_reaches_max_layer() {
+        let floor = grid([0.0, 0.0], [1.0, 1.0], 0.05).map(|(x, y)| uncertain_point(x, y, 0.1));
+        let wall_x = grid([0.0, 0.0], [1.0, 1.0], 0.05).map(|(y, z)| uncertain_point(0.1, y, z));
+        let wall_y = grid([0.0, 0.0], [1.0, 1.0], 0.05).map(|(x, z)| uncertain_point(x, 0.1, z));
+        let map = voxel_map(floor.chain(wall_x).chain(wall_y));
+
+        let root = octree(&map, 0, 0, 0);
+        assert!(root.plane().is_none());
+
+        let corner = root
+            .leafs()
+            .find(|leaf| leaf.center().iter().all(|&x| x < 0.5))
+            .expect("the corner leaf should exist");
+        assert!(corner.plane().is_none());
+
+        let max_layer_corner = corner
+            .leafs()
+            .find(|leaf| leaf.center().iter().all(|&x| x < 0.25))
+            .expect("the corner leaf should exist");
+        assert_eq!(max_layer_corner.layer(), CONFIG.max_layer() - 1);
+        assert!(max_layer_corner.plane().is_none());
+        assert_eq!(max_layer_corner.leafs().count(), 0);
+
+        // the far away octant only contains the floor
+        let floor_leaf = root
+            .leafs()
+            .find(|leaf| leaf.center().x > 0.5 && leaf.center().y > 0.5)
+            .expect("the floor leaf should exist");
+        assert_normal(floor_leaf, Vector3::z());
+    }
+
+    #[test]
+    fn registration_refines_extrinsic() {
+        use nalgebra::{IsometryMatrix3, SMatrix};
+
+        use crate::{
+            esikf::{self, StateVector},
+            frame::{Body, BodyPoint},
+            imu, vio,
+        };
+
+        type State = esikf::OdometerUncertainties;
+
+        // a 4m x 4m x 3m room
+        let room = || {
+            let horizontal = |z| grid([0.0, 0.0], [4.0, 4.0], 0.1).map(move |(x, y)| [x, y, z]);
+            let wall_x = |x| grid([0.0, 0.0], [4.0, 3.0], 0.1).map(move |(y, z)| [x, y, z]);
+            let wall_y = |y| grid([0.0, 0.0], [4.0, 3.0], 0.1).map(move |(x, z)| [x, y, z]);
+            horizontal(0.05)
+                .chain(horizontal(2.95))
+                .chain(wall_x(0.05))
+                .chain(wall_x(3.95))
+                .chain(wall_y(0.05))
+                .chain(wall_y(3.95))
+                .map(|[x, y, z]| Vector3::new(x, y, z))
+        };
+        let map = voxel_map(room().map(|point| uncertain_point(point.x, point.y, point.z)));
+
+        let imu_to_world =
+            IsometryMatrix3::new(Vector3::new(2.0, 1.8, 1.4), Vector3::new(0.0, 0.0, 0.3));
+        let body_to_imu = IsometryMatrix3::new(
+            Vector3::new(0.1, 0.05, -0.02),
+            Vector3::new(0.02, -0.01, 0.05),
+        );
+        let body_to_world = imu_to_world * body_to_imu;
+        let scan: Vec<UncertainPoint<Body>> = room()
+            .step_by(7)
+            .map(|point| {
+                let body_point =
+                    BodyPoint::from(body_to_world.inverse_transform_point(&point.into()));
+                UncertainPoint::new_body_point(body_point, &CONFIG)
+            })
+            .collect();
+
+        let guess = IsometryMatrix3::new(
+            Vector3::new(0.03, -0.02, 0.01),
+            Vector3::new(0.0, 0.0, 0.02),
+        ) * body_to_imu;
+        let mut variances = StateVector::from_element(1e-12);
+        variances
+            .fixed_rows_mut::<6>(State::BODY_TO_IMU_ROTATION_INDEX)
+            .fill(1e-2);
+        let mut odometer = UncertainOdometer::new(
+            imu_to_world.into(),
+            imu::State::default(),
+            vio::State::default(),
+            guess.into(),
+            SMatrix::from_diagonal(&variances).into(),
+        );
+
+        odometer.iterated_update(&esikf::Config::default(), |odometer| {
+            map.build_residual(&scan, odometer)
+                .iter()
+                .map(|point_to_plane| point_to_plane.measurement(odometer))
+                .collect::<Vec<_>>()
+        });
+
+        let error = body_to_imu.inverse() * *odometer.body_to_imu;
+        assert!(error.translation.vector.norm() < 2e-3, "{error}");
+        assert!(error.rotation.angle() < 0.1_f64.to_radians(), "{error}");
     }
 }
@@ -9,6 +9,9 @@ use crate::{
 use nalgebra::{Matrix3, Matrix6, RowVector3, SymmetricEigen, Vector3, stack};
 use rust_livo2_macros::uncertainties;
 
+/// The range of a plane, in multiples of its radius, within which points are matched to it.
+const PLANE_RANGE_RADII: f64 = 3.0;
+
 pub struct Plane {
     normal: Vector3<f64>,
     center: WorldPoint<f64>,
@@ -17,6 +20,21 @@ pub struct Plane {
     distance_to_origin: f64,
 }
 
+impl Plane {
+    pub fn normal(&self) -> &Vector3<f64> {
+        &self.normal
+    }
+    pub fn center(&self) -> &WorldPoint<f64> {
+        &self.center
+    }
+    pub fn points_count(&self) -> usize {
+        self.points_count
+    }
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+}
+
 impl Uncertain for Plane {
     type Uncertainty = PlaneUncertainties;
 }
@@ -51,7 +69,7 @@ impl UncertainPlane {
 
         let (min_eigen_index, min_eigen_value) = eigenvalues.argmin();
 
-        if min_eigen_value < planer_threshold {
+        if min_eigen_value > planer_threshold {
             return None;
         }
 
@@ -98,7 +116,7 @@ impl UncertainPlane {
         ))
     }
 
-    pub fn sigma_to(&self, world_point: UncertainPoint<World>) -> f64 {
+    pub fn sigma_to(&self, world_point: &UncertainPoint<World>) -> f64 {
         let distance_error = world_point.coords - self.center.coords;
         let normal_error = -self.borrow().normal;
 
@@ -110,7 +128,27 @@ impl UncertainPlane {
         sigma.to_scalar()
     }
 
-    pub fn distance_to(&self, world_point: WorldPoint<f64>) -> f64 {
+    /// The probability density of the point lying on this plane,
+    /// `None` if the point is out of the plane range or `sigma_num` sigmas.
+    pub fn probability_of(
+        &self,
+        world_point: &UncertainPoint<World>,
+        sigma_num: f64,
+    ) -> Option<f64> {
+        let distance = self.distance_to(world_point);
+        let center_distance_squared = (world_point.coords - self.center.coords).norm_squared();
+        let range_distance = (center_distance_squared - distance.powi(2)).sqrt();
+        if range_distance > PLANE_RANGE_RADII * self.radius {
+            return None;
+        }
+        let sigma = self.sigma_to(world_point);
+        if distance.abs() > sigma_num * sigma.sqrt() {
+            return None;
+        }
+        Some((-distance.powi(2) / sigma / 2.0).exp() / sigma.sqrt())
+    }
+
+    pub fn distance_to(&self, world_point: &WorldPoint<f64>) -> f64 {
         self.normal.dot(&world_point.coords) - self.distance_to_origin
     }
 }
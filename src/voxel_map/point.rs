@@ -85,14 +85,11 @@ impl UncertainPoint<World> {
             )
             + translation_covariance;
 
-        let world_point = body_point
-            .to_imu_point(body_to_imu)
-            .to_world_point(&current_odom.isometry);
+        let world_point = (body_to_imu * &current_odom.isometry).transform_point(&body_point);
 
         Self::new_uncertained(world_point, covariance_matrix)
     }
 
-    #[expect(unused)]
     pub fn from_body_point_without_pose_error(
         body_point: UncertainPoint<Body>,
         current_pose: &UncertainOdometer,
@@ -102,9 +99,7 @@ impl UncertainPoint<World> {
 
         let covariance_matrix = body_point.covariance.forward(body_to_world_rotation);
 
-        let world_point = body_point
-            .to_imu_point(body_to_imu)
-            .to_world_point(&current_pose.isometry);
+        let world_point = (body_to_imu * &current_pose.isometry).transform_point(&body_point);
 
         Self::new_uncertained(world_point, covariance_matrix)
     }
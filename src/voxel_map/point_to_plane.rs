@@ -1,14 +1,109 @@
+use nalgebra::{Vector3, stack};
 use rust_livo2_macros::uncertainties;
 
-use crate::voxel_map::{plane::PlaneUncertainties, point::WorldPointUncertainties};
+use crate::{
+    esikf::{Measurement, MeasurementJacobian, OdometerUncertainties, UncertainOdometer},
+    frame::{Body, BodyPoint, FramedIsometry, Imu, ImuPoint, World},
+    uncertain::UncertainForward,
+    voxel_map::{
+        plane::{PlaneUncertainties, UncertainPlane},
+        point::{UncertainPoint, WorldPointUncertainties},
+    },
+};
 
+/// A point matched to a plane in the voxel map.
 pub struct UncertainPoint2Plane {
+    /// the matched point in body frame
+    pub body_point: BodyPoint<f64>,
+    /// the matched point in imu frame
+    pub imu_point: ImuPoint<f64>,
+    /// the normal of the matched plane
+    pub normal: Vector3<f64>,
+    /// the vector from the plane center to the point in world frame
+    pub center_to_point: Vector3<f64>,
+    /// the signed distance from the point to the plane
+    pub distance: f64,
     pub covariance: Point2PlaneUncertainties,
 }
 
 #[uncertainties]
 #[derive(Debug, Clone)]
 pub struct Point2PlaneUncertainties {
-    plane: PlaneUncertainties,
-    world_point: WorldPointUncertainties,
+    pub plane: PlaneUncertainties,
+    pub world_point: WorldPointUncertainties,
+}
+
+impl UncertainPoint2Plane {
+    /// `world_point` should not contain the odometer uncertainty,
+    /// which is considered in the esikf update.
+    pub fn new(
+        body_point: BodyPoint<f64>,
+        body_to_imu: &FramedIsometry<f64, Body, Imu>,
+        world_point: &UncertainPoint<World>,
+        plane: &UncertainPlane,
+    ) -> Self {
+        type Uncertainties = Point2PlaneUncertainties;
+
+        let mut covariance = Uncertainties::from(Point2PlaneUncertaintiesMatrix::zeros());
+        covariance
+            .fixed_view_mut::<6, 6>(Uncertainties::PLANE_INDEX, Uncertainties::PLANE_INDEX)
+            .copy_from(&*plane.covariance);
+        covariance
+            .fixed_view_mut::<3, 3>(
+                Uncertainties::WORLD_POINT_INDEX,
+                Uncertainties::WORLD_POINT_INDEX,
+            )
+            .copy_from(&world_point.covariance);
+
+        Self {
+            imu_point: body_to_imu.transform_point(&body_point),
+            body_point,
+            normal: *plane.normal(),
+            center_to_point: world_point.coords - plane.center().coords,
+            distance: plane.distance_to(world_point),
+            covariance,
+        }
+    }
+
+    /// The variance of the distance.
+    pub fn variance(&self) -> f64 {
+        #[expect(clippy::toplevel_ref_arg)]
+        let error_matrix = stack![self.center_to_point; -self.normal; self.normal];
+
+        self.covariance.backward(error_matrix).to_scalar()
+    }
+
+    /// The distance measurement of the odometer, the expected distance is zero.
+    pub fn measurement(&self, odometer: &UncertainOdometer) -> Measurement {
+        type State = OdometerUncertainties;
+
+        let rotation_jacobian = -self.normal.transpose()
+            * odometer.rotation().matrix()
+            * self.imu_point.coords.cross_matrix();
+
+        let mut jacobian = MeasurementJacobian::zeros();
+        jacobian
+            .fixed_columns_mut::<3>(State::ROTATION_INDEX)
+            .copy_from(&rotation_jacobian);
+        jacobian
+            .fixed_columns_mut::<3>(State::TRANSLATION_INDEX)
+            .copy_from(&self.normal.transpose());
+
+        let imu_normal = self.normal.transpose() * odometer.rotation().matrix();
+        let extrinsic_rotation_jacobian = -imu_normal
+            * odometer.body_to_imu.rotation.matrix()
+            * self.body_point.coords.cross_matrix();
+        jacobian
+            .fixed_columns_mut::<3>(State::BODY_TO_IMU_ROTATION_INDEX)
+            .copy_from(&extrinsic_rotation_jacobian);
+        jacobian
+            .fixed_columns_mut::<3>(State::BODY_TO_IMU_TRANSLATION_INDEX)
+            .copy_from(&imu_normal);
+
+        Measurement {
+            residual: -self.distance,
+            jacobian,
+            variance: self.variance(),
+        }
+    }
 }